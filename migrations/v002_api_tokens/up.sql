@@ -0,0 +1,16 @@
+CREATE TABLE api_tokens (
+    id UUID DEFAULT gen_random_uuid() NOT NULL,
+    name VARCHAR(48) NOT NULL,
+    token_hash BYTEA NOT NULL,
+    scopes TEXT[] NOT NULL,
+    user_id UUID NOT NULL,
+    revoked BOOL NOT NULL DEFAULT false,
+    last_used_at TIMESTAMP DEFAULT NULL,
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+);
@@ -0,0 +1,15 @@
+CREATE TABLE password_resets (
+    id UUID DEFAULT gen_random_uuid() NOT NULL,
+    token_hash BYTEA NOT NULL,
+    user_id UUID NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP DEFAULT NULL,
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    UNIQUE(token_hash),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+);
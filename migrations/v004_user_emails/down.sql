@@ -0,0 +1,6 @@
+DROP TABLE email_verifications CASCADE;
+
+ALTER TABLE users
+    DROP CONSTRAINT users_email_key,
+    DROP COLUMN email_verified_at,
+    DROP COLUMN email;
@@ -0,0 +1,21 @@
+ALTER TABLE users
+    ADD COLUMN email VARCHAR(254) DEFAULT NULL,
+    ADD COLUMN email_verified_at TIMESTAMP DEFAULT NULL,
+    ADD CONSTRAINT users_email_key UNIQUE (email);
+
+CREATE TABLE email_verifications (
+    id UUID DEFAULT gen_random_uuid() NOT NULL,
+    token_hash BYTEA NOT NULL,
+    email VARCHAR(254) NOT NULL,
+    user_id UUID NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP DEFAULT NULL,
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    UNIQUE(token_hash),
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+);
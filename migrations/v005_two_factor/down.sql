@@ -0,0 +1,7 @@
+DROP TABLE login_challenges CASCADE;
+DROP TABLE recovery_codes CASCADE;
+
+ALTER TABLE users
+    DROP COLUMN totp_last_step,
+    DROP COLUMN totp_enabled,
+    DROP COLUMN totp_secret;
@@ -0,0 +1,32 @@
+ALTER TABLE users
+    ADD COLUMN totp_secret BYTEA DEFAULT NULL,
+    ADD COLUMN totp_enabled BOOL NOT NULL DEFAULT false,
+    ADD COLUMN totp_last_step BIGINT DEFAULT NULL;
+
+CREATE TABLE recovery_codes (
+    id UUID DEFAULT gen_random_uuid() NOT NULL,
+    code_hash BYTEA NOT NULL,
+    user_id UUID NOT NULL,
+    used_at TIMESTAMP DEFAULT NULL,
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+);
+
+CREATE TABLE login_challenges (
+    id UUID DEFAULT gen_random_uuid() NOT NULL,
+    user_id UUID NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP NOT NULL,
+
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    PRIMARY KEY (id),
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+            REFERENCES users(id)
+);
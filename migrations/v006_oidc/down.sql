@@ -0,0 +1,2 @@
+DROP TABLE oidc_logins CASCADE;
+DROP TABLE user_identities CASCADE;
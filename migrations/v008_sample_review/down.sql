@@ -0,0 +1,5 @@
+ALTER TABLE results
+    DROP COLUMN model_version;
+
+ALTER TABLE samples
+    DROP COLUMN review_status;
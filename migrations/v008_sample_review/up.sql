@@ -0,0 +1,6 @@
+ALTER TABLE samples
+    ADD COLUMN review_status VARCHAR(16) NOT NULL DEFAULT 'unreviewed';
+
+-- Unknown for results stored before versions were recorded.
+ALTER TABLE results
+    ADD COLUMN model_version VARCHAR(64);
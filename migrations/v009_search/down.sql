@@ -0,0 +1,6 @@
+DROP INDEX pets_search;
+
+DROP INDEX samples_search;
+
+ALTER TABLE samples
+    DROP COLUMN notes;
@@ -0,0 +1,14 @@
+ALTER TABLE samples
+    ADD COLUMN notes TEXT;
+
+-- Search matches these exact expressions, keep them in sync with
+-- `PgRepository::search`. The simple configuration does not stem, labels,
+-- names and notes are not necessarily English.
+CREATE INDEX samples_search ON samples USING GIN ((
+    setweight(to_tsvector('simple', label), 'A')
+        || setweight(to_tsvector('simple', coalesce(notes, '')), 'B')
+));
+
+CREATE INDEX pets_search ON pets USING GIN ((
+    setweight(to_tsvector('simple', name), 'A')
+));
@@ -0,0 +1,263 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::StreamExt;
+use image::{DynamicImage, Rgb, RgbImage};
+use serde::Serialize;
+
+use crate::config::{ConfigArgs, ServerConfig};
+use crate::detector::{self, Classification, Detector, ResultBox};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// One row per box; images without boxes get a single empty row.
+    Csv,
+    Json,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct ScanArgs {
+    /// Directory of images to scan.
+    input: PathBuf,
+    /// File to write the results to.
+    #[arg(long, short)]
+    output: PathBuf,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+    /// Also save each scanned image with its boxes drawn into this directory.
+    #[arg(long)]
+    annotate: Option<PathBuf>,
+    /// Images decoded and scanned at once.
+    #[arg(long, short, default_value_t = 4)]
+    jobs: usize,
+    /// Include subdirectories.
+    #[arg(long, short)]
+    recursive: bool,
+}
+
+#[derive(Serialize)]
+struct ImageReport {
+    file: String,
+    boxes: Vec<ResultBox>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+const LINE_WIDTH: u32 = 2;
+
+pub(crate) fn run(config: &ConfigArgs, args: ScanArgs) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    find_images(&args.input, args.recursive, &mut files)?;
+    files.sort();
+
+    let detector = detector::from_config(
+        &ServerConfig::detector(config).unwrap_or_else(|errors| errors.exit()),
+    );
+    let total = files.len();
+
+    let mut reports = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(
+            futures::stream::iter(files)
+                .map(|path| {
+                    let file = path
+                        .strip_prefix(&args.input)
+                        .unwrap_or(&path)
+                        .display()
+                        .to_string();
+                    let annotated = args
+                        .annotate
+                        .as_ref()
+                        .map(|dir| dir.join(&file).with_extension("png"));
+
+                    scan_file(detector.clone(), path, file, annotated)
+                })
+                .buffer_unordered(args.jobs.max(1))
+                .enumerate()
+                .map(|(index, report)| {
+                    match &report.error {
+                        Some(error) => {
+                            eprintln!("[{}/{total}] {}: {error}", index + 1, report.file)
+                        }
+                        None => eprintln!(
+                            "[{}/{total}] {}: {} boxes",
+                            index + 1,
+                            report.file,
+                            report.boxes.len()
+                        ),
+                    }
+
+                    report
+                })
+                .collect::<Vec<_>>(),
+        );
+
+    reports.sort_by(|a, b| a.file.cmp(&b.file));
+
+    let mut output = std::io::BufWriter::new(std::fs::File::create(&args.output)?);
+
+    match args.format {
+        OutputFormat::Csv => write_csv(&mut output, &reports)?,
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut output, &reports)?,
+    }
+
+    output.flush()?;
+
+    let failed = reports
+        .iter()
+        .filter(|report| report.error.is_some())
+        .count();
+    eprintln!(
+        "Scanned {} images, {failed} failed, results in {}",
+        total - failed,
+        args.output.display()
+    );
+
+    Ok(())
+}
+
+fn find_images(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if recursive {
+                find_images(&path, recursive, files)?;
+            }
+        } else if image::ImageFormat::from_path(&path).is_ok() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+async fn scan_file(
+    detector: Arc<dyn Detector>,
+    path: PathBuf,
+    file: String,
+    annotated: Option<PathBuf>,
+) -> ImageReport {
+    match scan(detector.as_ref(), path, annotated).await {
+        Ok(boxes) => ImageReport {
+            file,
+            boxes,
+            error: None,
+        },
+        Err(error) => ImageReport {
+            file,
+            boxes: Vec::new(),
+            error: Some(error),
+        },
+    }
+}
+
+/// Same preprocessing and inference as `/scan`.
+async fn scan(
+    detector: &dyn Detector,
+    path: PathBuf,
+    annotated: Option<PathBuf>,
+) -> Result<Vec<ResultBox>, String> {
+    let image = tokio::task::spawn_blocking(move || {
+        image::open(&path)
+            .map(|raw| detector::prepare_scan(&raw))
+            .map_err(|e| format!("Unsupported image: {e}"))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    let boxes = detector.infer(&image).await.map_err(|e| e.to_string())?;
+
+    if let Some(path) = annotated {
+        let drawn = boxes.clone();
+
+        tokio::task::spawn_blocking(move || {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+
+            draw_boxes(&image, &drawn)
+                .save(&path)
+                .map_err(std::io::Error::other)
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Could not save annotated image: {e}"))?;
+    }
+
+    Ok(boxes)
+}
+
+fn draw_boxes(image: &DynamicImage, boxes: &[ResultBox]) -> RgbImage {
+    let mut canvas = image.to_rgb8();
+    let (width, height) = canvas.dimensions();
+
+    for item in boxes {
+        let color = match item.classification {
+            Classification::Normal => Rgb([0, 200, 0]),
+            Classification::Incipient => Rgb([220, 0, 0]),
+        };
+
+        let left = item.x.max(0.0) as u32;
+        let top = item.y.max(0.0) as u32;
+        let right = ((item.x + item.width) as u32).min(width - 1);
+        let bottom = ((item.y + item.height) as u32).min(height - 1);
+
+        for inset in 0..LINE_WIDTH {
+            let (left, top) = (left + inset, top + inset);
+            let (right, bottom) = (right.saturating_sub(inset), bottom.saturating_sub(inset));
+
+            if left > right || top > bottom {
+                break;
+            }
+
+            for x in left..=right {
+                canvas.put_pixel(x, top, color);
+                canvas.put_pixel(x, bottom, color);
+            }
+
+            for y in top..=bottom {
+                canvas.put_pixel(left, y, color);
+                canvas.put_pixel(right, y, color);
+            }
+        }
+    }
+
+    canvas
+}
+
+fn write_csv(output: &mut impl Write, reports: &[ImageReport]) -> std::io::Result<()> {
+    writeln!(
+        output,
+        "file,box,x,y,width,height,probability,classification,error"
+    )?;
+
+    for report in reports {
+        let file = csv_field(&report.file);
+
+        if report.boxes.is_empty() {
+            let error = report.error.as_deref().map(csv_field).unwrap_or_default();
+            writeln!(output, "{file},,,,,,,,{error}")?;
+        }
+
+        for (index, item) in report.boxes.iter().enumerate() {
+            writeln!(
+                output,
+                "{file},{index},{},{},{},{},{},{:?},",
+                item.x, item.y, item.width, item.height, item.probability, item.classification
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
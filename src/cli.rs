@@ -0,0 +1,273 @@
+use std::future::Future;
+use std::io::{BufRead, IsTerminal};
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveTime};
+use clap::{Parser, Subcommand};
+
+use crate::config::{ConfigArgs, ServerConfig};
+use crate::database::{self, Database, DatabaseConfig, MigrateAction, SampleSelection};
+use crate::error::Error;
+use crate::messages::users::RegisterUser;
+use crate::password_hasher::PasswordHasher;
+
+#[derive(Parser)]
+#[command(version, about)]
+pub(crate) struct Cli {
+    #[command(flatten)]
+    pub(crate) config: ConfigArgs,
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Run the HTTP server (the default).
+    Serve,
+    /// Inspect or change the database schema.
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Manage user accounts.
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// Maintain stored samples.
+    Samples {
+        #[command(subcommand)]
+        action: SamplesAction,
+    },
+    /// Print row counts.
+    Stats,
+    /// Run the detector over a directory of images without the server.
+    Scan(crate::batch_scan::ScanArgs),
+}
+
+/// Environment variable holding the password for user commands.
+const PASSWORD_VAR: &str = "USER_PASSWORD";
+
+/// Passwords are read from `USER_PASSWORD` or piped on stdin so they stay
+/// out of the shell history and off the screen.
+#[derive(Subcommand)]
+pub(crate) enum UserAction {
+    /// Create a user.
+    Create {
+        login_name: String,
+        #[arg(long)]
+        first_name: String,
+        #[arg(long)]
+        last_name: String,
+        #[arg(long)]
+        email: Option<String>,
+        #[arg(long)]
+        admin: bool,
+    },
+    /// Grant admin rights, or take them away with --revoke.
+    Admin {
+        login_name: String,
+        #[arg(long)]
+        revoke: bool,
+    },
+    /// Set a new password and sign the user out everywhere.
+    ResetPassword { login_name: String },
+    /// Sign the user out everywhere.
+    RevokeSessions { login_name: String },
+}
+
+#[derive(Subcommand)]
+pub(crate) enum SamplesAction {
+    /// Permanently remove soft-deleted samples and their results.
+    Purge,
+    /// Run inference again and replace the stored results.
+    Reinfer {
+        /// Only these samples; all samples matching the other filters if empty.
+        sample_ids: Vec<uuid::Uuid>,
+        /// Only samples of this login name.
+        #[arg(long)]
+        owner: Option<String>,
+        /// Only samples uploaded on or after this date (YYYY-MM-DD).
+        #[arg(long)]
+        since: Option<NaiveDate>,
+        /// Only samples without results.
+        #[arg(long)]
+        pending: bool,
+    },
+}
+
+pub(crate) fn migrate(args: &ConfigArgs, action: MigrateAction) -> std::io::Result<()> {
+    match ServerConfig::database(args).unwrap_or_else(|errors| errors.exit()) {
+        DatabaseConfig::Postgres { url, .. } => {
+            database::migrate(&url, action).map_err(std::io::Error::other)
+        }
+        DatabaseConfig::Memory => Err(std::io::Error::other(
+            "The memory backend has no migrations",
+        )),
+    }
+}
+
+pub(crate) fn user(args: &ConfigArgs, action: UserAction) -> std::io::Result<()> {
+    let database = database(args);
+    let salt = ServerConfig::salt(args).unwrap_or_else(|errors| errors.exit());
+    let hasher = Arc::new(PasswordHasher::new(salt));
+
+    match action {
+        UserAction::Create {
+            login_name,
+            first_name,
+            last_name,
+            email,
+            admin,
+        } => {
+            let desc = RegisterUser {
+                login_name,
+                first_name,
+                last_name,
+                password: read_password()?,
+                email,
+            };
+
+            block_on(async {
+                let id = database.create_user(hasher, desc, admin).await?;
+                println!("Created user {id}");
+
+                Ok(())
+            })
+        }
+        UserAction::Admin { login_name, revoke } => {
+            block_on(database.set_admin(login_name, !revoke))
+        }
+        UserAction::ResetPassword { login_name } => {
+            let password = read_password()?;
+
+            block_on(database.set_password(hasher, login_name, password))
+        }
+        UserAction::RevokeSessions { login_name } => block_on(database.revoke_sessions(login_name)),
+    }
+}
+
+pub(crate) fn samples(args: &ConfigArgs, action: SamplesAction) -> std::io::Result<()> {
+    let database = database(args);
+
+    match action {
+        SamplesAction::Purge => block_on(async {
+            let purged = database.purge_deleted_samples().await?;
+            println!("Purged {purged} samples");
+
+            Ok(())
+        }),
+        SamplesAction::Reinfer {
+            sample_ids,
+            owner,
+            since,
+            pending,
+        } => {
+            let detector = crate::detector::from_config(
+                &ServerConfig::detector(args).unwrap_or_else(|errors| errors.exit()),
+            );
+
+            block_on(async {
+                let owner_id = match owner {
+                    Some(login_name) => Some(database.find_user(login_name).await?),
+                    None => None,
+                };
+
+                let selected = database
+                    .select_samples(SampleSelection {
+                        sample_ids,
+                        owner_id,
+                        created_since: since.map(|date| date.and_time(NaiveTime::MIN)),
+                        pending_only: pending,
+                    })
+                    .await?;
+
+                let (mut updated, mut empty, mut failed) = (0, 0, 0);
+
+                for sample_id in selected {
+                    match database.reinfer_sample(sample_id, detector.as_ref()).await {
+                        Ok(0) => {
+                            println!("{sample_id}: nothing detected, results kept");
+                            empty += 1;
+                        }
+                        Ok(boxes) => {
+                            println!("{sample_id}: {boxes} boxes");
+                            updated += 1;
+                        }
+                        Err(e) => {
+                            eprintln!("{sample_id}: {}", describe(&e));
+                            failed += 1;
+                        }
+                    }
+                }
+
+                println!("Updated {updated}, nothing detected {empty}, failed {failed}");
+
+                Ok(())
+            })
+        }
+    }
+}
+
+pub(crate) fn stats(args: &ConfigArgs) -> std::io::Result<()> {
+    let database = database(args);
+
+    block_on(async {
+        let stats = database.stats().await?;
+
+        println!("users            {}", stats.users);
+        println!("  admins         {}", stats.admins);
+        println!("sessions         {}", stats.sessions);
+        println!("samples          {}", stats.samples.total);
+        println!("  pending        {}", stats.samples.pending);
+        println!("  deleted        {}", stats.samples.deleted);
+        println!("results          {}", stats.results);
+
+        Ok(())
+    })
+}
+
+fn database(args: &ConfigArgs) -> Database {
+    Database::new(&ServerConfig::database(args).unwrap_or_else(|errors| errors.exit()))
+}
+
+fn block_on(future: impl Future<Output = Result<(), Error>>) -> std::io::Result<()> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(future)
+        .map_err(|e| std::io::Error::other(describe(&e)))
+}
+
+/// The error as shown to API clients, including field details.
+fn describe(error: &Error) -> String {
+    match error {
+        Error::Validation(fields) => format!(
+            "{error} {}",
+            serde_json::to_string(fields).unwrap_or_default()
+        ),
+        _ => error.to_string(),
+    }
+}
+
+/// Takes the password from `USER_PASSWORD`, or else one line of stdin. A
+/// terminal is refused since typing into it would echo the password.
+fn read_password() -> std::io::Result<String> {
+    if let Ok(password) = std::env::var(PASSWORD_VAR) {
+        return Ok(password);
+    }
+
+    let stdin = std::io::stdin();
+
+    if stdin.is_terminal() {
+        return Err(std::io::Error::other(format!(
+            "Refusing to read a password from the terminal, it would be shown; \
+             pipe it on stdin or set {PASSWORD_VAR}"
+        )));
+    }
+
+    let mut password = String::new();
+    stdin.lock().read_line(&mut password)?;
+
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use dotenvy::dotenv;
+
+const DEFAULT_FILE: &str = "pupsight.toml";
+
+/// Settings are read from a TOML file, then environment variables (and
+/// `.env`), then these flags, each overriding the one before.
+#[derive(Clone, Default, clap::Args)]
+pub(crate) struct ConfigArgs {
+    /// TOML config file. Defaults to $PUPSIGHT_CONFIG, then ./pupsight.toml if present.
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on, same as `--set server.bind=...`.
+    #[arg(long, global = true)]
+    bind: Option<String>,
+    /// Port to listen on, same as `--set server.port=...`.
+    #[arg(long, global = true)]
+    port: Option<String>,
+    /// Override any setting by its config file key, e.g. `limits.scan=5/60`.
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+}
+
+/// Every problem found while loading, so they can all be fixed at once.
+#[derive(Debug)]
+pub struct ConfigErrors(Vec<String>);
+
+impl std::fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+
+        for error in &self.0 {
+            write!(f, "\n  - {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ConfigErrors {
+    pub(crate) fn exit(self) -> ! {
+        eprintln!("{self}");
+        std::process::exit(78)
+    }
+}
+
+enum Origin {
+    File,
+    Env(&'static str),
+    Cli,
+}
+
+/// Resolves settings across the layers, collecting errors instead of
+/// stopping at the first one.
+pub(crate) struct Loader {
+    file: BTreeMap<String, String>,
+    file_path: Option<PathBuf>,
+    cli: BTreeMap<String, String>,
+    used: HashSet<&'static str>,
+    errors: Vec<String>,
+}
+
+impl Loader {
+    pub(crate) fn new(args: &ConfigArgs) -> Self {
+        dotenv().ok();
+
+        let mut loader = Self {
+            file: BTreeMap::new(),
+            file_path: None,
+            cli: BTreeMap::new(),
+            used: HashSet::new(),
+            errors: Vec::new(),
+        };
+
+        match args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("PUPSIGHT_CONFIG").map(PathBuf::from))
+        {
+            Some(path) => loader.read_file(path),
+            None if Path::new(DEFAULT_FILE).exists() => loader.read_file(DEFAULT_FILE.into()),
+            None => {}
+        }
+
+        for item in &args.overrides {
+            match item.split_once('=') {
+                Some((key, value)) => {
+                    loader.cli.insert(key.trim().to_string(), value.to_string());
+                }
+                None => loader
+                    .errors
+                    .push(format!("--set {item:?}: expected KEY=VALUE")),
+            }
+        }
+
+        if let Some(bind) = &args.bind {
+            loader.cli.insert("server.bind".to_string(), bind.clone());
+        }
+
+        if let Some(port) = &args.port {
+            loader.cli.insert("server.port".to_string(), port.clone());
+        }
+
+        loader
+    }
+
+    fn read_file(&mut self, path: PathBuf) {
+        let table = match std::fs::read_to_string(&path) {
+            Ok(text) => text.parse::<toml::Table>().map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match table {
+            Ok(table) => Self::flatten("", &table, &mut self.file),
+            Err(e) => self.errors.push(format!("{}: {e}", path.display())),
+        }
+
+        self.file_path = Some(path);
+    }
+
+    /// `[a] b = 1` becomes `a.b = "1"`; arrays are joined with commas.
+    fn flatten(prefix: &str, table: &toml::Table, out: &mut BTreeMap<String, String>) {
+        for (key, value) in table {
+            let key = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{prefix}.{key}")
+            };
+
+            match value {
+                toml::Value::Table(table) => Self::flatten(&key, table, out),
+                value => {
+                    out.insert(key, Self::scalar(value));
+                }
+            }
+        }
+    }
+
+    fn scalar(value: &toml::Value) -> String {
+        match value {
+            toml::Value::String(value) => value.clone(),
+            toml::Value::Array(items) => {
+                items.iter().map(Self::scalar).collect::<Vec<_>>().join(",")
+            }
+            value => value.to_string(),
+        }
+    }
+
+    fn origin(&self, key: &str, origin: &Origin) -> String {
+        match origin {
+            Origin::File => match &self.file_path {
+                Some(path) => format!("{key} in {}", path.display()),
+                None => key.to_string(),
+            },
+            Origin::Env(env) => format!("{key} (from {env})"),
+            Origin::Cli => format!("{key} (from --set)"),
+        }
+    }
+
+    fn value(&mut self, key: &'static str, env: &'static str) -> Option<(String, Origin)> {
+        self.used.insert(key);
+
+        if let Some(value) = self.cli.get(key) {
+            return Some((value.clone(), Origin::Cli));
+        }
+
+        if let Ok(value) = std::env::var(env) {
+            return Some((value, Origin::Env(env)));
+        }
+
+        self.file
+            .get(key)
+            .map(|value| (value.clone(), Origin::File))
+    }
+
+    pub(crate) fn parse<T>(&mut self, key: &'static str, env: &'static str) -> Option<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let (raw, origin) = self.value(key, env)?;
+
+        match raw.trim().parse::<T>() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let key = self.origin(key, &origin);
+                self.errors.push(format!("{key}: {e}"));
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn or<T>(&mut self, key: &'static str, env: &'static str, default: T) -> T
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.parse(key, env).unwrap_or(default)
+    }
+
+    pub(crate) fn required<T>(&mut self, key: &'static str, env: &'static str) -> Option<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        if self.value(key, env).is_none() {
+            self.errors.push(format!(
+                "{key}: required, set it in the config file or {env}"
+            ));
+            return None;
+        }
+
+        self.parse(key, env)
+    }
+
+    /// Comma or whitespace separated values.
+    pub(crate) fn list(&mut self, key: &'static str, env: &'static str) -> Option<Vec<String>> {
+        self.value(key, env).map(|(raw, _)| {
+            raw.split([',', ' ', '\t', '\n'])
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+    }
+
+    /// A string setting limited to `choices`, returned lowercased.
+    pub(crate) fn choice(
+        &mut self,
+        key: &'static str,
+        env: &'static str,
+        choices: &[&str],
+        default: &str,
+    ) -> String {
+        let Some((raw, origin)) = self.value(key, env) else {
+            return default.to_string();
+        };
+
+        let value = raw.trim().to_lowercase();
+
+        if choices.contains(&value.as_str()) {
+            return value;
+        }
+
+        let key = self.origin(key, &origin);
+        self.errors.push(format!(
+            "{key}: expected one of {}, got {raw:?}",
+            choices.join(", ")
+        ));
+
+        default.to_string()
+    }
+
+    #[inline]
+    pub(crate) fn seconds(
+        &mut self,
+        key: &'static str,
+        env: &'static str,
+        default: u64,
+    ) -> std::time::Duration {
+        std::time::Duration::from_secs(self.or(key, env, default))
+    }
+
+    /// Like `seconds`, but `0` disables the timeout.
+    #[inline]
+    pub(crate) fn optional_seconds(
+        &mut self,
+        key: &'static str,
+        env: &'static str,
+        default: u64,
+    ) -> Option<std::time::Duration> {
+        Some(self.seconds(key, env, default)).filter(|duration| !duration.is_zero())
+    }
+
+    #[inline]
+    pub(crate) fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(message.into());
+    }
+
+    /// Reads the settings of a branch not taken, only so `reject_unknown`
+    /// accepts their keys. A shared config file may set them for another
+    /// deployment; their values and errors are dropped.
+    pub(crate) fn skip<T>(&mut self, read: impl FnOnce(&mut Self) -> T) {
+        let errors = self.errors.len();
+        read(self);
+        self.errors.truncate(errors);
+    }
+
+    /// Flags keys set in the file or with `--set` that no setting reads,
+    /// which are almost always typos.
+    pub(crate) fn reject_unknown(&mut self) {
+        let unknown: BTreeSet<String> = self
+            .file
+            .keys()
+            .chain(self.cli.keys())
+            .filter(|key| !self.used.contains(key.as_str()))
+            .map(|key| format!("{key}: unknown setting"))
+            .collect();
+
+        self.errors.extend(unknown);
+    }
+
+    pub(crate) fn finish<T>(mut self, value: T) -> Result<T, ConfigErrors> {
+        if self.errors.is_empty() {
+            return Ok(value);
+        }
+
+        // Settings shared by several sections are read, and fail, more than once.
+        let mut seen = HashSet::new();
+        self.errors.retain(|error| seen.insert(error.clone()));
+
+        Err(ConfigErrors(self.errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{ConfigArgs, Loader};
+
+    /// A config file holding `toml`, named after the test writing it.
+    fn file(test: &str, toml: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pupsight-{test}-{}.toml", std::process::id()));
+        std::fs::write(&path, toml).unwrap();
+
+        path
+    }
+
+    fn loader(config: Option<PathBuf>, overrides: &[&str]) -> Loader {
+        Loader::new(&ConfigArgs {
+            config,
+            overrides: overrides.iter().map(|item| item.to_string()).collect(),
+            ..ConfigArgs::default()
+        })
+    }
+
+    /// Every bad setting is reported in one go, each once.
+    #[test]
+    fn finish_collects_every_error() {
+        let path = file("errors", "[server]\nport = \"eighty\"\n");
+        let mut loader = loader(Some(path.clone()), &["limits.scan"]);
+
+        loader.parse::<u16>("server.port", "PUPSIGHT_TEST_ERRORS_PORT");
+        loader.parse::<u16>("server.port", "PUPSIGHT_TEST_ERRORS_PORT");
+        loader.required::<String>("database.url", "PUPSIGHT_TEST_ERRORS_URL");
+
+        let errors = loader.finish(()).unwrap_err().0;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            errors,
+            [
+                "--set \"limits.scan\": expected KEY=VALUE".to_string(),
+                format!(
+                    "server.port in {}: invalid digit found in string",
+                    path.display()
+                ),
+                "database.url: required, set it in the config file or PUPSIGHT_TEST_ERRORS_URL"
+                    .to_string(),
+            ]
+        );
+    }
+
+    /// `--set` wins over the environment, which wins over the file.
+    #[test]
+    fn layers_override_in_order() {
+        let path = file(
+            "layers",
+            "[server]\nbind = \"file\"\nport = 1\nworkers = 1\n",
+        );
+        std::env::set_var("PUPSIGHT_TEST_LAYERS_PORT", "2");
+        std::env::set_var("PUPSIGHT_TEST_LAYERS_WORKERS", "2");
+        let mut loader = loader(Some(path.clone()), &["server.workers=3"]);
+
+        let bind: Option<String> = loader.parse("server.bind", "PUPSIGHT_TEST_LAYERS_BIND");
+        let port: Option<u16> = loader.parse("server.port", "PUPSIGHT_TEST_LAYERS_PORT");
+        let workers: Option<u16> = loader.parse("server.workers", "PUPSIGHT_TEST_LAYERS_WORKERS");
+        let missing = loader.or("server.backlog", "PUPSIGHT_TEST_LAYERS_BACKLOG", 4);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(bind.as_deref(), Some("file"));
+        assert_eq!(port, Some(2));
+        assert_eq!(workers, Some(3));
+        assert_eq!(missing, 4);
+        assert!(loader.finish(()).is_ok());
+    }
+
+    /// Keys in the file or `--set` that nothing reads are typos, unless a
+    /// branch not taken reads them.
+    #[test]
+    fn reject_unknown_flags_typos() {
+        let path = file(
+            "unknown",
+            "[server]\nprot = 8080\n\n[tls]\ncert = \"cert.pem\"\n",
+        );
+        let mut loader = loader(Some(path.clone()), &["limits.sacn=5/60"]);
+
+        loader.parse::<u16>("server.port", "PUPSIGHT_TEST_UNKNOWN_PORT");
+        loader.skip(|loader| loader.parse::<u16>("tls.cert", "PUPSIGHT_TEST_UNKNOWN_CERT"));
+        loader.reject_unknown();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loader.finish(()).unwrap_err().0,
+            [
+                "limits.sacn: unknown setting",
+                "server.prot: unknown setting"
+            ]
+        );
+    }
+}
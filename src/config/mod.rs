@@ -0,0 +1,462 @@
+mod layers;
+
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use crate::database::{DatabaseConfig, MigrationMode, PoolConfig};
+use crate::detector::{DetectorConfig, OnnxConfig};
+use crate::logging::{LogConfig, LogFormat, LogLevel};
+use crate::mailer::MailerConfig;
+use crate::oidc::OidcConfig;
+use crate::rate_limit::{Rate, RateLimitConfig};
+use crate::shutdown::ShutdownConfig;
+use crate::tls::TlsConfig;
+pub(crate) use layers::ConfigArgs;
+use layers::{ConfigErrors, Loader};
+
+/// Argon2 rejects shorter salts.
+const SALT_MIN: usize = 8;
+
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    pub legacy_paths: bool,
+    pub tls: Option<TlsConfig>,
+    pub log: LogConfig,
+    pub shutdown: ShutdownConfig,
+    pub database: DatabaseConfig,
+    pub detector: DetectorConfig,
+    pub salt: Box<[u8]>,
+    pub json_body_limit: usize,
+    pub cookie: CookieConfig,
+    pub csrf_trusted_origins: Vec<String>,
+    pub mailer: MailerConfig,
+    pub mail_from: String,
+    pub password_reset_url: Option<String>,
+    pub email_verification_url: Option<String>,
+    pub rate_limit: RateLimitConfig,
+    pub oidc: Option<OidcConfig>,
+}
+
+#[derive(Clone)]
+pub struct CookieConfig {
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub max_age: Duration,
+    pub domain: Option<String>,
+}
+
+impl ServerConfig {
+    pub fn load(args: &ConfigArgs) -> Result<Self, ConfigErrors> {
+        let mut loader = Loader::new(args);
+
+        let config = Self {
+            bind: loader.or(
+                "server.bind",
+                "BIND_ADDRESS",
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            ),
+            port: loader.or("server.port", "WEB_PORT", 8083),
+            legacy_paths: loader.or("server.legacy_paths", "LEGACY_API_PATHS", true),
+            tls: Self::tls(&mut loader),
+            log: Self::log_settings(&mut loader),
+            shutdown: ShutdownConfig {
+                drain: loader.or("shutdown.drain", "SHUTDOWN_DRAIN", true),
+                timeout: loader.seconds("shutdown.timeout", "SHUTDOWN_TIMEOUT", 30),
+            },
+            database: Self::database_settings(&mut loader),
+            detector: Self::detector_settings(&mut loader),
+            salt: Self::salt_settings(&mut loader),
+            json_body_limit: loader.or("limits.json_body_bytes", "JSON_BODY_LIMIT", 2 << 20),
+            cookie: Self::cookie(&mut loader),
+            csrf_trusted_origins: {
+                loader
+                    .list("csrf.trusted_origins", "CSRF_TRUSTED_ORIGINS")
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|origin| origin.trim_end_matches('/').to_string())
+                    .collect()
+            },
+            mailer: {
+                let transport = loader.choice("mail.transport", "MAILER", &["log", "file"], "log");
+                let dir = |loader: &mut Loader| {
+                    loader
+                        .required::<PathBuf>("mail.dir", "MAILER_DIR")
+                        .unwrap_or_default()
+                };
+
+                match transport.as_str() {
+                    "file" => MailerConfig::File(dir(&mut loader)),
+                    _ => {
+                        loader.skip(dir);
+                        MailerConfig::Log
+                    }
+                }
+            },
+            mail_from: loader.or(
+                "mail.from",
+                "MAIL_FROM",
+                "no-reply@pupsight.local".to_string(),
+            ),
+            password_reset_url: loader.parse("mail.password_reset_url", "PASSWORD_RESET_URL"),
+            email_verification_url: loader
+                .parse("mail.email_verification_url", "EMAIL_VERIFICATION_URL"),
+            rate_limit: Self::rate_limit(&mut loader),
+            oidc: Self::oidc(&mut loader),
+        };
+
+        if config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.redirect_port == Some(config.port))
+        {
+            loader.error("tls.redirect_port: must differ from server.port");
+        }
+
+        loader.reject_unknown();
+        loader.finish(config)
+    }
+
+    /// Only the database settings, for commands that need nothing else.
+    pub fn database(args: &ConfigArgs) -> Result<DatabaseConfig, ConfigErrors> {
+        let mut loader = Loader::new(args);
+        let config = Self::database_settings(&mut loader);
+
+        loader.finish(config)
+    }
+
+    pub fn log(args: &ConfigArgs) -> Result<LogConfig, ConfigErrors> {
+        let mut loader = Loader::new(args);
+        let config = Self::log_settings(&mut loader);
+
+        loader.finish(config)
+    }
+
+    pub fn detector(args: &ConfigArgs) -> Result<DetectorConfig, ConfigErrors> {
+        let mut loader = Loader::new(args);
+        let config = Self::detector_settings(&mut loader);
+
+        loader.finish(config)
+    }
+
+    pub fn salt(args: &ConfigArgs) -> Result<Box<[u8]>, ConfigErrors> {
+        let mut loader = Loader::new(args);
+        let salt = Self::salt_settings(&mut loader);
+
+        loader.finish(salt)
+    }
+
+    fn log_settings(loader: &mut Loader) -> LogConfig {
+        LogConfig {
+            level: loader.or("log.level", "LOG_LEVEL", LogLevel::Info),
+            format: match loader
+                .choice("log.format", "LOG_FORMAT", &["pretty", "json"], "pretty")
+                .as_str()
+            {
+                "json" => LogFormat::Json,
+                _ => LogFormat::Pretty,
+            },
+            ort_level: loader.or("log.ort_level", "ORT_LOG_LEVEL", LogLevel::Warn),
+        }
+    }
+
+    fn database_settings(loader: &mut Loader) -> DatabaseConfig {
+        match loader
+            .choice(
+                "database.backend",
+                "DATABASE_BACKEND",
+                &["postgres", "memory"],
+                "postgres",
+            )
+            .as_str()
+        {
+            "memory" => {
+                loader.skip(Self::postgres);
+                DatabaseConfig::Memory
+            }
+            _ => Self::postgres(loader),
+        }
+    }
+
+    fn postgres(loader: &mut Loader) -> DatabaseConfig {
+        DatabaseConfig::Postgres {
+            url: loader
+                .required("database.url", "CLIENT_DB_URL")
+                .unwrap_or_default(),
+            pool: Self::pool(loader),
+            migrations: loader.or("database.migrations", "DB_MIGRATIONS", MigrationMode::Run),
+        }
+    }
+
+    fn pool(loader: &mut Loader) -> PoolConfig {
+        let config = PoolConfig {
+            max_size: loader.or("database.pool.max_size", "DB_POOL_MAX_SIZE", 10),
+            min_idle: loader.parse("database.pool.min_idle", "DB_POOL_MIN_IDLE"),
+            connection_timeout: loader.seconds(
+                "database.pool.connection_timeout",
+                "DB_POOL_CONNECTION_TIMEOUT",
+                30,
+            ),
+            idle_timeout: loader.optional_seconds(
+                "database.pool.idle_timeout",
+                "DB_POOL_IDLE_TIMEOUT",
+                600,
+            ),
+            max_lifetime: loader.optional_seconds(
+                "database.pool.max_lifetime",
+                "DB_POOL_MAX_LIFETIME",
+                1800,
+            ),
+            test_on_check_out: loader.or(
+                "database.pool.test_on_checkout",
+                "DB_POOL_TEST_ON_CHECKOUT",
+                true,
+            ),
+        };
+
+        if config.max_size == 0 {
+            loader.error("database.pool.max_size: must be at least 1");
+        }
+
+        if config
+            .min_idle
+            .is_some_and(|min_idle| min_idle > config.max_size)
+        {
+            loader.error("database.pool.min_idle: must not exceed database.pool.max_size");
+        }
+
+        if config.connection_timeout.is_zero() {
+            loader.error("database.pool.connection_timeout: must be at least 1 second");
+        }
+
+        config
+    }
+
+    fn detector_settings(loader: &mut Loader) -> DetectorConfig {
+        match loader
+            .choice(
+                "detector.backend",
+                "DETECTOR_BACKEND",
+                &["onnx", "scripted"],
+                "onnx",
+            )
+            .as_str()
+        {
+            "scripted" => {
+                loader.skip(Self::onnx);
+                Self::scripted(loader)
+            }
+            _ => {
+                loader.skip(Self::scripted);
+                Self::onnx(loader)
+            }
+        }
+    }
+
+    fn scripted(loader: &mut Loader) -> DetectorConfig {
+        DetectorConfig::Scripted(
+            loader
+                .required("detector.script", "DETECTOR_SCRIPT")
+                .unwrap_or_default(),
+        )
+    }
+
+    fn onnx(loader: &mut Loader) -> DetectorConfig {
+        let config = OnnxConfig {
+            model: loader.parse("detector.model", "MODEL_PATH"),
+            confidence_threshold: loader.or(
+                "detector.confidence_threshold",
+                "DETECTOR_CONFIDENCE_THRESHOLD",
+                0.3,
+            ),
+            iou_threshold: loader.or("detector.iou_threshold", "DETECTOR_IOU_THRESHOLD", 0.75),
+            log_level: loader.or("log.ort_level", "ORT_LOG_LEVEL", LogLevel::Warn),
+        };
+
+        if let Some(model) = config.model.as_ref().filter(|model| !model.is_file()) {
+            loader.error(format!("detector.model: {} not found", model.display()));
+        }
+
+        if !(0.0..=1.0).contains(&config.confidence_threshold) {
+            loader.error("detector.confidence_threshold: must be between 0 and 1");
+        }
+
+        if !(0.0..=1.0).contains(&config.iou_threshold) {
+            loader.error("detector.iou_threshold: must be between 0 and 1");
+        }
+
+        DetectorConfig::Onnx(config)
+    }
+
+    fn salt_settings(loader: &mut Loader) -> Box<[u8]> {
+        let salt = loader
+            .required::<String>("auth.salt", "ARGON_SALT")
+            .unwrap_or_default()
+            .into_bytes()
+            .into_boxed_slice();
+
+        if !salt.is_empty() && salt.len() < SALT_MIN {
+            loader.error(format!("auth.salt: must be at least {SALT_MIN} bytes"));
+        }
+
+        salt
+    }
+
+    fn tls(loader: &mut Loader) -> Option<TlsConfig> {
+        let cert = loader.parse::<PathBuf>("tls.cert", "TLS_CERT");
+        let key = loader.parse::<PathBuf>("tls.key", "TLS_KEY");
+
+        let reload_interval = |loader: &mut Loader| {
+            loader.optional_seconds("tls.reload_interval", "TLS_RELOAD_INTERVAL", 60)
+        };
+        let redirect_port =
+            |loader: &mut Loader| loader.parse("tls.redirect_port", "TLS_REDIRECT_PORT");
+
+        match (cert, key) {
+            (Some(cert), Some(key)) => {
+                for (name, path) in [("tls.cert", &cert), ("tls.key", &key)] {
+                    if !path.is_file() {
+                        loader.error(format!("{name}: {} not found", path.display()));
+                    }
+                }
+
+                Some(TlsConfig {
+                    cert,
+                    key,
+                    reload_interval: reload_interval(loader),
+                    redirect_port: redirect_port(loader),
+                })
+            }
+            (cert, key) => {
+                if cert.is_some() != key.is_some() {
+                    loader.error("tls.cert and tls.key must be set together");
+                }
+
+                loader.skip(reload_interval);
+                loader.skip(redirect_port);
+                None
+            }
+        }
+    }
+
+    fn cookie(loader: &mut Loader) -> CookieConfig {
+        let config = CookieConfig {
+            secure: loader.or("cookie.secure", "COOKIE_SECURE", true),
+            same_site: {
+                match loader
+                    .choice(
+                        "cookie.same_site",
+                        "COOKIE_SAME_SITE",
+                        &["strict", "lax", "none"],
+                        "lax",
+                    )
+                    .as_str()
+                {
+                    "strict" => SameSite::Strict,
+                    "none" => SameSite::None,
+                    _ => SameSite::Lax,
+                }
+            },
+            max_age: Duration::seconds(loader.or("cookie.max_age", "COOKIE_MAX_AGE", 604_800)),
+            domain: loader.parse("cookie.domain", "COOKIE_DOMAIN"),
+        };
+
+        if config.same_site == SameSite::None && !config.secure {
+            loader.error(
+                "cookie.same_site: none requires cookie.secure, browsers drop the cookie otherwise",
+            );
+        }
+
+        config
+    }
+
+    fn rate_limit(loader: &mut Loader) -> RateLimitConfig {
+        let default = |value: &str| value.parse::<Rate>().expect("valid default rate");
+
+        let config = RateLimitConfig {
+            auth: loader.or("limits.auth", "RATE_LIMIT_AUTH", default("10/60")),
+            scan: loader.or("limits.scan", "RATE_LIMIT_SCAN", default("20/60")),
+            default: loader.or("limits.default", "RATE_LIMIT_DEFAULT", default("300/60")),
+            account: loader.or("limits.account", "RATE_LIMIT_ACCOUNT", default("10/300")),
+            lockout_threshold: loader.or("limits.lockout_threshold", "LOGIN_LOCKOUT_THRESHOLD", 5),
+            lockout_base: loader.seconds("limits.lockout_base", "LOGIN_LOCKOUT_BASE", 30),
+            lockout_max: loader.seconds("limits.lockout_max", "LOGIN_LOCKOUT_MAX", 3600),
+            trust_proxy_headers: loader.or(
+                "limits.trust_proxy_headers",
+                "TRUST_PROXY_HEADERS",
+                false,
+            ),
+        };
+
+        if config.lockout_threshold == 0 {
+            loader.error("limits.lockout_threshold: must be at least 1");
+        }
+
+        if config.lockout_base > config.lockout_max {
+            loader.error("limits.lockout_base: must not exceed limits.lockout_max");
+        }
+
+        config
+    }
+
+    fn oidc(loader: &mut Loader) -> Option<OidcConfig> {
+        let issuer_url = loader.parse::<String>("oidc.issuer_url", "OIDC_ISSUER_URL");
+
+        // Read even when disabled so they are not reported as unknown.
+        let client_id = loader.parse::<String>("oidc.client_id", "OIDC_CLIENT_ID");
+        let client_secret = loader.parse("oidc.client_secret", "OIDC_CLIENT_SECRET");
+        let redirect_url = loader.parse::<String>("oidc.redirect_url", "OIDC_REDIRECT_URL");
+        let scopes = loader
+            .list("oidc.scopes", "OIDC_SCOPES")
+            .unwrap_or_else(|| vec!["profile".to_string(), "email".to_string()]);
+        let post_login_redirect = loader.or(
+            "oidc.post_login_redirect",
+            "OIDC_POST_LOGIN_REDIRECT",
+            "/".to_string(),
+        );
+
+        let issuer_url = issuer_url?;
+
+        if client_id.is_none() {
+            loader.error("oidc.client_id: required when oidc.issuer_url is set");
+        }
+
+        if redirect_url.is_none() {
+            loader.error("oidc.redirect_url: required when oidc.issuer_url is set");
+        }
+
+        Some(OidcConfig {
+            issuer_url,
+            client_id: client_id.unwrap_or_default(),
+            client_secret,
+            redirect_url: redirect_url.unwrap_or_default(),
+            scopes: scopes
+                .into_iter()
+                .filter(|scope| scope != "openid")
+                .collect(),
+            post_login_redirect,
+        })
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+}
+
+impl CookieConfig {
+    pub fn build(&self, name: &'static str, value: String, http_only: bool) -> Cookie<'static> {
+        let mut builder = Cookie::build(name, value)
+            .path("/")
+            .http_only(http_only)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .max_age(self.max_age);
+
+        if let Some(domain) = &self.domain {
+            builder = builder.domain(domain.clone());
+        }
+
+        builder.finish()
+    }
+}
@@ -0,0 +1,1346 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+
+use super::pets::PetInsert;
+use super::repository::{
+    HealthRepository, OidcLoginInsert, PageRequest, PetRepository, PoolState, ResultRepository,
+    SampleCounts, SampleFilter, SampleRepository, SampleSelection, SearchRepository,
+    SessionRepository, SessionUser, SortKey, TotpState, UserRepository,
+};
+use super::samples::{ResultInsert, ReviewStatus, SampleInsert};
+use super::users::{self, ApiTokenInsert, OidcUserInsert, Scope, UserInsert, UserUpdate};
+use crate::detector::Classification;
+use crate::error::Error;
+use crate::messages::pets::PetEntry;
+use crate::messages::samples::{
+    InferredListEntry, InferredResultListEntry, PendingListEntry, SampleSort, SortOrder,
+};
+use crate::messages::search::{SearchHit, SearchKind};
+use crate::messages::users::{ApiTokenEntry, Profile};
+use crate::password_hasher::PasswordHash;
+
+/// Keeps everything in process memory; for tests and trying the server out
+/// without Postgres. Nothing survives a restart.
+#[derive(Default)]
+pub(crate) struct MemoryRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    users: Vec<UserRow>,
+    sessions: HashMap<uuid::Uuid, SessionRow>,
+    login_challenges: HashMap<uuid::Uuid, LoginChallengeRow>,
+    api_tokens: Vec<ApiTokenRow>,
+    email_verifications: Vec<TokenRow>,
+    password_resets: Vec<TokenRow>,
+    recovery_codes: Vec<RecoveryCodeRow>,
+    identities: HashMap<(String, String), uuid::Uuid>,
+    oidc_logins: HashMap<String, OidcLoginInsert>,
+    samples: Vec<SampleRow>,
+    results: Vec<ResultRow>,
+    pets: HashMap<uuid::Uuid, PetRow>,
+}
+
+struct UserRow {
+    id: uuid::Uuid,
+    login_name: String,
+    first_name: String,
+    last_name: String,
+    argon2: PasswordHash,
+    email: Option<String>,
+    email_verified_at: Option<NaiveDateTime>,
+    totp_secret: Option<Vec<u8>>,
+    totp_enabled: bool,
+    totp_last_step: Option<i64>,
+    is_admin: bool,
+}
+
+struct SessionRow {
+    access_token: Vec<u8>,
+    user_id: uuid::Uuid,
+}
+
+struct LoginChallengeRow {
+    user_id: uuid::Uuid,
+    attempts: i32,
+    expires_at: NaiveDateTime,
+}
+
+struct ApiTokenRow {
+    id: uuid::Uuid,
+    name: String,
+    token_hash: Vec<u8>,
+    scopes: Vec<Option<String>>,
+    user_id: uuid::Uuid,
+    revoked: bool,
+    last_used_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+/// Email verification or password reset token.
+struct TokenRow {
+    token_hash: Vec<u8>,
+    user_id: uuid::Uuid,
+    email: Option<String>,
+    expires_at: NaiveDateTime,
+    used: bool,
+}
+
+struct RecoveryCodeRow {
+    code_hash: Vec<u8>,
+    user_id: uuid::Uuid,
+    used: bool,
+}
+
+struct SampleRow {
+    id: uuid::Uuid,
+    label: String,
+    bytes: Vec<u8>,
+    owner_id: uuid::Uuid,
+    pet_id: Option<uuid::Uuid>,
+    deleted: bool,
+    review_status: ReviewStatus,
+    notes: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+struct PetRow {
+    name: String,
+    birthday: Option<NaiveDateTime>,
+    exact_birthday: bool,
+    owner_id: uuid::Uuid,
+    created_at: NaiveDateTime,
+}
+
+struct ResultRow {
+    id: uuid::Uuid,
+    sample_id: uuid::Uuid,
+    certainty: f32,
+    is_normal: bool,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    model_version: Option<String>,
+    created_at: NaiveDateTime,
+}
+
+impl ResultRow {
+    fn new(result: ResultInsert, created_at: NaiveDateTime) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4(),
+            sample_id: result.sample_id,
+            certainty: result.certainty,
+            is_normal: result.is_normal,
+            x: result.x,
+            y: result.y,
+            width: result.width,
+            height: result.height,
+            model_version: Some(result.model_version),
+            created_at,
+        }
+    }
+}
+
+#[inline]
+fn now() -> NaiveDateTime {
+    chrono::Utc::now().naive_utc()
+}
+
+/// Case insensitive substring match, like `ILIKE '%keyword%'`.
+#[inline]
+fn matches_keyword(label: &str, keyword: Option<&str>) -> bool {
+    keyword.is_none_or(|keyword| label.to_lowercase().contains(&keyword.to_lowercase()))
+}
+
+/// Lowercase words, roughly what the `simple` text search configuration of
+/// Postgres makes of a text.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+impl MemoryRepository {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl State {
+    fn user(&self, user_id: uuid::Uuid) -> Option<&UserRow> {
+        self.users.iter().find(|user| user.id == user_id)
+    }
+
+    fn user_mut(&mut self, user_id: uuid::Uuid) -> Option<&mut UserRow> {
+        self.users.iter_mut().find(|user| user.id == user_id)
+    }
+
+    fn session_user(&self, user_id: uuid::Uuid) -> Option<SessionUser> {
+        self.user(user_id).map(|user| SessionUser {
+            user_id: user.id,
+            login_name: user.login_name.clone(),
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+        })
+    }
+
+    fn email_taken(&self, email: &str, except: Option<uuid::Uuid>) -> bool {
+        self.users
+            .iter()
+            .any(|user| user.email.as_deref() == Some(email) && Some(user.id) != except)
+    }
+
+    /// Same uniqueness rules as the Postgres schema.
+    fn insert_user(&mut self, row: UserRow) -> Result<uuid::Uuid, Error> {
+        if self
+            .users
+            .iter()
+            .any(|user| user.login_name == row.login_name)
+        {
+            return Err(users::login_name_taken());
+        }
+
+        if let Some(email) = &row.email {
+            if self.email_taken(email, None) {
+                return Err(users::email_taken());
+            }
+        }
+
+        let id = row.id;
+        self.users.push(row);
+
+        Ok(id)
+    }
+
+    fn consume_token(
+        tokens: &mut [TokenRow],
+        token_hash: &[u8],
+    ) -> Option<(uuid::Uuid, Option<String>)> {
+        let now = now();
+
+        tokens
+            .iter_mut()
+            .find(|token| token.token_hash == token_hash && !token.used && token.expires_at > now)
+            .map(|token| {
+                token.used = true;
+
+                (token.user_id, token.email.clone())
+            })
+    }
+
+    fn sample_results(&self, sample_id: uuid::Uuid) -> impl Iterator<Item = &ResultRow> {
+        self.results
+            .iter()
+            .filter(move |result| result.sample_id == sample_id)
+    }
+
+    /// Samples matching everything in `filter` but its result conditions.
+    fn filtered_samples<'a>(
+        &'a self,
+        filter: &'a SampleFilter,
+    ) -> impl Iterator<Item = &'a SampleRow> {
+        self.samples.iter().filter(move |sample| {
+            sample.owner_id == filter.owner_id
+                && !sample.deleted
+                && matches_keyword(&sample.label, filter.keyword.as_deref())
+                && filter
+                    .pet_id
+                    .is_none_or(|pet_id| sample.pet_id == Some(pet_id))
+                && filter
+                    .created_from
+                    .is_none_or(|from| sample.created_at >= from)
+                && filter
+                    .created_until
+                    .is_none_or(|until| sample.created_at < until)
+                && filter
+                    .review_status
+                    .is_none_or(|status| sample.review_status == status)
+        })
+    }
+
+    fn pending_samples<'a>(
+        &'a self,
+        filter: &'a SampleFilter,
+    ) -> impl Iterator<Item = &'a SampleRow> {
+        self.filtered_samples(filter)
+            .filter(|sample| self.sample_results(sample.id).next().is_none())
+    }
+
+    /// Samples with at least one result matching the result conditions of `filter`.
+    fn inferred_samples<'a>(
+        &'a self,
+        filter: &'a SampleFilter,
+    ) -> impl Iterator<Item = &'a SampleRow> {
+        self.filtered_samples(filter).filter(|sample| {
+            self.sample_results(sample.id).any(|result| {
+                filter.classification.is_none_or(|classification| {
+                    result.is_normal == (classification == Classification::Normal)
+                }) && filter
+                    .min_certainty
+                    .is_none_or(|min_certainty| result.certainty >= min_certainty)
+                    && filter
+                        .model_version
+                        .as_ref()
+                        .is_none_or(|version| result.model_version.as_ref() == Some(version))
+            })
+        })
+    }
+
+    fn sort_key(&self, sample: &SampleRow, sort: SampleSort) -> SortKey {
+        match sort {
+            SampleSort::Date => SortKey::Date(sample.created_at),
+            SampleSort::Certainty => SortKey::Certainty(
+                self.sample_results(sample.id)
+                    .fold(0.0, |certainty, result| result.certainty.max(certainty)),
+            ),
+            SampleSort::Label => SortKey::Label(sample.label.clone()),
+        }
+    }
+
+    /// One page of `samples`, like the keyset query of Postgres.
+    fn page<'a>(
+        &self,
+        samples: impl Iterator<Item = &'a SampleRow>,
+        page: &PageRequest,
+    ) -> Vec<&'a SampleRow> {
+        let compare = |(a, a_id): &(SortKey, uuid::Uuid), (b, b_id): &(SortKey, uuid::Uuid)| {
+            let ordering = match (a, b) {
+                (SortKey::Date(a), SortKey::Date(b)) => a.cmp(b),
+                (SortKey::Certainty(a), SortKey::Certainty(b)) => a.total_cmp(b),
+                (SortKey::Label(a), SortKey::Label(b)) => a.cmp(b),
+                _ => std::cmp::Ordering::Equal,
+            }
+            .then(a_id.cmp(b_id));
+
+            match page.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        };
+        let after = page
+            .after
+            .as_ref()
+            .map(|after| (after.key.clone(), after.id));
+
+        let mut samples: Vec<_> = samples
+            .map(|sample| ((self.sort_key(sample, page.sort), sample.id), sample))
+            .filter(|(key, _)| {
+                after
+                    .as_ref()
+                    .is_none_or(|after| compare(key, after).is_gt())
+            })
+            .collect();
+
+        samples.sort_by(|(a, _), (b, _)| compare(a, b));
+        samples.truncate(page.limit);
+        samples.into_iter().map(|(_, sample)| sample).collect()
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn insert_user(&self, record: UserInsert) -> Result<uuid::Uuid, Error> {
+        self.state().insert_user(UserRow {
+            id: uuid::Uuid::new_v4(),
+            login_name: record.login_name,
+            first_name: record.first_name,
+            last_name: record.last_name,
+            argon2: record.argon2,
+            email: record.email,
+            email_verified_at: None,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: None,
+            is_admin: record.is_admin,
+        })
+    }
+
+    async fn profile(&self, user_id: uuid::Uuid) -> Result<Option<Profile>, Error> {
+        Ok(self.state().user(user_id).map(|user| Profile {
+            id: user.id,
+            login_name: user.login_name.clone(),
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            email: user.email.clone(),
+            email_verified: user.email_verified_at.is_some(),
+        }))
+    }
+
+    async fn update_user(&self, user_id: uuid::Uuid, changes: UserUpdate) -> Result<(), Error> {
+        let mut state = self.state();
+
+        if let Some(email) = &changes.email {
+            if state.email_taken(email, Some(user_id)) {
+                return Err(users::email_taken());
+            }
+        }
+
+        if let Some(user) = state.user_mut(user_id) {
+            if let Some(first_name) = changes.first_name {
+                user.first_name = first_name;
+            }
+            if let Some(last_name) = changes.last_name {
+                user.last_name = last_name;
+            }
+            if let Some(email) = changes.email {
+                user.email = Some(email);
+            }
+            if let Some(email_verified_at) = changes.email_verified_at {
+                user.email_verified_at = email_verified_at;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_user(&self, user_id: uuid::Uuid) -> Result<(), Error> {
+        let mut state = self.state();
+        let State {
+            samples,
+            results,
+            pets,
+            ..
+        } = &mut *state;
+
+        results.retain(|result| {
+            !samples
+                .iter()
+                .any(|sample| sample.id == result.sample_id && sample.owner_id == user_id)
+        });
+        samples.retain(|sample| sample.owner_id != user_id);
+        for sample in samples.iter_mut() {
+            if sample
+                .pet_id
+                .is_some_and(|pet_id| pets.get(&pet_id).is_some_and(|pet| pet.owner_id == user_id))
+            {
+                sample.pet_id = None;
+            }
+        }
+        pets.retain(|_, pet| pet.owner_id != user_id);
+
+        state
+            .sessions
+            .retain(|_, session| session.user_id != user_id);
+        state.api_tokens.retain(|token| token.user_id != user_id);
+        state
+            .password_resets
+            .retain(|token| token.user_id != user_id);
+        state
+            .email_verifications
+            .retain(|token| token.user_id != user_id);
+        state.recovery_codes.retain(|code| code.user_id != user_id);
+        state
+            .login_challenges
+            .retain(|_, challenge| challenge.user_id != user_id);
+        state.identities.retain(|_, id| *id != user_id);
+        state.users.retain(|user| user.id != user_id);
+
+        Ok(())
+    }
+
+    async fn user_by_credentials(
+        &self,
+        login_name: String,
+        hash: PasswordHash,
+    ) -> Result<Option<(uuid::Uuid, bool)>, Error> {
+        Ok(self
+            .state()
+            .users
+            .iter()
+            .find(|user| user.login_name == login_name && user.argon2 == hash)
+            .map(|user| (user.id, user.totp_enabled)))
+    }
+
+    async fn password_matches(
+        &self,
+        user_id: uuid::Uuid,
+        hash: PasswordHash,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .user(user_id)
+            .is_some_and(|user| user.argon2 == hash))
+    }
+
+    async fn update_password(
+        &self,
+        user_id: uuid::Uuid,
+        current: Option<PasswordHash>,
+        replacement: PasswordHash,
+    ) -> Result<bool, Error> {
+        let mut state = self.state();
+
+        match state.user_mut(user_id) {
+            Some(user) if current.is_none_or(|current| user.argon2 == current) => {
+                user.argon2 = replacement;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn insert_email_verification(
+        &self,
+        user_id: uuid::Uuid,
+        email: String,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error> {
+        self.state().email_verifications.push(TokenRow {
+            token_hash,
+            user_id,
+            email: Some(email),
+            expires_at,
+            used: false,
+        });
+
+        Ok(())
+    }
+
+    async fn consume_email_verification(&self, token_hash: Vec<u8>) -> Result<bool, Error> {
+        let mut state = self.state();
+
+        let Some((user_id, email)) =
+            State::consume_token(&mut state.email_verifications, &token_hash)
+        else {
+            return Ok(false);
+        };
+
+        match state.user_mut(user_id) {
+            Some(user) if user.email == email => {
+                user.email_verified_at = Some(now());
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn unverified_email(&self, user_id: uuid::Uuid) -> Result<Option<String>, Error> {
+        Ok(self
+            .state()
+            .user(user_id)
+            .filter(|user| user.email_verified_at.is_none())
+            .and_then(|user| user.email.clone()))
+    }
+
+    async fn verified_email(
+        &self,
+        login_name: String,
+    ) -> Result<Option<(uuid::Uuid, String)>, Error> {
+        Ok(self
+            .state()
+            .users
+            .iter()
+            .find(|user| user.login_name == login_name && user.email_verified_at.is_some())
+            .and_then(|user| Some((user.id, user.email.clone()?))))
+    }
+
+    async fn insert_password_reset(
+        &self,
+        user_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error> {
+        self.state().password_resets.push(TokenRow {
+            token_hash,
+            user_id,
+            email: None,
+            expires_at,
+            used: false,
+        });
+
+        Ok(())
+    }
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        Ok(
+            State::consume_token(&mut self.state().password_resets, &token_hash)
+                .map(|(user_id, _)| user_id),
+        )
+    }
+
+    async fn totp(&self, user_id: uuid::Uuid) -> Result<Option<TotpState>, Error> {
+        Ok(self.state().user(user_id).map(|user| TotpState {
+            secret: user.totp_secret.clone(),
+            enabled: user.totp_enabled,
+            last_step: user.totp_last_step,
+        }))
+    }
+
+    async fn begin_totp(&self, user_id: uuid::Uuid, secret: Vec<u8>) -> Result<bool, Error> {
+        let mut state = self.state();
+
+        match state.user_mut(user_id) {
+            Some(user) if !user.totp_enabled => {
+                user.totp_secret = Some(secret);
+                user.totp_last_step = None;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: uuid::Uuid,
+        step: i64,
+        recovery_code_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let mut state = self.state();
+
+        if let Some(user) = state.user_mut(user_id) {
+            user.totp_enabled = true;
+            user.totp_last_step = Some(step);
+        }
+
+        state.recovery_codes.retain(|code| code.user_id != user_id);
+        state
+            .recovery_codes
+            .extend(
+                recovery_code_hashes
+                    .into_iter()
+                    .map(|code_hash| RecoveryCodeRow {
+                        code_hash,
+                        user_id,
+                        used: false,
+                    }),
+            );
+
+        Ok(())
+    }
+
+    async fn disable_totp(&self, user_id: uuid::Uuid) -> Result<(), Error> {
+        let mut state = self.state();
+
+        if let Some(user) = state.user_mut(user_id) {
+            user.totp_enabled = false;
+            user.totp_secret = None;
+            user.totp_last_step = None;
+        }
+
+        state.recovery_codes.retain(|code| code.user_id != user_id);
+        state
+            .login_challenges
+            .retain(|_, challenge| challenge.user_id != user_id);
+
+        Ok(())
+    }
+
+    async fn set_totp_step(&self, user_id: uuid::Uuid, step: i64) -> Result<(), Error> {
+        if let Some(user) = self.state().user_mut(user_id) {
+            user.totp_last_step = Some(step);
+        }
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: uuid::Uuid,
+        code_hash: Vec<u8>,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .recovery_codes
+            .iter_mut()
+            .find(|code| code.user_id == user_id && code.code_hash == code_hash && !code.used)
+            .map(|code| code.used = true)
+            .is_some())
+    }
+
+    async fn identity_user(
+        &self,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        Ok(self.state().identities.get(&(issuer, subject)).copied())
+    }
+
+    async fn login_name_exists(&self, login_name: String) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .users
+            .iter()
+            .any(|user| user.login_name == login_name))
+    }
+
+    async fn email_exists(&self, email: String) -> Result<bool, Error> {
+        Ok(self.state().email_taken(&email, None))
+    }
+
+    async fn insert_oidc_user(
+        &self,
+        record: OidcUserInsert,
+        issuer: String,
+        subject: String,
+    ) -> Result<uuid::Uuid, Error> {
+        let mut state = self.state();
+
+        let user_id = state.insert_user(UserRow {
+            id: uuid::Uuid::new_v4(),
+            login_name: record.login_name,
+            first_name: record.first_name,
+            last_name: record.last_name,
+            argon2: record.argon2,
+            email: record.email,
+            email_verified_at: record.email_verified_at,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_last_step: None,
+            is_admin: false,
+        })?;
+
+        state.identities.insert((issuer, subject), user_id);
+
+        Ok(user_id)
+    }
+
+    async fn user_id(&self, login_name: String) -> Result<Option<uuid::Uuid>, Error> {
+        Ok(self
+            .state()
+            .users
+            .iter()
+            .find(|user| user.login_name == login_name)
+            .map(|user| user.id))
+    }
+
+    async fn set_admin(&self, user_id: uuid::Uuid, is_admin: bool) -> Result<(), Error> {
+        if let Some(user) = self.state().user_mut(user_id) {
+            user.is_admin = is_admin;
+        }
+
+        Ok(())
+    }
+
+    async fn count_users(&self) -> Result<(i64, i64), Error> {
+        let state = self.state();
+        let admins = state.users.iter().filter(|user| user.is_admin).count();
+
+        Ok((state.users.len() as i64, admins as i64))
+    }
+}
+
+#[async_trait]
+impl SessionRepository for MemoryRepository {
+    async fn create_session(&self, user_id: uuid::Uuid) -> Result<(uuid::Uuid, Vec<u8>), Error> {
+        let id = uuid::Uuid::new_v4();
+        let access_token = rand::random::<[u8; 32]>().to_vec();
+
+        self.state().sessions.insert(
+            id,
+            SessionRow {
+                access_token: access_token.clone(),
+                user_id,
+            },
+        );
+
+        Ok((id, access_token))
+    }
+
+    async fn session_user(
+        &self,
+        session_id: uuid::Uuid,
+        access_token: Vec<u8>,
+    ) -> Result<Option<SessionUser>, Error> {
+        let state = self.state();
+
+        Ok(state
+            .sessions
+            .get(&session_id)
+            .filter(|session| session.access_token == access_token)
+            .and_then(|session| state.session_user(session.user_id)))
+    }
+
+    async fn delete_sessions(
+        &self,
+        user_id: uuid::Uuid,
+        keep: Option<uuid::Uuid>,
+    ) -> Result<(), Error> {
+        self.state()
+            .sessions
+            .retain(|id, session| session.user_id != user_id || Some(*id) == keep);
+
+        Ok(())
+    }
+
+    async fn create_login_challenge(
+        &self,
+        user_id: uuid::Uuid,
+        expires_at: NaiveDateTime,
+    ) -> Result<uuid::Uuid, Error> {
+        let id = uuid::Uuid::new_v4();
+
+        self.state().login_challenges.insert(
+            id,
+            LoginChallengeRow {
+                user_id,
+                attempts: 0,
+                expires_at,
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn attempt_login_challenge(
+        &self,
+        challenge_id: uuid::Uuid,
+        max_attempts: i32,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        let now = now();
+
+        Ok(self
+            .state()
+            .login_challenges
+            .get_mut(&challenge_id)
+            .filter(|challenge| challenge.expires_at > now && challenge.attempts < max_attempts)
+            .map(|challenge| {
+                challenge.attempts += 1;
+
+                challenge.user_id
+            }))
+    }
+
+    async fn login_challenge_login_name(
+        &self,
+        challenge_id: uuid::Uuid,
+    ) -> Result<Option<String>, Error> {
+        let now = now();
+        let state = self.state();
+
+        Ok(state
+            .login_challenges
+            .get(&challenge_id)
+            .filter(|challenge| challenge.expires_at > now)
+            .and_then(|challenge| state.user(challenge.user_id))
+            .map(|user| user.login_name.clone()))
+    }
+
+    async fn delete_login_challenge(&self, challenge_id: uuid::Uuid) -> Result<(), Error> {
+        self.state().login_challenges.remove(&challenge_id);
+
+        Ok(())
+    }
+
+    async fn insert_api_token(&self, record: ApiTokenInsert) -> Result<uuid::Uuid, Error> {
+        let id = uuid::Uuid::new_v4();
+
+        self.state().api_tokens.push(ApiTokenRow {
+            id,
+            name: record.name,
+            token_hash: record.token_hash,
+            scopes: record.scopes,
+            user_id: record.user_id,
+            revoked: false,
+            last_used_at: None,
+            created_at: now(),
+        });
+
+        Ok(id)
+    }
+
+    async fn api_token_user(
+        &self,
+        token_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<(SessionUser, Vec<Scope>)>, Error> {
+        let mut state = self.state();
+
+        let Some(token) = state
+            .api_tokens
+            .iter_mut()
+            .find(|token| token.id == token_id && token.token_hash == token_hash && !token.revoked)
+        else {
+            return Ok(None);
+        };
+
+        token.last_used_at = Some(now());
+
+        let (user_id, scopes) = (token.user_id, Scope::parse_all(&token.scopes));
+
+        Ok(state.session_user(user_id).map(|user| (user, scopes)))
+    }
+
+    async fn api_tokens(&self, user_id: uuid::Uuid) -> Result<Vec<ApiTokenEntry>, Error> {
+        Ok(self
+            .state()
+            .api_tokens
+            .iter()
+            .rev()
+            .filter(|token| token.user_id == user_id && !token.revoked)
+            .map(|token| ApiTokenEntry {
+                id: token.id,
+                name: token.name.clone(),
+                scopes: Scope::parse_all(&token.scopes),
+                last_used_at: token.last_used_at,
+                created_at: token.created_at,
+            })
+            .collect())
+    }
+
+    async fn revoke_api_token(
+        &self,
+        user_id: uuid::Uuid,
+        token_id: uuid::Uuid,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .api_tokens
+            .iter_mut()
+            .find(|token| token.id == token_id && token.user_id == user_id && !token.revoked)
+            .map(|token| token.revoked = true)
+            .is_some())
+    }
+
+    async fn insert_oidc_login(&self, record: OidcLoginInsert) -> Result<(), Error> {
+        let now = now();
+        let mut state = self.state();
+
+        state.oidc_logins.retain(|_, login| login.expires_at >= now);
+        state.oidc_logins.insert(record.state.clone(), record);
+
+        Ok(())
+    }
+
+    async fn take_oidc_login(&self, state: String) -> Result<Option<(String, String)>, Error> {
+        Ok(self
+            .state()
+            .oidc_logins
+            .remove(&state)
+            .filter(|login| login.expires_at > now())
+            .map(|login| (login.pkce_verifier, login.nonce)))
+    }
+
+    async fn count_sessions(&self) -> Result<i64, Error> {
+        Ok(self.state().sessions.len() as i64)
+    }
+}
+
+#[async_trait]
+impl SampleRepository for MemoryRepository {
+    async fn insert_samples(&self, samples: Vec<SampleInsert>) -> Result<(), Error> {
+        let now = now();
+
+        self.state()
+            .samples
+            .extend(samples.into_iter().map(|sample| SampleRow {
+                id: uuid::Uuid::new_v4(),
+                label: sample.label,
+                bytes: sample.bytes,
+                owner_id: sample.owner_id,
+                pet_id: None,
+                deleted: sample.deleted,
+                review_status: ReviewStatus::default(),
+                notes: None,
+                created_at: now,
+            }));
+
+        Ok(())
+    }
+
+    async fn sample_bytes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: Option<uuid::Uuid>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .state()
+            .samples
+            .iter()
+            .find(|sample| {
+                sample.id == sample_id
+                    && !sample.deleted
+                    && owner_id.is_none_or(|owner_id| sample.owner_id == owner_id)
+            })
+            .map(|sample| sample.bytes.clone()))
+    }
+
+    async fn delete_sample(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .samples
+            .iter_mut()
+            .find(|sample| sample.id == sample_id && sample.owner_id == owner_id && !sample.deleted)
+            .map(|sample| sample.deleted = true)
+            .is_some())
+    }
+
+    async fn set_review_status(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        status: ReviewStatus,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .samples
+            .iter_mut()
+            .find(|sample| sample.id == sample_id && sample.owner_id == owner_id && !sample.deleted)
+            .map(|sample| sample.review_status = status)
+            .is_some())
+    }
+
+    async fn set_notes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        notes: Option<String>,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .samples
+            .iter_mut()
+            .find(|sample| sample.id == sample_id && sample.owner_id == owner_id && !sample.deleted)
+            .map(|sample| sample.notes = notes)
+            .is_some())
+    }
+
+    async fn pending_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<PendingListEntry>, Error> {
+        let state = self.state();
+
+        Ok(state
+            .page(state.pending_samples(&filter), &page)
+            .into_iter()
+            .map(|sample| PendingListEntry {
+                id: sample.id,
+                label: sample.label.clone(),
+                pet_id: sample.pet_id,
+                review_status: sample.review_status,
+                notes: sample.notes.clone(),
+                created_at: sample.created_at,
+            })
+            .collect())
+    }
+
+    async fn count_pending_samples(&self, filter: SampleFilter) -> Result<i64, Error> {
+        Ok(self.state().pending_samples(&filter).count() as i64)
+    }
+
+    async fn purge_deleted_samples(&self) -> Result<usize, Error> {
+        let mut state = self.state();
+        let before = state.samples.len();
+
+        state.samples.retain(|sample| !sample.deleted);
+
+        Ok(before - state.samples.len())
+    }
+
+    async fn select_samples(&self, selection: SampleSelection) -> Result<Vec<uuid::Uuid>, Error> {
+        let state = self.state();
+
+        Ok(state
+            .samples
+            .iter()
+            .filter(|sample| {
+                !sample.deleted
+                    && (selection.sample_ids.is_empty()
+                        || selection.sample_ids.contains(&sample.id))
+                    && selection
+                        .owner_id
+                        .is_none_or(|owner_id| sample.owner_id == owner_id)
+                    && selection
+                        .created_since
+                        .is_none_or(|created_since| sample.created_at >= created_since)
+                    && !(selection.pending_only
+                        && state
+                            .results
+                            .iter()
+                            .any(|result| result.sample_id == sample.id))
+            })
+            .map(|sample| sample.id)
+            .collect())
+    }
+
+    async fn count_samples(&self) -> Result<SampleCounts, Error> {
+        let state = self.state();
+
+        Ok(SampleCounts {
+            total: state.samples.len() as i64,
+            deleted: state.samples.iter().filter(|sample| sample.deleted).count() as i64,
+            pending: state
+                .samples
+                .iter()
+                .filter(|sample| {
+                    !sample.deleted
+                        && !state
+                            .results
+                            .iter()
+                            .any(|result| result.sample_id == sample.id)
+                })
+                .count() as i64,
+        })
+    }
+}
+
+#[async_trait]
+impl ResultRepository for MemoryRepository {
+    async fn insert_results(&self, results: Vec<ResultInsert>) -> Result<(), Error> {
+        let now = now();
+
+        self.state().results.extend(
+            results
+                .into_iter()
+                .map(|result| ResultRow::new(result, now)),
+        );
+
+        Ok(())
+    }
+
+    async fn inferred_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<InferredListEntry>, Error> {
+        let state = self.state();
+
+        Ok(state
+            .page(state.inferred_samples(&filter), &page)
+            .into_iter()
+            .map(|sample| InferredListEntry {
+                id: sample.id,
+                label: sample.label.clone(),
+                pet_id: sample.pet_id,
+                review_status: sample.review_status,
+                notes: sample.notes.clone(),
+                created_at: sample.created_at,
+                updated_at: None,
+                results: state
+                    .sample_results(sample.id)
+                    .map(|result| InferredResultListEntry {
+                        id: result.id,
+                        certainty: result.certainty,
+                        is_normal: result.is_normal,
+                        x: result.x,
+                        y: result.y,
+                        width: result.width,
+                        height: result.height,
+                        iris_x: None,
+                        iris_y: None,
+                        iris_a: None,
+                        iris_b: None,
+                        coverage: None,
+                        model_version: result.model_version.clone(),
+                        created_at: result.created_at,
+                        updated_at: None,
+                    })
+                    .collect::<Vec<_>>(),
+            })
+            .collect())
+    }
+
+    async fn count_inferred_samples(&self, filter: SampleFilter) -> Result<i64, Error> {
+        Ok(self.state().inferred_samples(&filter).count() as i64)
+    }
+
+    async fn delete_deleted_sample_results(&self) -> Result<(), Error> {
+        let mut state = self.state();
+        let State {
+            samples, results, ..
+        } = &mut *state;
+
+        results.retain(|result| {
+            !samples
+                .iter()
+                .any(|sample| sample.id == result.sample_id && sample.deleted)
+        });
+
+        Ok(())
+    }
+
+    async fn replace_results(
+        &self,
+        sample_id: uuid::Uuid,
+        results: Vec<ResultInsert>,
+    ) -> Result<(), Error> {
+        let now = now();
+        let mut state = self.state();
+
+        state.results.retain(|result| result.sample_id != sample_id);
+        state.results.extend(
+            results
+                .into_iter()
+                .map(|result| ResultRow::new(result, now)),
+        );
+
+        Ok(())
+    }
+
+    async fn count_results(&self) -> Result<i64, Error> {
+        Ok(self.state().results.len() as i64)
+    }
+}
+
+#[async_trait]
+impl PetRepository for MemoryRepository {
+    async fn insert_pet(&self, record: PetInsert) -> Result<uuid::Uuid, Error> {
+        let id = uuid::Uuid::new_v4();
+
+        self.state().pets.insert(
+            id,
+            PetRow {
+                name: record.name,
+                birthday: record.birthday,
+                exact_birthday: record.exact_birthday,
+                owner_id: record.owner_id,
+                created_at: now(),
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn pets(&self, owner_id: uuid::Uuid) -> Result<Vec<PetEntry>, Error> {
+        let mut pets: Vec<PetEntry> = self
+            .state()
+            .pets
+            .iter()
+            .filter(|(_, pet)| pet.owner_id == owner_id)
+            .map(|(id, pet)| PetEntry {
+                id: *id,
+                name: pet.name.clone(),
+                birthday: pet.birthday,
+                exact_birthday: pet.exact_birthday,
+                created_at: pet.created_at,
+            })
+            .collect();
+
+        pets.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+        Ok(pets)
+    }
+
+    async fn assign_pet(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        pet_id: Option<uuid::Uuid>,
+    ) -> Result<bool, Error> {
+        let mut state = self.state();
+        let State { samples, pets, .. } = &mut *state;
+
+        if pet_id.is_some_and(|pet_id| pets.get(&pet_id).is_none_or(|pet| pet.owner_id != owner_id))
+        {
+            return Ok(false);
+        }
+
+        Ok(samples
+            .iter_mut()
+            .find(|sample| sample.id == sample_id && sample.owner_id == owner_id && !sample.deleted)
+            .map(|sample| sample.pet_id = pet_id)
+            .is_some())
+    }
+}
+
+#[async_trait]
+impl SearchRepository for MemoryRepository {
+    /// Samples having every word of `query` in their label or notes and pets
+    /// having every word in their name; search operators are not understood.
+    async fn search(
+        &self,
+        owner_id: uuid::Uuid,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, Error> {
+        let query = words(&query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let state = self.state();
+        let mut hits: Vec<_> = state
+            .samples
+            .iter()
+            .filter(|sample| sample.owner_id == owner_id && !sample.deleted)
+            .filter_map(|sample| {
+                let label = words(&sample.label);
+                let notes = words(sample.notes.as_deref().unwrap_or_default());
+
+                // Label matches weigh more, like the `A` and `B` weights of Postgres.
+                let rank = query.iter().try_fold(0.0, |rank, word| {
+                    if label.contains(word) {
+                        Some(rank + 1.0)
+                    } else if notes.contains(word) {
+                        Some(rank + 0.4)
+                    } else {
+                        None
+                    }
+                })?;
+
+                Some(SearchHit {
+                    kind: SearchKind::Sample,
+                    id: sample.id,
+                    title: sample.label.clone(),
+                    pet_id: sample.pet_id,
+                    created_at: sample.created_at,
+                    rank: rank / query.len() as f32,
+                })
+            })
+            .collect();
+
+        hits.extend(
+            state
+                .pets
+                .iter()
+                .filter(|(_, pet)| pet.owner_id == owner_id)
+                .filter(|(_, pet)| {
+                    let name = words(&pet.name);
+                    query.iter().all(|word| name.contains(word))
+                })
+                .map(|(id, pet)| SearchHit {
+                    kind: SearchKind::Pet,
+                    id: *id,
+                    title: pet.name.clone(),
+                    pet_id: None,
+                    created_at: pet.created_at,
+                    rank: 1.0,
+                }),
+        );
+
+        hits.sort_by(|a, b| {
+            b.rank
+                .total_cmp(&a.rank)
+                .then(b.created_at.cmp(&a.created_at))
+                .then(a.id.cmp(&b.id))
+        });
+        hits.truncate(limit);
+
+        Ok(hits)
+    }
+}
+
+#[async_trait]
+impl HealthRepository for MemoryRepository {
+    async fn ping(&self) -> Result<Option<PoolState>, Error> {
+        Ok(None)
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        None
+    }
+}
@@ -0,0 +1,147 @@
+use diesel::migration::{Migration, MigrationSource};
+use diesel::pg::Pg;
+use diesel::{Connection, PgConnection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// What to do with pending migrations when the server starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationMode {
+    /// Apply pending migrations.
+    Run,
+    /// Refuse to start unless the database matches the embedded migrations.
+    Verify,
+    Skip,
+}
+
+impl std::str::FromStr for MigrationMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "run" => Ok(Self::Run),
+            "verify" => Ok(Self::Verify),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!("Expected run, verify or skip, got {value:?}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::Subcommand)]
+pub enum MigrateAction {
+    /// List embedded migrations and whether each one is applied.
+    Status,
+    /// Apply all pending migrations.
+    Up,
+    /// Revert the most recently applied migrations.
+    Down {
+        #[arg(long, default_value_t = 1)]
+        steps: u32,
+    },
+}
+
+pub(crate) fn prepare(connection: &mut PgConnection, mode: MigrationMode) -> Result<(), BoxError> {
+    match mode {
+        MigrationMode::Run => {
+            for version in connection.run_pending_migrations(MIGRATIONS)? {
+                tracing::info!("Applied migration {version}");
+            }
+
+            Ok(())
+        }
+        MigrationMode::Verify => verify(connection),
+        MigrationMode::Skip => Ok(()),
+    }
+}
+
+fn verify(connection: &mut PgConnection) -> Result<(), BoxError> {
+    let pending: Vec<_> = connection
+        .pending_migrations(MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+
+    if !pending.is_empty() {
+        return Err(format!("Pending migrations: {}", pending.join(", ")).into());
+    }
+
+    let unknown = unknown_versions(connection)?;
+
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Database has migrations this build does not know: {}",
+            unknown.join(", ")
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Applied versions that are not embedded, i.e. the database is ahead of
+/// this build.
+fn unknown_versions(connection: &mut PgConnection) -> Result<Vec<String>, BoxError> {
+    let embedded: Vec<_> = MigrationSource::<Pg>::migrations(&MIGRATIONS)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+
+    Ok(connection
+        .applied_migrations()?
+        .into_iter()
+        .map(|version| version.to_string())
+        .filter(|version| !embedded.contains(version))
+        .collect())
+}
+
+pub(crate) fn migrate(url: &str, action: MigrateAction) -> Result<(), BoxError> {
+    let mut connection = PgConnection::establish(url)?;
+
+    match action {
+        MigrateAction::Status => {
+            let applied: Vec<_> = connection
+                .applied_migrations()?
+                .into_iter()
+                .map(|version| version.to_string())
+                .collect();
+
+            for migration in MigrationSource::<Pg>::migrations(&MIGRATIONS)? {
+                let version = migration.name().version().to_string();
+                let mark = if applied.contains(&version) { 'X' } else { ' ' };
+
+                println!("[{mark}] {}", migration.name());
+            }
+
+            for version in unknown_versions(&mut connection)? {
+                println!("[?] {version} (not in this build)");
+            }
+        }
+        MigrateAction::Up => {
+            let applied = connection.run_pending_migrations(MIGRATIONS)?;
+
+            if applied.is_empty() {
+                println!("Nothing to migrate");
+            }
+
+            for version in applied {
+                println!("Applied migration {version}");
+            }
+        }
+        MigrateAction::Down { steps } => {
+            for _ in 0..steps {
+                if connection.applied_migrations()?.is_empty() {
+                    println!("Nothing to revert");
+                    break;
+                }
+
+                let version = connection.revert_last_migration(MIGRATIONS)?;
+                println!("Reverted migration {version}");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,68 +1,323 @@
+mod memory;
+mod migrations;
+mod pets;
+mod postgres;
+mod repository;
 mod samples;
+mod search;
 mod users;
 
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use base64::prelude::{Engine, BASE64_STANDARD};
-use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::{
-    BoolExpressionMethods, ExpressionMethods, PgConnection, PgTextExpressionMethods, QueryDsl,
-    RunQueryDsl, SelectableHelper,
-};
+use base64::prelude::{Engine, BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
 
-use crate::messages::samples::SampleUploadResult;
+use crate::error::Error;
+use crate::mailer::Mailer;
+use crate::messages::samples::{SampleSort, SortOrder};
 use crate::messages::users::LoginUserResult;
 use crate::password_hasher::PasswordHasher;
-pub(crate) use samples::SampleInsert;
-pub(crate) use users::UserSession;
+pub(crate) use memory::MemoryRepository;
+pub(crate) use migrations::migrate;
+pub use migrations::{MigrateAction, MigrationMode};
+use postgres::PgRepository;
+pub(crate) use repository::PoolState;
+pub(crate) use repository::SampleSelection;
+use repository::{
+    HealthRepository, PageRequest, PetRepository, Repository, ResultRepository, SampleCounts,
+    SampleCursor, SampleFilter, SampleRepository, SearchRepository, SessionRepository, SortKey,
+    UserRepository,
+};
+pub(crate) use samples::{ReviewStatus, SampleInsert};
+pub(crate) use users::{BearerToken, Scope, UserSession};
 
-use crate::{messages, schema};
+use crate::{messages, oidc, totp, validation};
 
+/// Account and sample operations on top of whichever storage backend is
+/// configured. Routes only talk to this; the repositories only store data.
 pub(crate) struct Database {
-    pool: Pool<ConnectionManager<PgConnection>>,
+    users: Arc<dyn UserRepository>,
+    sessions: Arc<dyn SessionRepository>,
+    samples: Arc<dyn SampleRepository>,
+    results: Arc<dyn ResultRepository>,
+    pets: Arc<dyn PetRepository>,
+    search: Arc<dyn SearchRepository>,
+    health: Arc<dyn HealthRepository>,
+}
+
+#[derive(Clone)]
+pub enum DatabaseConfig {
+    Postgres {
+        url: String,
+        pool: PoolConfig,
+        migrations: MigrationMode,
+    },
+    Memory,
+}
+
+#[derive(Clone)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub test_on_check_out: bool,
+}
+
+/// Row counts for the `stats` command.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub(crate) users: i64,
+    pub(crate) admins: i64,
+    pub(crate) sessions: i64,
+    pub(crate) samples: SampleCounts,
+    pub(crate) results: i64,
+}
+
+#[inline]
+fn expires_in(duration: chrono::Duration) -> chrono::NaiveDateTime {
+    chrono::Utc::now().naive_utc() + duration
+}
+
+/// `spawn_blocking` that keeps the caller's span, and so its request id.
+async fn blocking<T, F>(task: F) -> Result<T, Error>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let span = tracing::Span::current();
+
+    tokio::task::spawn_blocking(move || span.in_scope(task))
+        .await
+        .map_err(Error::internal)
+}
+
+/// Asks for one sample more than the page holds, which tells whether
+/// another page follows.
+fn page_request(desc: &messages::samples::SampleList) -> Result<(PageRequest, usize), Error> {
+    let size = desc
+        .limit
+        .unwrap_or(repository::PAGE_SIZE)
+        .clamp(1, repository::PAGE_SIZE_MAX);
+    let order = desc.order.unwrap_or(desc.sort.default_order());
+
+    Ok((
+        PageRequest {
+            after: desc
+                .cursor
+                .as_deref()
+                .map(|cursor| SampleCursor::parse(cursor, desc.sort, order))
+                .transpose()?,
+            limit: size + 1,
+            sort: desc.sort,
+            order,
+        },
+        size,
+    ))
+}
+
+fn sample_filter(owner_id: uuid::Uuid, desc: messages::samples::SampleList) -> SampleFilter {
+    SampleFilter {
+        owner_id,
+        keyword: desc.keyword,
+        pet_id: desc.pet_id,
+        created_from: desc
+            .created_from
+            .map(|from| from.and_time(chrono::NaiveTime::MIN)),
+        created_until: desc
+            .created_to
+            .and_then(|to| to.checked_add_days(chrono::Days::new(1)))
+            .map(|until| until.and_time(chrono::NaiveTime::MIN)),
+        review_status: desc.review_status,
+        classification: desc.classification,
+        min_certainty: desc.min_certainty,
+        model_version: desc.model_version,
+    }
+}
+
+/// Drops the extra sample `page_request` asked for, returning where the
+/// next page starts if there is one.
+fn next_cursor<T>(
+    items: &mut Vec<T>,
+    size: usize,
+    order: SortOrder,
+    cursor: impl Fn(&T) -> SampleCursor,
+) -> Option<String> {
+    if items.len() <= size {
+        return None;
+    }
+
+    items.truncate(size);
+    items.last().map(|item| cursor(item).encode(order))
+}
+
+#[inline]
+fn invalid_token() -> Error {
+    Error::invalid_field("invalid_token", "token", "Invalid or expired")
 }
 
 impl Database {
     #[inline]
-    pub(crate) async fn new(url: &str) -> Self {
-        let manager = ConnectionManager::new(url);
-        let pool = Pool::builder()
-            .test_on_check_out(true)
-            .build(manager)
-            .expect("Could not build connection pool");
+    pub(crate) fn new(config: &DatabaseConfig) -> Self {
+        match config {
+            DatabaseConfig::Postgres {
+                url,
+                pool,
+                migrations,
+            } => Self::with_repository(Arc::new(PgRepository::new(url, pool, *migrations))),
+            DatabaseConfig::Memory => Self::with_repository(Arc::new(MemoryRepository::new())),
+        }
+    }
 
-        Self { pool }
+    pub(crate) fn with_repository<R: Repository + 'static>(repository: Arc<R>) -> Self {
+        Self {
+            users: repository.clone(),
+            sessions: repository.clone(),
+            samples: repository.clone(),
+            results: repository.clone(),
+            pets: repository.clone(),
+            search: repository.clone(),
+            health: repository,
+        }
     }
 
     #[inline]
     pub(crate) async fn register(
         &self,
         hasher: Arc<PasswordHasher<'static>>,
+        mailer: Arc<Mailer>,
         desc: messages::users::RegisterUser,
-    ) -> messages::users::RegisterUserResult {
+    ) -> Result<messages::users::RegisterUserResult, Error> {
+        desc.validate()?;
+
         let record = users::UserInsert {
             login_name: desc.login_name,
             first_name: desc.first_name,
             last_name: desc.last_name,
-            argon2: hasher.hash(&desc.password),
+            argon2: hasher.hash(&desc.password)?,
+            email: desc.email,
+            is_admin: false,
+        };
+        let email = record.email.clone();
+
+        let id = self.users.insert_user(record).await?;
+
+        if let Some(email) = email {
+            self.issue_email_verification(mailer, id, email).await?;
+        }
+
+        Ok(messages::users::RegisterUserResult { id })
+    }
+
+    async fn issue_email_verification(
+        &self,
+        mailer: Arc<Mailer>,
+        user_id: uuid::Uuid,
+        email: String,
+    ) -> Result<(), Error> {
+        let secret = rand::random::<[u8; 32]>();
+
+        self.users
+            .insert_email_verification(
+                user_id,
+                email.clone(),
+                Sha256::digest(secret).to_vec(),
+                expires_in(chrono::Duration::hours(24)),
+            )
+            .await?;
+
+        blocking(move || {
+            mailer.send_email_verification(&email, &BASE64_URL_SAFE_NO_PAD.encode(secret))
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn get_profile(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<messages::users::Profile, Error> {
+        self.users
+            .profile(user_id)
+            .await?
+            .ok_or_else(users::unauthenticated)
+    }
+
+    #[inline]
+    pub(crate) async fn update_profile(
+        &self,
+        mailer: Arc<Mailer>,
+        user_id: uuid::Uuid,
+        desc: messages::users::UpdateProfile,
+    ) -> Result<messages::users::Profile, Error> {
+        desc.validate()?;
+
+        let current = self.get_profile(user_id).await?;
+
+        let email = desc
+            .email
+            .filter(|email| Some(email) != current.email.as_ref());
+
+        let changes = users::UserUpdate {
+            first_name: desc.first_name,
+            last_name: desc.last_name,
+            email_verified_at: email.as_ref().map(|_| None),
+            email: email.clone(),
         };
 
-        let mut connection = self.pool.get().unwrap();
+        if changes.first_name.is_some() || changes.last_name.is_some() || changes.email.is_some() {
+            self.users.update_user(user_id, changes).await?;
+        }
+
+        if let Some(email) = email {
+            self.issue_email_verification(mailer, user_id, email)
+                .await?;
+        }
 
-        let result = diesel::insert_into(schema::users::table)
-            .values(&record)
-            .returning(schema::users::id)
-            .get_result::<uuid::Uuid>(&mut connection);
+        self.get_profile(user_id).await
+    }
 
-        match result {
-            Ok(id) => messages::users::RegisterUserResult::Success { id },
-            Err(diesel::result::Error::DatabaseError(
-                diesel::result::DatabaseErrorKind::UniqueViolation,
-                _,
-            )) => messages::users::RegisterUserResult::LoginNameAlreadyExists,
-            Err(_) => messages::users::RegisterUserResult::ServerError,
+    #[inline]
+    pub(crate) async fn verify_email(
+        &self,
+        desc: messages::users::VerifyEmail,
+    ) -> Result<(), Error> {
+        let secret = BASE64_URL_SAFE_NO_PAD
+            .decode(&desc.token)
+            .map_err(|_| invalid_token())?;
+
+        if !self
+            .users
+            .consume_email_verification(Sha256::digest(secret).to_vec())
+            .await?
+        {
+            return Err(invalid_token());
         }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn resend_email_verification(
+        &self,
+        mailer: Arc<Mailer>,
+        user_id: uuid::Uuid,
+    ) -> Result<(), Error> {
+        let email = self
+            .users
+            .unverified_email(user_id)
+            .await?
+            .ok_or(Error::Conflict {
+                code: "no_pending_email",
+                field: "email",
+                message: "No unverified email address".into(),
+            })?;
+
+        self.issue_email_verification(mailer, user_id, email).await
     }
 
     #[inline]
@@ -70,38 +325,265 @@ impl Database {
         &self,
         hasher: Arc<PasswordHasher<'static>>,
         desc: messages::users::LoginUser,
-    ) -> LoginUserResult {
-        use crate::schema::{session, users};
+    ) -> Result<LoginUserResult, Error> {
+        let hash = hasher.hash(&desc.password)?;
+
+        match self
+            .users
+            .user_by_credentials(desc.login_name, hash)
+            .await?
+        {
+            Some((user_id, true)) => {
+                let challenge_id = self
+                    .sessions
+                    .create_login_challenge(user_id, expires_in(chrono::Duration::minutes(5)))
+                    .await?;
+
+                Ok(LoginUserResult::SecondFactorRequired { challenge_id })
+            }
+            Some((user_id, false)) => self.create_session(user_id, desc.bearer).await,
+            None => Err(users::invalid_credentials()),
+        }
+    }
+
+    async fn create_session(
+        &self,
+        user_id: uuid::Uuid,
+        bearer: bool,
+    ) -> Result<LoginUserResult, Error> {
+        let (session_id, access_token) = self.sessions.create_session(user_id).await?;
+
+        Ok(LoginUserResult::Success {
+            id: session_id,
+            access_token: BASE64_STANDARD.encode(access_token),
+            bearer,
+        })
+    }
+
+    /// Login name the challenge was issued to, so codes count against the
+    /// same lockout as passwords.
+    #[inline]
+    pub(crate) async fn login_challenge_login_name(
+        &self,
+        challenge_id: uuid::Uuid,
+    ) -> Result<Option<String>, Error> {
+        self.sessions.login_challenge_login_name(challenge_id).await
+    }
+
+    #[inline]
+    pub(crate) async fn login_second_factor(
+        &self,
+        desc: messages::users::LoginSecondFactor,
+    ) -> Result<LoginUserResult, Error> {
+        const MAX_ATTEMPTS: i32 = 5;
+
+        let user_id = self
+            .sessions
+            .attempt_login_challenge(desc.challenge_id, MAX_ATTEMPTS)
+            .await?
+            .ok_or_else(users::invalid_credentials)?;
+
+        let Some(state) = self
+            .users
+            .totp(user_id)
+            .await?
+            .filter(|state| state.enabled)
+        else {
+            return Err(users::invalid_credentials());
+        };
+
+        let step = state
+            .secret
+            .and_then(|secret| totp::verify(&secret, &desc.code, state.last_step));
 
-        let mut connection = self.pool.get().expect("Unable to connect to database");
+        let accepted = if let Some(step) = step {
+            self.users.set_totp_step(user_id, step).await?;
 
-        let hash = hasher.hash(&desc.password);
+            true
+        } else {
+            self.users
+                .use_recovery_code(user_id, totp::hash_recovery_code(&desc.code))
+                .await?
+        };
+
+        if !accepted {
+            return Err(users::invalid_credentials());
+        }
+
+        self.sessions
+            .delete_login_challenge(desc.challenge_id)
+            .await?;
+
+        self.create_session(user_id, desc.bearer).await
+    }
 
-        match users::table
-            .filter(users::login_name.eq(desc.login_name))
-            .select((users::id, users::argon2.eq(hash)))
-            .first::<(uuid::Uuid, bool)>(&mut connection)
+    #[inline]
+    pub(crate) async fn begin_oidc_login(
+        &self,
+        request: oidc::AuthorizationRequest,
+    ) -> Result<messages::users::OidcRedirect, Error> {
+        self.sessions
+            .insert_oidc_login(repository::OidcLoginInsert {
+                state: request.state.clone(),
+                pkce_verifier: request.pkce_verifier,
+                nonce: request.nonce,
+                expires_at: expires_in(chrono::Duration::minutes(10)),
+            })
+            .await?;
+
+        Ok(messages::users::OidcRedirect {
+            url: request.url,
+            state: request.state,
+        })
+    }
+
+    /// Consumes a pending OIDC login, returning its PKCE verifier and nonce.
+    #[inline]
+    pub(crate) async fn take_oidc_login(&self, state: String) -> Result<(String, String), Error> {
+        self.sessions
+            .take_oidc_login(state)
+            .await?
+            .ok_or_else(oidc::invalid_state)
+    }
+
+    /// Signs in the user linked to an external identity, provisioning a new
+    /// account on first login. Accounts are never linked by email alone.
+    #[inline]
+    pub(crate) async fn oidc_sign_in(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        identity: oidc::Identity,
+    ) -> Result<LoginUserResult, Error> {
+        if let Some(user_id) = self
+            .users
+            .identity_user(identity.issuer.clone(), identity.subject.clone())
+            .await?
         {
-            Ok((user_id, is_matched)) => {
-                if is_matched {
-                    match diesel::insert_into(session::table)
-                        .values(session::user_id.eq(user_id))
-                        .returning((session::id, session::access_token))
-                        .get_result::<(uuid::Uuid, Vec<u8>)>(&mut connection)
-                    {
-                        Ok((session_id, access_token)) => LoginUserResult::Success {
-                            id: session_id,
-                            access_token: BASE64_STANDARD.encode(access_token),
-                        },
-                        Err(_) => LoginUserResult::ServerError,
-                    }
-                } else {
-                    LoginUserResult::Invalid
-                }
-            }
-            Err(diesel::result::Error::NotFound) => LoginUserResult::Invalid,
-            Err(_) => LoginUserResult::ServerError,
+            return self.create_session(user_id, false).await;
+        }
+
+        let base = users::OidcUserInsert::login_name_base(&identity);
+        let mut login_name = base.clone();
+
+        while self.users.login_name_exists(login_name.clone()).await? {
+            let suffix = format!("{:04}", rand::random::<u16>() % 10_000);
+            let keep = validation::LOGIN_NAME_MAX - suffix.len() - 1;
+
+            login_name = format!("{}_{suffix}", base.chars().take(keep).collect::<String>());
+        }
+
+        let email = match identity
+            .email
+            .filter(|_| identity.email_verified)
+            .filter(|email| email.chars().count() <= validation::EMAIL_MAX)
+        {
+            Some(email) if !self.users.email_exists(email.clone()).await? => Some(email),
+            _ => None,
+        };
+
+        let user_id = self
+            .users
+            .insert_oidc_user(
+                users::OidcUserInsert {
+                    login_name,
+                    first_name: users::OidcUserInsert::person_name(identity.given_name),
+                    last_name: users::OidcUserInsert::person_name(identity.family_name),
+                    // Never used to log in; password login stays unavailable.
+                    argon2: hasher.hash(&BASE64_STANDARD.encode(rand::random::<[u8; 32]>()))?,
+                    email_verified_at: email.as_ref().map(|_| chrono::Utc::now().naive_utc()),
+                    email,
+                },
+                identity.issuer,
+                identity.subject,
+            )
+            .await?;
+
+        self.create_session(user_id, false).await
+    }
+
+    #[inline]
+    pub(crate) async fn enroll_totp(
+        &self,
+        user_id: uuid::Uuid,
+        login_name: String,
+    ) -> Result<messages::users::EnrollTotpResult, Error> {
+        let secret = totp::generate_secret();
+
+        // Re-enrolling before confirmation replaces the pending secret.
+        if !self.users.begin_totp(user_id, secret.clone()).await? {
+            return Err(Error::Conflict {
+                code: "totp_enabled",
+                field: "totp",
+                message: "Already enabled".into(),
+            });
+        }
+
+        let (secret, provisioning_uri) = totp::provisioning(&secret, &login_name);
+
+        Ok(messages::users::EnrollTotpResult {
+            secret,
+            provisioning_uri,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn confirm_totp(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::users::TotpCode,
+    ) -> Result<messages::users::ConfirmTotpResult, Error> {
+        let secret = self
+            .users
+            .totp(user_id)
+            .await?
+            .filter(|state| !state.enabled)
+            .and_then(|state| state.secret)
+            .ok_or(Error::Conflict {
+                code: "totp_not_enrolled",
+                field: "totp",
+                message: "Not enrolled".into(),
+            })?;
+
+        let step = totp::verify(&secret, &desc.code, None).ok_or_else(users::invalid_code)?;
+
+        let codes = totp::generate_recovery_codes();
+
+        self.users
+            .enable_totp(
+                user_id,
+                step,
+                codes
+                    .iter()
+                    .map(|code| totp::hash_recovery_code(code))
+                    .collect(),
+            )
+            .await?;
+
+        Ok(messages::users::ConfirmTotpResult {
+            recovery_codes: codes,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn disable_totp(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::users::TotpCode,
+    ) -> Result<(), Error> {
+        let (secret, last_step) = match self.users.totp(user_id).await? {
+            Some(repository::TotpState {
+                secret: Some(secret),
+                enabled: true,
+                last_step,
+            }) => (secret, last_step),
+            _ => return Err(users::invalid_code()),
+        };
+
+        if totp::verify(&secret, &desc.code, last_step).is_none() {
+            return Err(users::invalid_code());
         }
+
+        self.users.disable_totp(user_id).await
     }
 
     #[inline]
@@ -109,97 +591,243 @@ impl Database {
         &self,
         session_id: uuid::Uuid,
         access_token: Vec<u8>,
-    ) -> Result<users::UserSession, users::AuthorizationError> {
-        use crate::schema::{session, users};
-
-        let mut connection = self.pool.get().expect("Unable to connect to database");
-
-        let (matched, user_id, login_name, first_name, last_name): (
-            bool,
-            uuid::Uuid,
-            String,
-            String,
-            String,
-        ) = users::table
-            .inner_join(session::table)
-            .filter(session::id.eq(session_id))
-            .select((
-                session::access_token.eq(access_token),
-                users::id,
-                users::login_name,
-                users::first_name,
-                users::last_name,
-            ))
-            .get_result(&mut connection)
-            .or(Err(self::users::AuthorizationError))?;
-
-        if !matched {
-            return Err(self::users::AuthorizationError);
+    ) -> Result<users::UserSession, Error> {
+        let user = self
+            .sessions
+            .session_user(session_id, access_token)
+            .await?
+            .ok_or_else(users::unauthenticated)?;
+
+        Ok(users::UserSession {
+            user_id: user.user_id,
+            login_name: user.login_name,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            credential: users::Credential::Session { id: session_id },
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn get_api_token_session(
+        &self,
+        token_id: uuid::Uuid,
+        secret: Vec<u8>,
+    ) -> Result<users::UserSession, Error> {
+        let (user, scopes) = self
+            .sessions
+            .api_token_user(token_id, Sha256::digest(secret).to_vec())
+            .await?
+            .ok_or_else(users::unauthenticated)?;
+
+        Ok(users::UserSession {
+            user_id: user.user_id,
+            login_name: user.login_name,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            credential: users::Credential::ApiToken {
+                id: token_id,
+                scopes,
+            },
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn create_api_token(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::users::CreateApiToken,
+    ) -> Result<messages::users::CreateApiTokenResult, Error> {
+        desc.validate()?;
+
+        if desc.scopes.is_empty() {
+            return Err(Error::invalid_field(
+                "scopes_required",
+                "scopes",
+                "At least one scope is required",
+            ));
         }
 
-        Ok(self::users::UserSession {
-            user_id,
-            login_name,
-            first_name,
-            last_name,
+        let secret = rand::random::<[u8; 32]>();
+
+        let id = self
+            .sessions
+            .insert_api_token(users::ApiTokenInsert {
+                name: desc.name,
+                token_hash: Sha256::digest(secret).to_vec(),
+                scopes: desc
+                    .scopes
+                    .iter()
+                    .map(|scope| Some(scope.as_str().to_string()))
+                    .collect(),
+                user_id,
+            })
+            .await?;
+
+        Ok(messages::users::CreateApiTokenResult {
+            id,
+            token: BearerToken::format_api_token(id, &secret),
         })
     }
 
     #[inline]
-    pub(crate) async fn upload_samples(
+    pub(crate) async fn list_api_tokens(
         &self,
-        samples: Vec<samples::SampleInsert>,
-    ) -> SampleUploadResult {
-        use crate::schema::samples;
+        user_id: uuid::Uuid,
+    ) -> Result<messages::users::ApiTokenListResult, Error> {
+        Ok(messages::users::ApiTokenListResult {
+            items: self.sessions.api_tokens(user_id).await?,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn revoke_api_token(
+        &self,
+        user_id: uuid::Uuid,
+        token_id: uuid::Uuid,
+    ) -> Result<(), Error> {
+        if !self.sessions.revoke_api_token(user_id, token_id).await? {
+            return Err(Error::NotFound {
+                code: "token_not_found",
+                message: "Token not found".into(),
+            });
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn change_password(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        user_id: uuid::Uuid,
+        session_id: uuid::Uuid,
+        desc: messages::users::ChangePassword,
+    ) -> Result<(), Error> {
+        desc.validate()?;
 
-        let mut connection = self.pool.get().expect("Unable to connect to database");
+        let current = hasher.hash(&desc.current_password)?;
+        let replacement = hasher.hash(&desc.new_password)?;
 
-        match diesel::insert_into(samples::table)
-            .values(samples)
-            .execute(&mut connection)
+        if !self
+            .users
+            .update_password(user_id, Some(current), replacement)
+            .await?
         {
-            Ok(_) => SampleUploadResult::Success,
-            Err(_) => SampleUploadResult::Failed,
+            return Err(Error::IncorrectPassword {
+                field: "current_password",
+            });
         }
+
+        self.sessions
+            .delete_sessions(user_id, Some(session_id))
+            .await
     }
 
     #[inline]
-    pub(crate) async fn get_sample_image(
+    pub(crate) async fn request_password_reset(
         &self,
-        sample_id: uuid::Uuid,
-    ) -> messages::samples::SampleImageResult {
-        use crate::schema::samples;
+        mailer: Arc<Mailer>,
+        desc: messages::users::ForgotPassword,
+    ) -> Result<(), Error> {
+        // Unknown login names and accounts without a verified address are
+        // accepted silently so the endpoint cannot be used to enumerate users.
+        let Some((user_id, email)) = self.users.verified_email(desc.login_name).await? else {
+            return Ok(());
+        };
 
-        let mut connection = self.pool.get().expect("Unable to connect to database");
+        let secret = rand::random::<[u8; 32]>();
 
-        match samples::table
-            .filter(samples::id.eq(sample_id).and(samples::deleted.eq(false)))
-            .select(samples::bytes)
-            .first::<Vec<u8>>(&mut connection)
-        {
-            Ok(bytes) => messages::samples::SampleImageResult::Success { bytes },
-            Err(diesel::result::Error::NotFound) => messages::samples::SampleImageResult::NotFound,
-            Err(_) => messages::samples::SampleImageResult::ServerError,
+        self.users
+            .insert_password_reset(
+                user_id,
+                Sha256::digest(secret).to_vec(),
+                expires_in(chrono::Duration::hours(1)),
+            )
+            .await?;
+
+        blocking(move || {
+            mailer.send_password_reset(&email, &BASE64_URL_SAFE_NO_PAD.encode(secret))
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn reset_password(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        desc: messages::users::ResetPassword,
+    ) -> Result<(), Error> {
+        desc.validate()?;
+
+        let secret = BASE64_URL_SAFE_NO_PAD
+            .decode(&desc.token)
+            .map_err(|_| invalid_token())?;
+
+        let replacement = hasher.hash(&desc.new_password)?;
+
+        let user_id = self
+            .users
+            .consume_password_reset(Sha256::digest(secret).to_vec())
+            .await?
+            .ok_or_else(invalid_token)?;
+
+        self.users
+            .update_password(user_id, None, replacement)
+            .await?;
+
+        self.sessions.delete_sessions(user_id, None).await
+    }
+
+    #[inline]
+    pub(crate) async fn delete_account(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        user_id: uuid::Uuid,
+        desc: messages::users::DeleteAccount,
+    ) -> Result<(), Error> {
+        let hash = hasher.hash(&desc.password)?;
+
+        if !self.users.password_matches(user_id, hash).await? {
+            return Err(Error::IncorrectPassword { field: "password" });
         }
+
+        self.users.delete_user(user_id).await
     }
 
     #[inline]
-    pub(crate) async fn delete_sample_image(
+    pub(crate) async fn upload_samples(
         &self,
-        sample_id: uuid::Uuid,
-    ) -> messages::samples::SampleInferResult {
-        use crate::schema::samples;
+        samples: Vec<samples::SampleInsert>,
+    ) -> Result<(), Error> {
+        self.samples.insert_samples(samples).await
+    }
 
-        let mut connection = self.pool.get().expect("Unable to connect to database");
+    #[inline]
+    pub(crate) async fn get_sample_image(
+        &self,
+        user_id: uuid::Uuid,
+        sample_id: uuid::Uuid,
+    ) -> Result<messages::samples::SampleImageResult, Error> {
+        self.samples
+            .sample_bytes(sample_id, Some(user_id))
+            .await?
+            .map(|bytes| messages::samples::SampleImageResult { bytes })
+            .ok_or_else(samples::sample_not_found)
+    }
 
-        match diesel::update(samples::table.filter(samples::id.eq(sample_id)))
-            .set(samples::deleted.eq(true))
-            .execute(&mut connection)
-        {
-            Ok(_) => messages::samples::SampleInferResult::Success,
-            Err(diesel::result::Error::NotFound) => messages::samples::SampleInferResult::NotFound,
-            Err(_) => messages::samples::SampleInferResult::ServerError,
+    #[inline]
+    pub(crate) async fn delete_sample_image(
+        &self,
+        user_id: uuid::Uuid,
+        sample_id: uuid::Uuid,
+    ) -> Result<(), Error> {
+        if !self.samples.delete_sample(sample_id, user_id).await? {
+            return Err(samples::sample_not_found());
         }
+
+        Ok(())
     }
 
     #[inline]
@@ -207,45 +835,47 @@ impl Database {
         &self,
         owner_id: uuid::Uuid,
         sample_id: uuid::Uuid,
-        detector: &crate::Detector,
-    ) -> messages::samples::SampleInferResult {
-        use crate::schema::{results, samples};
-
-        let mut connection = self.pool.get().expect("Unable to connect to database");
-
-        let img = match samples::table
-            .filter(
-                samples::deleted.eq(false).and(
-                    samples::id
-                        .eq(sample_id)
-                        .and(samples::owner_id.eq(owner_id)),
-                ),
-            )
-            .select(samples::bytes)
-            .first::<Vec<u8>>(&mut connection)
-        {
-            Ok(bytes) => {
-                if let Ok(img) = image::load_from_memory(&bytes) {
-                    img.resize_exact(640, 640, image::imageops::FilterType::Gaussian)
-                } else {
-                    return messages::samples::SampleInferResult::ImageLoadError;
-                }
-            }
-            Err(diesel::result::Error::NotFound) => {
-                return messages::samples::SampleInferResult::NotFound
-            }
-            Err(_) => return messages::samples::SampleInferResult::ServerError,
-        };
+        detector: &dyn crate::Detector,
+    ) -> Result<(), Error> {
+        let bytes = self
+            .samples
+            .sample_bytes(sample_id, Some(owner_id))
+            .await?
+            .ok_or_else(samples::sample_not_found)?;
 
-        let boxes = detector.infer(&img).await;
+        let results = Self::detect(sample_id, bytes, detector).await?;
 
-        if boxes.is_empty() {
-            return messages::samples::SampleInferResult::Reject;
+        if results.is_empty() {
+            return Err(Error::Unprocessable {
+                code: "no_detection",
+                field: None,
+                message: "Nothing was detected in the sample".into(),
+            });
         }
 
-        let result: Vec<self::samples::ResultInsert> = boxes
+        self.results.insert_results(results).await
+    }
+
+    #[tracing::instrument(skip(bytes, detector))]
+    async fn detect(
+        sample_id: uuid::Uuid,
+        bytes: Vec<u8>,
+        detector: &dyn crate::Detector,
+    ) -> Result<Vec<samples::ResultInsert>, Error> {
+        let img = blocking(move || {
+            image::load_from_memory(&bytes)
+                .map(|img| img.resize_exact(640, 640, image::imageops::FilterType::Gaussian))
+                .map_err(|e| {
+                    Error::internal(format!("Stored sample {sample_id} is unreadable: {e}"))
+                })
+        })
+        .await??;
+
+        Ok(detector
+            .infer(&img)
+            .await?
             .into_iter()
-            .map(|entry| self::samples::ResultInsert {
+            .map(|entry| samples::ResultInsert {
                 sample_id,
                 certainty: entry.probability,
                 is_normal: entry.classification == crate::detector::Classification::Normal,
@@ -253,157 +883,311 @@ impl Database {
                 y: entry.y,
                 width: entry.width,
                 height: entry.height,
+                model_version: detector.model_version().to_string(),
             })
-            .collect();
+            .collect())
+    }
 
-        match diesel::insert_into(results::table)
-            .values(result)
-            .execute(&mut connection)
+    pub(crate) async fn get_pending_list(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::samples::SampleList,
+    ) -> Result<messages::samples::PendingListResult, Error> {
+        desc.validate(false)?;
+
+        let (page, size) = page_request(&desc)?;
+        let count = desc.total;
+        let filter = sample_filter(user_id, desc);
+        let total = if count {
+            Some(self.samples.count_pending_samples(filter.clone()).await?)
+        } else {
+            None
+        };
+        let mut items = self.samples.pending_samples(filter, page.clone()).await?;
+        let next_cursor = next_cursor(&mut items, size, page.order, |item| SampleCursor {
+            key: match page.sort {
+                SampleSort::Label => SortKey::Label(item.label.clone()),
+                _ => SortKey::Date(item.created_at),
+            },
+            id: item.id,
+        });
+
+        Ok(messages::samples::PendingListResult {
+            items,
+            has_next: next_cursor.is_some(),
+            next_cursor,
+            total,
+        })
+    }
+
+    pub(crate) async fn get_inferred_list(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::samples::SampleList,
+    ) -> Result<messages::samples::InferredListResult, Error> {
+        desc.validate(true)?;
+
+        let (page, size) = page_request(&desc)?;
+        let count = desc.total;
+        let filter = sample_filter(user_id, desc);
+        let total = if count {
+            Some(self.results.count_inferred_samples(filter.clone()).await?)
+        } else {
+            None
+        };
+        let mut items = self.results.inferred_samples(filter, page.clone()).await?;
+        let next_cursor = next_cursor(&mut items, size, page.order, |item| SampleCursor {
+            key: match page.sort {
+                SampleSort::Date => SortKey::Date(item.created_at),
+                SampleSort::Certainty => SortKey::Certainty(
+                    item.results
+                        .iter()
+                        .map(|result| result.certainty)
+                        .fold(0.0, f32::max),
+                ),
+                SampleSort::Label => SortKey::Label(item.label.clone()),
+            },
+            id: item.id,
+        });
+
+        Ok(messages::samples::InferredListResult {
+            items,
+            has_next: next_cursor.is_some(),
+            next_cursor,
+            total,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn review_sample(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::samples::SampleReview,
+    ) -> Result<(), Error> {
+        if !self
+            .samples
+            .set_review_status(desc.sample_id, user_id, desc.review_status)
+            .await?
         {
-            Ok(_) => messages::samples::SampleInferResult::Success,
-            Err(_) => messages::samples::SampleInferResult::ServerError,
+            return Err(samples::sample_not_found());
         }
+
+        Ok(())
     }
 
     #[inline]
-    pub(crate) async fn get_pending_list(
+    pub(crate) async fn set_sample_notes(
         &self,
         user_id: uuid::Uuid,
-        desc: messages::samples::SamplePendingList,
-    ) -> messages::samples::PendingListResult {
-        use crate::schema::{results, samples};
-
-        let mut connection = self.pool.get().expect("Unable to connect to database");
-
-        match samples::table
-            .left_outer_join(results::table)
-            .filter(
-                results::sample_id
-                    .is_null()
-                    .and(
-                        samples::deleted
-                            .eq(false)
-                            .and(samples::owner_id.eq(user_id).and(samples::label.ilike(
-                                if let Some(search) = desc.keyword {
-                                    format!("%{}%", search)
-                                } else {
-                                    "%".to_string()
-                                },
-                            ))),
-                    ),
-            )
-            .select((samples::id, samples::label, samples::pet_id))
-            .limit(10)
-            .offset(desc.page as i64)
-            .order(samples::created_at.desc())
-            .get_results::<self::samples::SampleEntry>(&mut connection)
-        {
-            Ok(items) => {
-                let items: Vec<self::samples::SampleEntry> = items;
+        desc: messages::samples::SampleNotes,
+    ) -> Result<(), Error> {
+        desc.validate()?;
 
-                messages::samples::PendingListResult::Success {
-                    items: items
-                        .iter()
-                        .map(|entry| self::messages::samples::PendingListEntry {
-                            id: entry.id,
-                            label: entry.label.clone(),
-                            pet_id: entry.pet_id,
-                        })
-                        .collect(),
-                    has_next: items.len() == 10,
-                }
-            }
-            _ => messages::samples::PendingListResult::Failed,
+        if !self
+            .samples
+            .set_notes(desc.sample_id, user_id, desc.notes)
+            .await?
+        {
+            return Err(samples::sample_not_found());
         }
+
+        Ok(())
     }
 
     #[inline]
-    pub(crate) async fn get_inferred_list(
+    pub(crate) async fn assign_sample_pet(
         &self,
         user_id: uuid::Uuid,
-        desc: messages::samples::SampleInferredList,
-    ) -> messages::samples::InferredListResult {
-        use crate::schema::{results, samples};
-
-        let mut connection = self.pool.get().expect("Unable to connect to database");
-
-        match results::table
-            .inner_join(samples::table)
-            .filter(
-                samples::owner_id
-                    .eq(user_id)
-                    .and(samples::pet_id.is_null())
-                    .and(samples::label.ilike(if let Some(search) = desc.keyword {
-                        format!("%{}%", search)
-                    } else {
-                        "%".to_string()
-                    })),
-            )
-            .select((
-                self::samples::Result::as_select(),
-                self::samples::Sample::as_select(),
-            ))
-            .limit(10)
-            .offset(desc.page as i64)
-            .order(samples::created_at.desc())
-            .get_results::<(self::samples::Result, self::samples::Sample)>(&mut connection)
+        desc: messages::samples::SamplePet,
+    ) -> Result<(), Error> {
+        if !self
+            .pets
+            .assign_pet(desc.sample_id, user_id, desc.pet_id)
+            .await?
         {
-            Ok(r) => {
-                let list: Vec<(self::samples::Result, self::samples::Sample)> = r;
-
-                let map = list.into_iter().fold(
-                    HashMap::<uuid::Uuid, messages::samples::InferredListEntry>::new(),
-                    |mut buffer, (result, sample)| {
-                        let result_entry = messages::samples::InferredResultListEntry {
-                            id: result.id,
-                            certainty: result.certainty,
-                            is_normal: result.is_normal,
-                            x: result.x,
-                            y: result.y,
-                            width: result.width,
-                            height: result.height,
-                            iris_x: result.iris_x,
-                            iris_y: result.iris_y,
-                            iris_a: result.iris_a,
-                            iris_b: result.iris_b,
-                            coverage: result.coverage,
-                            created_at: result.created_at,
-                            updated_at: result.updated_at,
-                        };
-
-                        if let Some(entry) = buffer.get_mut(&result.sample_id) {
-                            entry.results.push(result_entry);
-
-                            buffer
-                        } else {
-                            buffer.insert(
-                                result.sample_id,
-                                messages::samples::InferredListEntry {
-                                    id: sample.id,
-                                    label: sample.label,
-                                    pet_id: sample.pet_id,
-                                    created_at: sample.created_at,
-                                    updated_at: sample.updated_at,
-                                    results: vec![result_entry],
-                                },
-                            );
-
-                            buffer
-                        }
-                    },
-                );
-
-                let mut items: Vec<messages::samples::InferredListEntry> =
-                    map.into_values().collect();
-
-                items.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-
-                messages::samples::InferredListResult::Success {
-                    items,
-                    has_next: false,
-                }
-            }
-            _ => messages::samples::InferredListResult::Failed,
+            return Err(pets::sample_or_pet_not_found());
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) async fn create_pet(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::pets::CreatePet,
+    ) -> Result<messages::pets::CreatePetResult, Error> {
+        desc.validate()?;
+
+        let id = self
+            .pets
+            .insert_pet(pets::PetInsert {
+                name: desc.name,
+                birthday: desc.birthday,
+                exact_birthday: desc.exact_birthday,
+                owner_id: user_id,
+            })
+            .await?;
+
+        Ok(messages::pets::CreatePetResult { id })
+    }
+
+    #[inline]
+    pub(crate) async fn list_pets(
+        &self,
+        user_id: uuid::Uuid,
+    ) -> Result<messages::pets::PetListResult, Error> {
+        Ok(messages::pets::PetListResult {
+            items: self.pets.pets(user_id).await?,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn search(
+        &self,
+        user_id: uuid::Uuid,
+        desc: messages::search::Search,
+    ) -> Result<messages::search::SearchResult, Error> {
+        desc.validate()?;
+
+        let limit = desc
+            .limit
+            .unwrap_or(repository::PAGE_SIZE)
+            .clamp(1, repository::PAGE_SIZE_MAX);
+
+        Ok(messages::search::SearchResult {
+            items: self.search.search(user_id, desc.q, limit).await?,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn find_user(&self, login_name: String) -> Result<uuid::Uuid, Error> {
+        self.users
+            .user_id(login_name)
+            .await?
+            .ok_or_else(users::user_not_found)
+    }
+
+    /// Like `register`, but no verification mail is sent.
+    #[inline]
+    pub(crate) async fn create_user(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        desc: messages::users::RegisterUser,
+        is_admin: bool,
+    ) -> Result<uuid::Uuid, Error> {
+        desc.validate()?;
+
+        self.users
+            .insert_user(users::UserInsert {
+                login_name: desc.login_name,
+                first_name: desc.first_name,
+                last_name: desc.last_name,
+                argon2: hasher.hash(&desc.password)?,
+                email: desc.email,
+                is_admin,
+            })
+            .await
+    }
+
+    #[inline]
+    pub(crate) async fn set_admin(&self, login_name: String, is_admin: bool) -> Result<(), Error> {
+        let user_id = self.find_user(login_name).await?;
+
+        self.users.set_admin(user_id, is_admin).await
+    }
+
+    /// Sets a new password without knowing the current one and signs the user
+    /// out everywhere.
+    #[inline]
+    pub(crate) async fn set_password(
+        &self,
+        hasher: Arc<PasswordHasher<'static>>,
+        login_name: String,
+        password: String,
+    ) -> Result<(), Error> {
+        if password.is_empty() {
+            return Err(Error::invalid_field(
+                "password_required",
+                "password",
+                "Required",
+            ));
         }
+
+        let user_id = self.find_user(login_name).await?;
+
+        self.users
+            .update_password(user_id, None, hasher.hash(&password)?)
+            .await?;
+
+        self.sessions.delete_sessions(user_id, None).await
+    }
+
+    #[inline]
+    pub(crate) async fn revoke_sessions(&self, login_name: String) -> Result<(), Error> {
+        let user_id = self.find_user(login_name).await?;
+
+        self.sessions.delete_sessions(user_id, None).await
+    }
+
+    #[inline]
+    pub(crate) async fn purge_deleted_samples(&self) -> Result<usize, Error> {
+        self.results.delete_deleted_sample_results().await?;
+        self.samples.purge_deleted_samples().await
+    }
+
+    #[inline]
+    pub(crate) async fn select_samples(
+        &self,
+        selection: SampleSelection,
+    ) -> Result<Vec<uuid::Uuid>, Error> {
+        self.samples.select_samples(selection).await
+    }
+
+    /// Replaces the results of a sample with a fresh detection, returning the
+    /// number of boxes found. Results are kept if nothing is detected.
+    #[inline]
+    pub(crate) async fn reinfer_sample(
+        &self,
+        sample_id: uuid::Uuid,
+        detector: &dyn crate::Detector,
+    ) -> Result<usize, Error> {
+        let bytes = self
+            .samples
+            .sample_bytes(sample_id, None)
+            .await?
+            .ok_or_else(samples::sample_not_found)?;
+
+        let results = Self::detect(sample_id, bytes, detector).await?;
+        let count = results.len();
+
+        if count > 0 {
+            self.results.replace_results(sample_id, results).await?;
+        }
+
+        Ok(count)
+    }
+
+    #[inline]
+    pub(crate) async fn stats(&self) -> Result<Stats, Error> {
+        let (users, admins) = self.users.count_users().await?;
+
+        Ok(Stats {
+            users,
+            admins,
+            sessions: self.sessions.count_sessions().await?,
+            samples: self.samples.count_samples().await?,
+            results: self.results.count_results().await?,
+        })
+    }
+
+    #[inline]
+    pub(crate) async fn ping(&self) -> Result<Option<PoolState>, Error> {
+        self.health.ping().await
+    }
+
+    #[inline]
+    pub(crate) fn pool_state(&self) -> Option<PoolState> {
+        self.health.pool_state()
     }
 }
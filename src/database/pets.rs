@@ -0,0 +1,21 @@
+use chrono::NaiveDateTime;
+use diesel::Insertable;
+
+use crate::error::Error;
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = crate::schema::pets)]
+pub(crate) struct PetInsert {
+    pub(crate) name: String,
+    pub(crate) birthday: Option<NaiveDateTime>,
+    pub(crate) exact_birthday: bool,
+    pub(crate) owner_id: uuid::Uuid,
+}
+
+#[inline]
+pub(crate) fn sample_or_pet_not_found() -> Error {
+    Error::NotFound {
+        code: "sample_or_pet_not_found",
+        message: "Sample or pet not found".into(),
+    }
+}
@@ -0,0 +1,1607 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use diesel::dsl::{exists, not, sql};
+use diesel::pg::Pg;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::{
+    BelongingToDsl, BoolExpressionMethods, Connection, ExpressionMethods,
+    NullableExpressionMethods, OptionalExtension, PgConnection, PgTextExpressionMethods, QueryDsl,
+    RunQueryDsl, SelectableHelper,
+};
+
+use super::migrations::{self, MigrationMode};
+use super::pets::PetInsert;
+use super::repository::{
+    HealthRepository, OidcLoginInsert, PageRequest, PetRepository, PoolState, ResultRepository,
+    SampleCounts, SampleFilter, SampleRepository, SampleSelection, SearchRepository,
+    SessionRepository, SessionUser, SortKey, TotpState, UserRepository,
+};
+use super::samples::{self, ResultInsert, ReviewStatus, SampleInsert};
+use super::search::{SearchRow, SEARCH_QUERY};
+use super::users::{self, ApiTokenInsert, OidcUserInsert, Scope, UserInsert, UserUpdate};
+use super::PoolConfig;
+use crate::detector::Classification;
+use crate::error::Error;
+use crate::messages::pets::PetEntry;
+use crate::messages::samples::{
+    InferredListEntry, InferredResultListEntry, PendingListEntry, SampleSort, SortOrder,
+};
+use crate::messages::search::{SearchHit, SearchKind};
+use crate::messages::users::{ApiTokenEntry, Profile};
+use crate::password_hasher::PasswordHash;
+
+pub(crate) struct PgRepository {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PgRepository {
+    pub(crate) fn new(url: &str, config: &PoolConfig, mode: MigrationMode) -> Self {
+        let manager = ConnectionManager::new(url);
+        let pool = Pool::builder()
+            .max_size(config.max_size)
+            .min_idle(config.min_idle)
+            .connection_timeout(config.connection_timeout)
+            .idle_timeout(config.idle_timeout)
+            .max_lifetime(config.max_lifetime)
+            .test_on_check_out(config.test_on_check_out)
+            .build(manager)
+            .expect("Could not build connection pool");
+
+        let mut connection = pool.get().expect("Could not connect to the database");
+        migrations::prepare(&mut connection, mode)
+            .unwrap_or_else(|e| panic!("Database migrations failed: {e}"));
+        drop(connection);
+
+        Self { pool }
+    }
+
+    /// Runs synchronous Diesel work on the blocking thread pool so a slow query
+    /// or a wait for a free connection never stalls the async workers.
+    async fn run<T, F>(&self, task: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut PgConnection) -> Result<T, Error> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        // Keeps the request span, and with it the request id, on the blocking thread.
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || span.in_scope(|| task(&mut *pool.get()?)))
+            .await
+            .map_err(Error::internal)?
+    }
+
+    #[inline]
+    fn keyword_pattern(keyword: Option<String>) -> String {
+        match keyword {
+            Some(keyword) => format!("%{keyword}%"),
+            None => "%".to_string(),
+        }
+    }
+
+    /// Samples matching everything in `filter` but its result conditions.
+    fn sample_query(filter: &SampleFilter) -> crate::schema::samples::BoxedQuery<'static, Pg> {
+        use crate::schema::samples;
+
+        let mut query = samples::table
+            .filter(
+                samples::deleted
+                    .eq(false)
+                    .and(samples::owner_id.eq(filter.owner_id))
+                    .and(samples::label.ilike(Self::keyword_pattern(filter.keyword.clone()))),
+            )
+            .into_boxed();
+
+        if let Some(pet_id) = filter.pet_id {
+            query = query.filter(samples::pet_id.eq(pet_id));
+        }
+
+        if let Some(created_from) = filter.created_from {
+            query = query.filter(samples::created_at.ge(created_from));
+        }
+
+        if let Some(created_until) = filter.created_until {
+            query = query.filter(samples::created_at.lt(created_until));
+        }
+
+        if let Some(review_status) = filter.review_status {
+            query = query.filter(samples::review_status.eq(review_status.as_str()));
+        }
+
+        query
+    }
+
+    fn pending_query(filter: &SampleFilter) -> crate::schema::samples::BoxedQuery<'static, Pg> {
+        use crate::schema::{results, samples};
+
+        Self::sample_query(filter).filter(not(exists(
+            results::table.filter(results::sample_id.eq(samples::id)),
+        )))
+    }
+
+    /// Samples with at least one result matching the result conditions of `filter`.
+    fn inferred_query(filter: &SampleFilter) -> crate::schema::samples::BoxedQuery<'static, Pg> {
+        use crate::schema::{results, samples};
+
+        let mut matching = results::table.select(results::sample_id).into_boxed();
+
+        if let Some(classification) = filter.classification {
+            matching =
+                matching.filter(results::is_normal.eq(classification == Classification::Normal));
+        }
+
+        if let Some(min_certainty) = filter.min_certainty {
+            matching = matching.filter(results::certainty.ge(min_certainty));
+        }
+
+        if let Some(model_version) = filter.model_version.clone() {
+            matching = matching.filter(results::model_version.eq(model_version));
+        }
+
+        Self::sample_query(filter).filter(samples::id.eq_any(matching))
+    }
+
+    /// Narrows a sample query to one page. Seeking past the cursor with a
+    /// row comparison instead of skipping rows keeps pages stable while
+    /// samples are added and stays fast deep into the list.
+    fn page(
+        query: crate::schema::samples::BoxedQuery<'static, Pg>,
+        page: PageRequest,
+    ) -> crate::schema::samples::BoxedQuery<'static, Pg> {
+        use diesel::sql_types::{Bool, Float4, Text, Timestamp, Untyped};
+
+        let key = match page.sort {
+            SampleSort::Date => "samples.created_at",
+            SampleSort::Certainty => {
+                "(SELECT max(results.certainty) FROM results WHERE results.sample_id = samples.id)"
+            }
+            // Byte order, as the memory backend sorts, not the database locale.
+            SampleSort::Label => "samples.label COLLATE \"C\"",
+        };
+        let (direction, comparison) = match page.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+
+        let query = query
+            .order(sql::<Untyped>(&format!(
+                "{key} {direction}, samples.id {direction}"
+            )))
+            .limit(page.limit as i64);
+
+        let Some(after) = page.after else {
+            return query;
+        };
+
+        let seek = sql::<Bool>(&format!("({key}, samples.id) {comparison} ("));
+        match after.key {
+            SortKey::Date(created_at) => query.filter(
+                seek.bind::<Timestamp, _>(created_at)
+                    .sql(", ")
+                    .bind::<diesel::sql_types::Uuid, _>(after.id)
+                    .sql(")"),
+            ),
+            SortKey::Certainty(certainty) => query.filter(
+                seek.bind::<Float4, _>(certainty)
+                    .sql(", ")
+                    .bind::<diesel::sql_types::Uuid, _>(after.id)
+                    .sql(")"),
+            ),
+            SortKey::Label(label) => query.filter(
+                seek.bind::<Text, _>(label)
+                    .sql(", ")
+                    .bind::<diesel::sql_types::Uuid, _>(after.id)
+                    .sql(")"),
+            ),
+        }
+    }
+
+    #[inline]
+    fn is_unique_violation(error: &diesel::result::Error) -> bool {
+        matches!(
+            error,
+            diesel::result::Error::DatabaseError(
+                diesel::result::DatabaseErrorKind::UniqueViolation,
+                _,
+            )
+        )
+    }
+}
+
+#[async_trait]
+impl UserRepository for PgRepository {
+    async fn insert_user(&self, record: UserInsert) -> Result<uuid::Uuid, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            match diesel::insert_into(users::table)
+                .values(&record)
+                .returning(users::id)
+                .get_result::<uuid::Uuid>(connection)
+            {
+                Ok(id) => Ok(id),
+                Err(diesel::result::Error::DatabaseError(
+                    diesel::result::DatabaseErrorKind::UniqueViolation,
+                    info,
+                )) if info.constraint_name() == Some(self::users::EMAIL_UNIQUE_CONSTRAINT) => {
+                    Err(self::users::email_taken())
+                }
+                Err(e) if Self::is_unique_violation(&e) => Err(self::users::login_name_taken()),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    async fn profile(&self, user_id: uuid::Uuid) -> Result<Option<Profile>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::id.eq(user_id))
+                .select((
+                    users::id,
+                    users::login_name,
+                    users::first_name,
+                    users::last_name,
+                    users::email,
+                    users::email_verified_at.is_not_null(),
+                ))
+                .first::<(uuid::Uuid, String, String, String, Option<String>, bool)>(connection)
+                .optional()?
+                .map(
+                    |(id, login_name, first_name, last_name, email, email_verified)| Profile {
+                        id,
+                        login_name,
+                        first_name,
+                        last_name,
+                        email,
+                        email_verified,
+                    },
+                ))
+        })
+        .await
+    }
+
+    async fn update_user(&self, user_id: uuid::Uuid, changes: UserUpdate) -> Result<(), Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            match diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set(&changes)
+                .execute(connection)
+            {
+                Ok(_) => Ok(()),
+                Err(e) if Self::is_unique_violation(&e) => Err(self::users::email_taken()),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    async fn delete_user(&self, user_id: uuid::Uuid) -> Result<(), Error> {
+        use crate::schema::{
+            api_tokens, email_verifications, login_challenges, password_resets, pets,
+            recovery_codes, results, samples, session, user_identities, users,
+        };
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                let owned_samples = samples::table
+                    .filter(samples::owner_id.eq(user_id))
+                    .select(samples::id);
+                let owned_pets = pets::table
+                    .filter(pets::owner_id.eq(user_id))
+                    .select(pets::id.nullable());
+
+                diesel::delete(results::table.filter(results::sample_id.eq_any(owned_samples)))
+                    .execute(connection)?;
+                diesel::delete(samples::table.filter(samples::owner_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::update(samples::table.filter(samples::pet_id.eq_any(owned_pets)))
+                    .set(samples::pet_id.eq(None::<uuid::Uuid>))
+                    .execute(connection)?;
+                diesel::delete(pets::table.filter(pets::owner_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(session::table.filter(session::user_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(api_tokens::table.filter(api_tokens::user_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(password_resets::table.filter(password_resets::user_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(
+                    email_verifications::table.filter(email_verifications::user_id.eq(user_id)),
+                )
+                .execute(connection)?;
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(
+                    login_challenges::table.filter(login_challenges::user_id.eq(user_id)),
+                )
+                .execute(connection)?;
+                diesel::delete(user_identities::table.filter(user_identities::user_id.eq(user_id)))
+                    .execute(connection)?;
+                diesel::delete(users::table.filter(users::id.eq(user_id))).execute(connection)?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn user_by_credentials(
+        &self,
+        login_name: String,
+        hash: PasswordHash,
+    ) -> Result<Option<(uuid::Uuid, bool)>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::login_name.eq(login_name).and(users::argon2.eq(hash)))
+                .select((users::id, users::totp_enabled))
+                .first::<(uuid::Uuid, bool)>(connection)
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn password_matches(
+        &self,
+        user_id: uuid::Uuid,
+        hash: PasswordHash,
+    ) -> Result<bool, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::id.eq(user_id).and(users::argon2.eq(hash)))
+                .count()
+                .get_result::<i64>(connection)?
+                > 0)
+        })
+        .await
+    }
+
+    async fn update_password(
+        &self,
+        user_id: uuid::Uuid,
+        current: Option<PasswordHash>,
+        replacement: PasswordHash,
+    ) -> Result<bool, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            let updated = match current {
+                Some(current) => diesel::update(
+                    users::table.filter(users::id.eq(user_id).and(users::argon2.eq(current))),
+                )
+                .set(users::argon2.eq(replacement))
+                .execute(connection)?,
+                None => diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set(users::argon2.eq(replacement))
+                    .execute(connection)?,
+            };
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn insert_email_verification(
+        &self,
+        user_id: uuid::Uuid,
+        email: String,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error> {
+        use crate::schema::email_verifications;
+
+        self.run(move |connection| {
+            diesel::insert_into(email_verifications::table)
+                .values((
+                    email_verifications::token_hash.eq(token_hash),
+                    email_verifications::email.eq(email),
+                    email_verifications::user_id.eq(user_id),
+                    email_verifications::expires_at.eq(expires_at),
+                ))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn consume_email_verification(&self, token_hash: Vec<u8>) -> Result<bool, Error> {
+        use crate::schema::{email_verifications, users};
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                let Some((user_id, email)) = diesel::update(
+                    email_verifications::table.filter(
+                        email_verifications::token_hash
+                            .eq(token_hash)
+                            .and(email_verifications::used_at.is_null())
+                            .and(email_verifications::expires_at.gt(diesel::dsl::now)),
+                    ),
+                )
+                .set(email_verifications::used_at.eq(diesel::dsl::now))
+                .returning((email_verifications::user_id, email_verifications::email))
+                .get_result::<(uuid::Uuid, String)>(connection)
+                .optional()?
+                else {
+                    return Ok(false);
+                };
+
+                // The address may have changed again since the token was issued.
+                let updated = diesel::update(
+                    users::table.filter(users::id.eq(user_id).and(users::email.eq(email))),
+                )
+                .set(users::email_verified_at.eq(diesel::dsl::now))
+                .execute(connection)?;
+
+                Ok(updated > 0)
+            })
+        })
+        .await
+    }
+
+    async fn unverified_email(&self, user_id: uuid::Uuid) -> Result<Option<String>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(
+                    users::id
+                        .eq(user_id)
+                        .and(users::email_verified_at.is_null()),
+                )
+                .select(users::email)
+                .first::<Option<String>>(connection)
+                .optional()?
+                .flatten())
+        })
+        .await
+    }
+
+    async fn verified_email(
+        &self,
+        login_name: String,
+    ) -> Result<Option<(uuid::Uuid, String)>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(
+                    users::login_name
+                        .eq(login_name)
+                        .and(users::email_verified_at.is_not_null()),
+                )
+                .select((users::id, users::email))
+                .first::<(uuid::Uuid, Option<String>)>(connection)
+                .optional()?
+                .and_then(|(id, email)| Some((id, email?))))
+        })
+        .await
+    }
+
+    async fn insert_password_reset(
+        &self,
+        user_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error> {
+        use crate::schema::password_resets;
+
+        self.run(move |connection| {
+            diesel::insert_into(password_resets::table)
+                .values((
+                    password_resets::token_hash.eq(token_hash),
+                    password_resets::user_id.eq(user_id),
+                    password_resets::expires_at.eq(expires_at),
+                ))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        use crate::schema::password_resets;
+
+        self.run(move |connection| {
+            Ok(diesel::update(
+                password_resets::table.filter(
+                    password_resets::token_hash
+                        .eq(token_hash)
+                        .and(password_resets::used_at.is_null())
+                        .and(password_resets::expires_at.gt(diesel::dsl::now)),
+                ),
+            )
+            .set(password_resets::used_at.eq(diesel::dsl::now))
+            .returning(password_resets::user_id)
+            .get_result::<uuid::Uuid>(connection)
+            .optional()?)
+        })
+        .await
+    }
+
+    async fn totp(&self, user_id: uuid::Uuid) -> Result<Option<TotpState>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::id.eq(user_id))
+                .select((
+                    users::totp_secret,
+                    users::totp_enabled,
+                    users::totp_last_step,
+                ))
+                .first::<(Option<Vec<u8>>, bool, Option<i64>)>(connection)
+                .optional()?
+                .map(|(secret, enabled, last_step)| TotpState {
+                    secret,
+                    enabled,
+                    last_step,
+                }))
+        })
+        .await
+    }
+
+    async fn begin_totp(&self, user_id: uuid::Uuid, secret: Vec<u8>) -> Result<bool, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                users::table.filter(users::id.eq(user_id).and(users::totp_enabled.eq(false))),
+            )
+            .set((
+                users::totp_secret.eq(secret),
+                users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn enable_totp(
+        &self,
+        user_id: uuid::Uuid,
+        step: i64,
+        recovery_code_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), Error> {
+        use crate::schema::{recovery_codes, users};
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set((users::totp_enabled.eq(true), users::totp_last_step.eq(step)))
+                    .execute(connection)?;
+
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                    .execute(connection)?;
+
+                diesel::insert_into(recovery_codes::table)
+                    .values(
+                        recovery_code_hashes
+                            .into_iter()
+                            .map(|code_hash| {
+                                (
+                                    recovery_codes::code_hash.eq(code_hash),
+                                    recovery_codes::user_id.eq(user_id),
+                                )
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                    .execute(connection)?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn disable_totp(&self, user_id: uuid::Uuid) -> Result<(), Error> {
+        use crate::schema::{login_challenges, recovery_codes, users};
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                diesel::update(users::table.filter(users::id.eq(user_id)))
+                    .set((
+                        users::totp_enabled.eq(false),
+                        users::totp_secret.eq(None::<Vec<u8>>),
+                        users::totp_last_step.eq(None::<i64>),
+                    ))
+                    .execute(connection)?;
+
+                diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id)))
+                    .execute(connection)?;
+
+                diesel::delete(
+                    login_challenges::table.filter(login_challenges::user_id.eq(user_id)),
+                )
+                .execute(connection)?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn set_totp_step(&self, user_id: uuid::Uuid, step: i64) -> Result<(), Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set(users::totp_last_step.eq(step))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn use_recovery_code(
+        &self,
+        user_id: uuid::Uuid,
+        code_hash: Vec<u8>,
+    ) -> Result<bool, Error> {
+        use crate::schema::recovery_codes;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                recovery_codes::table.filter(
+                    recovery_codes::user_id
+                        .eq(user_id)
+                        .and(recovery_codes::code_hash.eq(code_hash))
+                        .and(recovery_codes::used_at.is_null()),
+                ),
+            )
+            .set(recovery_codes::used_at.eq(diesel::dsl::now))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn identity_user(
+        &self,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        use crate::schema::user_identities;
+
+        self.run(move |connection| {
+            Ok(user_identities::table
+                .filter(
+                    user_identities::issuer
+                        .eq(issuer)
+                        .and(user_identities::subject.eq(subject)),
+                )
+                .select(user_identities::user_id)
+                .first::<uuid::Uuid>(connection)
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn login_name_exists(&self, login_name: String) -> Result<bool, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::login_name.eq(login_name))
+                .count()
+                .get_result::<i64>(connection)?
+                > 0)
+        })
+        .await
+    }
+
+    async fn email_exists(&self, email: String) -> Result<bool, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::email.eq(email))
+                .count()
+                .get_result::<i64>(connection)?
+                > 0)
+        })
+        .await
+    }
+
+    async fn insert_oidc_user(
+        &self,
+        record: OidcUserInsert,
+        issuer: String,
+        subject: String,
+    ) -> Result<uuid::Uuid, Error> {
+        use crate::schema::{user_identities, users};
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                let user_id = diesel::insert_into(users::table)
+                    .values(&record)
+                    .returning(users::id)
+                    .get_result::<uuid::Uuid>(connection)?;
+
+                diesel::insert_into(user_identities::table)
+                    .values((
+                        user_identities::issuer.eq(issuer),
+                        user_identities::subject.eq(subject),
+                        user_identities::user_id.eq(user_id),
+                    ))
+                    .execute(connection)?;
+
+                Ok(user_id)
+            })
+        })
+        .await
+    }
+
+    async fn user_id(&self, login_name: String) -> Result<Option<uuid::Uuid>, Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            Ok(users::table
+                .filter(users::login_name.eq(login_name))
+                .select(users::id)
+                .first::<uuid::Uuid>(connection)
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set_admin(&self, user_id: uuid::Uuid, is_admin: bool) -> Result<(), Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            diesel::update(users::table.filter(users::id.eq(user_id)))
+                .set((
+                    users::is_admin.eq(is_admin),
+                    users::updated_at.eq(diesel::dsl::now.nullable()),
+                ))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn count_users(&self) -> Result<(i64, i64), Error> {
+        use crate::schema::users;
+
+        self.run(move |connection| {
+            let total = users::table.count().get_result::<i64>(connection)?;
+            let admins = users::table
+                .filter(users::is_admin.eq(true))
+                .count()
+                .get_result::<i64>(connection)?;
+
+            Ok((total, admins))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SessionRepository for PgRepository {
+    async fn create_session(&self, user_id: uuid::Uuid) -> Result<(uuid::Uuid, Vec<u8>), Error> {
+        use crate::schema::session;
+
+        self.run(move |connection| {
+            Ok(diesel::insert_into(session::table)
+                .values(session::user_id.eq(user_id))
+                .returning((session::id, session::access_token))
+                .get_result::<(uuid::Uuid, Vec<u8>)>(connection)?)
+        })
+        .await
+    }
+
+    async fn session_user(
+        &self,
+        session_id: uuid::Uuid,
+        access_token: Vec<u8>,
+    ) -> Result<Option<SessionUser>, Error> {
+        use crate::schema::{session, users};
+
+        self.run(move |connection| {
+            Ok(users::table
+                .inner_join(session::table)
+                .filter(
+                    session::id
+                        .eq(session_id)
+                        .and(session::access_token.eq(access_token)),
+                )
+                .select((
+                    users::id,
+                    users::login_name,
+                    users::first_name,
+                    users::last_name,
+                ))
+                .get_result::<(uuid::Uuid, String, String, String)>(connection)
+                .optional()?
+                .map(|(user_id, login_name, first_name, last_name)| SessionUser {
+                    user_id,
+                    login_name,
+                    first_name,
+                    last_name,
+                }))
+        })
+        .await
+    }
+
+    async fn delete_sessions(
+        &self,
+        user_id: uuid::Uuid,
+        keep: Option<uuid::Uuid>,
+    ) -> Result<(), Error> {
+        use crate::schema::session;
+
+        self.run(move |connection| {
+            match keep {
+                Some(keep) => diesel::delete(
+                    session::table.filter(session::user_id.eq(user_id).and(session::id.ne(keep))),
+                )
+                .execute(connection)?,
+                None => diesel::delete(session::table.filter(session::user_id.eq(user_id)))
+                    .execute(connection)?,
+            };
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn create_login_challenge(
+        &self,
+        user_id: uuid::Uuid,
+        expires_at: NaiveDateTime,
+    ) -> Result<uuid::Uuid, Error> {
+        use crate::schema::login_challenges;
+
+        self.run(move |connection| {
+            Ok(diesel::insert_into(login_challenges::table)
+                .values((
+                    login_challenges::user_id.eq(user_id),
+                    login_challenges::expires_at.eq(expires_at),
+                ))
+                .returning(login_challenges::id)
+                .get_result::<uuid::Uuid>(connection)?)
+        })
+        .await
+    }
+
+    async fn attempt_login_challenge(
+        &self,
+        challenge_id: uuid::Uuid,
+        max_attempts: i32,
+    ) -> Result<Option<uuid::Uuid>, Error> {
+        use crate::schema::login_challenges;
+
+        self.run(move |connection| {
+            Ok(diesel::update(
+                login_challenges::table.filter(
+                    login_challenges::id
+                        .eq(challenge_id)
+                        .and(login_challenges::expires_at.gt(diesel::dsl::now))
+                        .and(login_challenges::attempts.lt(max_attempts)),
+                ),
+            )
+            .set(login_challenges::attempts.eq(login_challenges::attempts + 1))
+            .returning(login_challenges::user_id)
+            .get_result::<uuid::Uuid>(connection)
+            .optional()?)
+        })
+        .await
+    }
+
+    async fn login_challenge_login_name(
+        &self,
+        challenge_id: uuid::Uuid,
+    ) -> Result<Option<String>, Error> {
+        use crate::schema::{login_challenges, users};
+
+        self.run(move |connection| {
+            Ok(login_challenges::table
+                .inner_join(users::table)
+                .filter(
+                    login_challenges::id
+                        .eq(challenge_id)
+                        .and(login_challenges::expires_at.gt(diesel::dsl::now)),
+                )
+                .select(users::login_name)
+                .first::<String>(connection)
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn delete_login_challenge(&self, challenge_id: uuid::Uuid) -> Result<(), Error> {
+        use crate::schema::login_challenges;
+
+        self.run(move |connection| {
+            diesel::delete(login_challenges::table.filter(login_challenges::id.eq(challenge_id)))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn insert_api_token(&self, record: ApiTokenInsert) -> Result<uuid::Uuid, Error> {
+        use crate::schema::api_tokens;
+
+        self.run(move |connection| {
+            Ok(diesel::insert_into(api_tokens::table)
+                .values(&record)
+                .returning(api_tokens::id)
+                .get_result::<uuid::Uuid>(connection)?)
+        })
+        .await
+    }
+
+    async fn api_token_user(
+        &self,
+        token_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<(SessionUser, Vec<Scope>)>, Error> {
+        use crate::schema::{api_tokens, users};
+
+        self.run(move |connection| {
+            let Some((user_id, scopes)) = diesel::update(
+                api_tokens::table.filter(
+                    api_tokens::id
+                        .eq(token_id)
+                        .and(api_tokens::token_hash.eq(token_hash))
+                        .and(api_tokens::revoked.eq(false)),
+                ),
+            )
+            .set(api_tokens::last_used_at.eq(diesel::dsl::now))
+            .returning((api_tokens::user_id, api_tokens::scopes))
+            .get_result::<(uuid::Uuid, Vec<Option<String>>)>(connection)
+            .optional()?
+            else {
+                return Ok(None);
+            };
+
+            Ok(users::table
+                .filter(users::id.eq(user_id))
+                .select((users::login_name, users::first_name, users::last_name))
+                .get_result::<(String, String, String)>(connection)
+                .optional()?
+                .map(|(login_name, first_name, last_name)| {
+                    (
+                        SessionUser {
+                            user_id,
+                            login_name,
+                            first_name,
+                            last_name,
+                        },
+                        Scope::parse_all(&scopes),
+                    )
+                }))
+        })
+        .await
+    }
+
+    async fn api_tokens(&self, user_id: uuid::Uuid) -> Result<Vec<ApiTokenEntry>, Error> {
+        use crate::schema::api_tokens;
+
+        self.run(move |connection| {
+            let rows = api_tokens::table
+                .filter(
+                    api_tokens::user_id
+                        .eq(user_id)
+                        .and(api_tokens::revoked.eq(false)),
+                )
+                .select((
+                    api_tokens::id,
+                    api_tokens::name,
+                    api_tokens::scopes,
+                    api_tokens::last_used_at,
+                    api_tokens::created_at,
+                ))
+                .order(api_tokens::created_at.desc())
+                .get_results::<(
+                    uuid::Uuid,
+                    String,
+                    Vec<Option<String>>,
+                    Option<NaiveDateTime>,
+                    NaiveDateTime,
+                )>(connection)?;
+
+            Ok(rows
+                .into_iter()
+                .map(
+                    |(id, name, scopes, last_used_at, created_at)| ApiTokenEntry {
+                        id,
+                        name,
+                        scopes: Scope::parse_all(&scopes),
+                        last_used_at,
+                        created_at,
+                    },
+                )
+                .collect())
+        })
+        .await
+    }
+
+    async fn revoke_api_token(
+        &self,
+        user_id: uuid::Uuid,
+        token_id: uuid::Uuid,
+    ) -> Result<bool, Error> {
+        use crate::schema::api_tokens;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                api_tokens::table.filter(
+                    api_tokens::id
+                        .eq(token_id)
+                        .and(api_tokens::user_id.eq(user_id))
+                        .and(api_tokens::revoked.eq(false)),
+                ),
+            )
+            .set(api_tokens::revoked.eq(true))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn insert_oidc_login(&self, record: OidcLoginInsert) -> Result<(), Error> {
+        use crate::schema::oidc_logins;
+
+        self.run(move |connection| {
+            diesel::delete(oidc_logins::table.filter(oidc_logins::expires_at.lt(diesel::dsl::now)))
+                .execute(connection)?;
+
+            diesel::insert_into(oidc_logins::table)
+                .values((
+                    oidc_logins::state.eq(record.state),
+                    oidc_logins::pkce_verifier.eq(record.pkce_verifier),
+                    oidc_logins::nonce.eq(record.nonce),
+                    oidc_logins::expires_at.eq(record.expires_at),
+                ))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn take_oidc_login(&self, state: String) -> Result<Option<(String, String)>, Error> {
+        use crate::schema::oidc_logins;
+
+        self.run(move |connection| {
+            Ok(diesel::delete(
+                oidc_logins::table.filter(
+                    oidc_logins::state
+                        .eq(state)
+                        .and(oidc_logins::expires_at.gt(diesel::dsl::now)),
+                ),
+            )
+            .returning((oidc_logins::pkce_verifier, oidc_logins::nonce))
+            .get_result::<(String, String)>(connection)
+            .optional()?)
+        })
+        .await
+    }
+
+    async fn count_sessions(&self) -> Result<i64, Error> {
+        use crate::schema::session;
+
+        self.run(move |connection| Ok(session::table.count().get_result::<i64>(connection)?))
+            .await
+    }
+}
+
+#[async_trait]
+impl SampleRepository for PgRepository {
+    async fn insert_samples(&self, samples: Vec<SampleInsert>) -> Result<(), Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            diesel::insert_into(samples::table)
+                .values(samples)
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn sample_bytes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: Option<uuid::Uuid>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            let mut query = samples::table
+                .filter(samples::id.eq(sample_id).and(samples::deleted.eq(false)))
+                .select(samples::bytes)
+                .into_boxed();
+
+            if let Some(owner_id) = owner_id {
+                query = query.filter(samples::owner_id.eq(owner_id));
+            }
+
+            Ok(query.first::<Vec<u8>>(connection).optional()?)
+        })
+        .await
+    }
+
+    async fn delete_sample(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+    ) -> Result<bool, Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                samples::table.filter(
+                    samples::id
+                        .eq(sample_id)
+                        .and(samples::owner_id.eq(owner_id))
+                        .and(samples::deleted.eq(false)),
+                ),
+            )
+            .set(samples::deleted.eq(true))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn set_review_status(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        status: ReviewStatus,
+    ) -> Result<bool, Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                samples::table.filter(
+                    samples::id
+                        .eq(sample_id)
+                        .and(samples::owner_id.eq(owner_id))
+                        .and(samples::deleted.eq(false)),
+                ),
+            )
+            .set(samples::review_status.eq(status.as_str()))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn set_notes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        notes: Option<String>,
+    ) -> Result<bool, Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            let updated = diesel::update(
+                samples::table.filter(
+                    samples::id
+                        .eq(sample_id)
+                        .and(samples::owner_id.eq(owner_id))
+                        .and(samples::deleted.eq(false)),
+                ),
+            )
+            .set(samples::notes.eq(notes))
+            .execute(connection)?;
+
+            Ok(updated > 0)
+        })
+        .await
+    }
+
+    async fn pending_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<PendingListEntry>, Error> {
+        self.run(move |connection| {
+            let items = Self::page(Self::pending_query(&filter), page)
+                .select(self::samples::SampleEntry::as_select())
+                .get_results::<self::samples::SampleEntry>(connection)?;
+
+            Ok(items
+                .into_iter()
+                .map(|entry| PendingListEntry {
+                    id: entry.id,
+                    label: entry.label,
+                    pet_id: entry.pet_id,
+                    created_at: entry.created_at,
+                    review_status: ReviewStatus::parse(&entry.review_status),
+                    notes: entry.notes,
+                })
+                .collect())
+        })
+        .await
+    }
+
+    async fn count_pending_samples(&self, filter: SampleFilter) -> Result<i64, Error> {
+        self.run(move |connection| {
+            Ok(Self::pending_query(&filter)
+                .count()
+                .get_result::<i64>(connection)?)
+        })
+        .await
+    }
+
+    async fn purge_deleted_samples(&self) -> Result<usize, Error> {
+        use crate::schema::samples;
+
+        self.run(move |connection| {
+            Ok(
+                diesel::delete(samples::table.filter(samples::deleted.eq(true)))
+                    .execute(connection)?,
+            )
+        })
+        .await
+    }
+
+    async fn select_samples(&self, selection: SampleSelection) -> Result<Vec<uuid::Uuid>, Error> {
+        use crate::schema::{results, samples};
+
+        self.run(move |connection| {
+            let mut query = samples::table
+                .filter(samples::deleted.eq(false))
+                .select(samples::id)
+                .order(samples::created_at.asc())
+                .into_boxed();
+
+            if !selection.sample_ids.is_empty() {
+                query = query.filter(samples::id.eq_any(selection.sample_ids));
+            }
+
+            if let Some(owner_id) = selection.owner_id {
+                query = query.filter(samples::owner_id.eq(owner_id));
+            }
+
+            if let Some(created_since) = selection.created_since {
+                query = query.filter(samples::created_at.ge(created_since));
+            }
+
+            if selection.pending_only {
+                query = query.filter(diesel::dsl::not(diesel::dsl::exists(
+                    results::table.filter(results::sample_id.eq(samples::id)),
+                )));
+            }
+
+            Ok(query.get_results::<uuid::Uuid>(connection)?)
+        })
+        .await
+    }
+
+    async fn count_samples(&self) -> Result<SampleCounts, Error> {
+        use crate::schema::{results, samples};
+
+        self.run(move |connection| {
+            let total = samples::table.count().get_result::<i64>(connection)?;
+            let deleted = samples::table
+                .filter(samples::deleted.eq(true))
+                .count()
+                .get_result::<i64>(connection)?;
+            let pending = samples::table
+                .filter(
+                    samples::deleted
+                        .eq(false)
+                        .and(diesel::dsl::not(diesel::dsl::exists(
+                            results::table.filter(results::sample_id.eq(samples::id)),
+                        ))),
+                )
+                .count()
+                .get_result::<i64>(connection)?;
+
+            Ok(SampleCounts {
+                total,
+                deleted,
+                pending,
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ResultRepository for PgRepository {
+    async fn insert_results(&self, results: Vec<ResultInsert>) -> Result<(), Error> {
+        use crate::schema::results;
+
+        self.run(move |connection| {
+            diesel::insert_into(results::table)
+                .values(results)
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn inferred_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<InferredListEntry>, Error> {
+        use crate::schema::results;
+
+        self.run(move |connection| {
+            let samples = Self::page(Self::inferred_query(&filter), page)
+                .select(self::samples::Sample::as_select())
+                .get_results::<self::samples::Sample>(connection)?;
+
+            let mut grouped = self::samples::Result::belonging_to(&samples)
+                .select(self::samples::Result::as_select())
+                .order(results::created_at.asc())
+                .get_results::<self::samples::Result>(connection)?
+                .into_iter()
+                .fold(
+                    HashMap::<uuid::Uuid, Vec<InferredResultListEntry>>::new(),
+                    |mut buffer, result| {
+                        buffer
+                            .entry(result.sample_id)
+                            .or_default()
+                            .push(InferredResultListEntry {
+                                id: result.id,
+                                certainty: result.certainty,
+                                is_normal: result.is_normal,
+                                x: result.x,
+                                y: result.y,
+                                width: result.width,
+                                height: result.height,
+                                iris_x: result.iris_x,
+                                iris_y: result.iris_y,
+                                iris_a: result.iris_a,
+                                iris_b: result.iris_b,
+                                coverage: result.coverage,
+                                model_version: result.model_version,
+                                created_at: result.created_at,
+                                updated_at: result.updated_at,
+                            });
+
+                        buffer
+                    },
+                );
+
+            Ok(samples
+                .into_iter()
+                .map(|sample| InferredListEntry {
+                    results: grouped.remove(&sample.id).unwrap_or_default(),
+                    id: sample.id,
+                    label: sample.label,
+                    pet_id: sample.pet_id,
+                    created_at: sample.created_at,
+                    updated_at: sample.updated_at,
+                    review_status: ReviewStatus::parse(&sample.review_status),
+                    notes: sample.notes,
+                })
+                .collect())
+        })
+        .await
+    }
+
+    async fn count_inferred_samples(&self, filter: SampleFilter) -> Result<i64, Error> {
+        self.run(move |connection| {
+            Ok(Self::inferred_query(&filter)
+                .count()
+                .get_result::<i64>(connection)?)
+        })
+        .await
+    }
+
+    async fn delete_deleted_sample_results(&self) -> Result<(), Error> {
+        use crate::schema::{results, samples};
+
+        self.run(move |connection| {
+            let deleted_samples = samples::table
+                .filter(samples::deleted.eq(true))
+                .select(samples::id);
+
+            diesel::delete(results::table.filter(results::sample_id.eq_any(deleted_samples)))
+                .execute(connection)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn replace_results(
+        &self,
+        sample_id: uuid::Uuid,
+        results: Vec<ResultInsert>,
+    ) -> Result<(), Error> {
+        use crate::schema::results;
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                diesel::delete(results::table.filter(results::sample_id.eq(sample_id)))
+                    .execute(connection)?;
+                diesel::insert_into(results::table)
+                    .values(results)
+                    .execute(connection)?;
+
+                Ok(())
+            })
+        })
+        .await
+    }
+
+    async fn count_results(&self) -> Result<i64, Error> {
+        use crate::schema::results;
+
+        self.run(move |connection| Ok(results::table.count().get_result::<i64>(connection)?))
+            .await
+    }
+}
+
+#[async_trait]
+impl PetRepository for PgRepository {
+    async fn insert_pet(&self, record: PetInsert) -> Result<uuid::Uuid, Error> {
+        use crate::schema::pets;
+
+        self.run(move |connection| {
+            Ok(diesel::insert_into(pets::table)
+                .values(record)
+                .returning(pets::id)
+                .get_result::<uuid::Uuid>(connection)?)
+        })
+        .await
+    }
+
+    async fn pets(&self, owner_id: uuid::Uuid) -> Result<Vec<PetEntry>, Error> {
+        use crate::schema::pets;
+
+        self.run(move |connection| {
+            let rows = pets::table
+                .filter(pets::owner_id.eq(owner_id))
+                .select((
+                    pets::id,
+                    pets::name,
+                    pets::birthday,
+                    pets::exact_birthday,
+                    pets::created_at,
+                ))
+                .order((pets::created_at, pets::id))
+                .get_results::<(
+                    uuid::Uuid,
+                    String,
+                    Option<NaiveDateTime>,
+                    bool,
+                    NaiveDateTime,
+                )>(connection)?;
+
+            Ok(rows
+                .into_iter()
+                .map(
+                    |(id, name, birthday, exact_birthday, created_at)| PetEntry {
+                        id,
+                        name,
+                        birthday,
+                        exact_birthday,
+                        created_at,
+                    },
+                )
+                .collect())
+        })
+        .await
+    }
+
+    async fn assign_pet(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        pet_id: Option<uuid::Uuid>,
+    ) -> Result<bool, Error> {
+        use crate::schema::{pets, samples};
+
+        self.run(move |connection| {
+            connection.transaction::<_, Error, _>(|connection| {
+                if let Some(pet_id) = pet_id {
+                    let owned = diesel::select(exists(
+                        pets::table.filter(pets::id.eq(pet_id).and(pets::owner_id.eq(owner_id))),
+                    ))
+                    .get_result::<bool>(connection)?;
+
+                    if !owned {
+                        return Ok(false);
+                    }
+                }
+
+                let updated = diesel::update(
+                    samples::table.filter(
+                        samples::id
+                            .eq(sample_id)
+                            .and(samples::owner_id.eq(owner_id))
+                            .and(samples::deleted.eq(false)),
+                    ),
+                )
+                .set(samples::pet_id.eq(pet_id))
+                .execute(connection)?;
+
+                Ok(updated > 0)
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SearchRepository for PgRepository {
+    async fn search(
+        &self,
+        owner_id: uuid::Uuid,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, Error> {
+        use diesel::sql_types::{BigInt, Text, Uuid};
+
+        self.run(move |connection| {
+            let rows = diesel::sql_query(SEARCH_QUERY)
+                .bind::<Uuid, _>(owner_id)
+                .bind::<Text, _>(query)
+                .bind::<BigInt, _>(limit as i64)
+                .load::<SearchRow>(connection)?;
+
+            Ok(rows
+                .into_iter()
+                .map(|row| SearchHit {
+                    kind: SearchKind::parse(&row.kind),
+                    id: row.id,
+                    title: row.title,
+                    pet_id: row.pet_id,
+                    created_at: row.created_at,
+                    rank: row.rank,
+                })
+                .collect())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl HealthRepository for PgRepository {
+    async fn ping(&self) -> Result<Option<PoolState>, Error> {
+        self.run(move |connection| {
+            diesel::sql_query("SELECT 1").execute(connection)?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(self.pool_state())
+    }
+
+    fn pool_state(&self) -> Option<PoolState> {
+        let state = self.pool.state();
+
+        Some(PoolState {
+            connections: state.connections,
+            idle: state.idle_connections,
+            max_size: self.pool.max_size(),
+        })
+    }
+}
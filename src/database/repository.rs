@@ -0,0 +1,505 @@
+use async_trait::async_trait;
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use chrono::NaiveDateTime;
+
+use super::pets::PetInsert;
+use super::samples::{ResultInsert, ReviewStatus, SampleInsert};
+use super::users::{ApiTokenInsert, OidcUserInsert, Scope, UserInsert, UserUpdate};
+use crate::detector::Classification;
+use crate::error::Error;
+use crate::messages::pets::PetEntry;
+use crate::messages::samples::{InferredListEntry, PendingListEntry, SampleSort, SortOrder};
+use crate::messages::search::SearchHit;
+use crate::messages::users::{ApiTokenEntry, Profile};
+use crate::password_hasher::PasswordHash;
+
+/// Samples per list page unless the caller asks for another size.
+pub(crate) const PAGE_SIZE: usize = 10;
+/// Largest list page a caller may ask for.
+pub(crate) const PAGE_SIZE_MAX: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SessionUser {
+    pub(crate) user_id: uuid::Uuid,
+    pub(crate) login_name: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct TotpState {
+    pub(crate) secret: Option<Vec<u8>>,
+    pub(crate) enabled: bool,
+    pub(crate) last_step: Option<i64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OidcLoginInsert {
+    pub(crate) state: String,
+    pub(crate) pkce_verifier: String,
+    pub(crate) nonce: String,
+    pub(crate) expires_at: NaiveDateTime,
+}
+
+/// Which samples to re-run inference on. Deleted samples are never selected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SampleSelection {
+    /// Any sample when empty.
+    pub(crate) sample_ids: Vec<uuid::Uuid>,
+    pub(crate) owner_id: Option<uuid::Uuid>,
+    pub(crate) created_since: Option<NaiveDateTime>,
+    /// Only samples without results.
+    pub(crate) pending_only: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SampleCounts {
+    pub(crate) total: i64,
+    pub(crate) deleted: i64,
+    pub(crate) pending: i64,
+}
+
+/// Which samples a list holds. The result conditions match samples with at
+/// least one result meeting all of them.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SampleFilter {
+    pub(crate) owner_id: uuid::Uuid,
+    /// Part of the label, any case.
+    pub(crate) keyword: Option<String>,
+    pub(crate) pet_id: Option<uuid::Uuid>,
+    pub(crate) created_from: Option<NaiveDateTime>,
+    /// Exclusive.
+    pub(crate) created_until: Option<NaiveDateTime>,
+    pub(crate) review_status: Option<ReviewStatus>,
+    pub(crate) classification: Option<Classification>,
+    pub(crate) min_certainty: Option<f32>,
+    pub(crate) model_version: Option<String>,
+}
+
+/// Value a sample is sorted by.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum SortKey {
+    Date(NaiveDateTime),
+    /// Highest certainty among the results.
+    Certainty(f32),
+    Label(String),
+}
+
+/// Where a sample list page ends: the sort value of its last sample and the
+/// id breaking ties between samples with the same value.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SampleCursor {
+    pub(crate) key: SortKey,
+    pub(crate) id: uuid::Uuid,
+}
+
+impl SampleCursor {
+    /// Opaque to clients, `<sort>.<order>.<id>.<value>` in URL safe base64,
+    /// so a cursor only continues the ordering it came from.
+    pub(crate) fn encode(&self, order: SortOrder) -> String {
+        let (sort, value) = match &self.key {
+            SortKey::Date(created_at) => (
+                SampleSort::Date,
+                created_at
+                    .and_utc()
+                    .timestamp_nanos_opt()
+                    .unwrap_or(i64::MAX)
+                    .to_string(),
+            ),
+            SortKey::Certainty(certainty) => {
+                (SampleSort::Certainty, certainty.to_bits().to_string())
+            }
+            SortKey::Label(label) => (SampleSort::Label, label.clone()),
+        };
+
+        BASE64_URL_SAFE_NO_PAD.encode(format!(
+            "{}.{}.{}.{value}",
+            sort.as_str(),
+            order.as_str(),
+            self.id
+        ))
+    }
+
+    pub(crate) fn parse(cursor: &str, sort: SampleSort, order: SortOrder) -> Result<Self, Error> {
+        let invalid = || Error::BadRequest {
+            code: "invalid_cursor",
+            message: "Cursor is not one returned as next_cursor for this sort and order".into(),
+        };
+
+        let decoded = BASE64_URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(invalid)?;
+        let mut parts = decoded.splitn(4, '.');
+
+        let (Some(cursor_sort), Some(cursor_order), Some(id), Some(value)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        if cursor_sort != sort.as_str() || cursor_order != order.as_str() {
+            return Err(invalid());
+        }
+
+        let key = match sort {
+            SampleSort::Date => SortKey::Date(
+                chrono::DateTime::from_timestamp_nanos(value.parse().map_err(|_| invalid())?)
+                    .naive_utc(),
+            ),
+            SampleSort::Certainty => {
+                SortKey::Certainty(f32::from_bits(value.parse().map_err(|_| invalid())?))
+            }
+            SampleSort::Label => SortKey::Label(value.to_string()),
+        };
+
+        Ok(Self {
+            key,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Up to `limit` samples following `after` in the given order, from the
+/// start when unset.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PageRequest {
+    pub(crate) after: Option<SampleCursor>,
+    pub(crate) limit: usize,
+    pub(crate) sort: SampleSort,
+    pub(crate) order: SortOrder,
+}
+
+/// Connections held by a pooled backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+pub(crate) struct PoolState {
+    pub(crate) connections: u32,
+    pub(crate) idle: u32,
+    pub(crate) max_size: u32,
+}
+
+/// Accounts and everything proving ownership of one: passwords, email and
+/// password reset tokens, TOTP and linked external identities.
+#[async_trait]
+pub(crate) trait UserRepository: Send + Sync {
+    /// Fails with a conflict on a taken login name or email.
+    async fn insert_user(&self, record: UserInsert) -> Result<uuid::Uuid, Error>;
+
+    async fn profile(&self, user_id: uuid::Uuid) -> Result<Option<Profile>, Error>;
+
+    async fn update_user(&self, user_id: uuid::Uuid, changes: UserUpdate) -> Result<(), Error>;
+
+    /// Removes the account along with everything it owns: sessions, tokens,
+    /// identities, samples with their results and pets. Samples of other
+    /// users assigned to its pets are unassigned. All or nothing.
+    async fn delete_user(&self, user_id: uuid::Uuid) -> Result<(), Error>;
+
+    async fn user_by_credentials(
+        &self,
+        login_name: String,
+        hash: PasswordHash,
+    ) -> Result<Option<(uuid::Uuid, bool)>, Error>;
+
+    async fn password_matches(
+        &self,
+        user_id: uuid::Uuid,
+        hash: PasswordHash,
+    ) -> Result<bool, Error>;
+
+    /// Replaces the password if `current` matches, or unconditionally when it
+    /// is `None`. Returns whether the password was replaced.
+    async fn update_password(
+        &self,
+        user_id: uuid::Uuid,
+        current: Option<PasswordHash>,
+        replacement: PasswordHash,
+    ) -> Result<bool, Error>;
+
+    async fn insert_email_verification(
+        &self,
+        user_id: uuid::Uuid,
+        email: String,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error>;
+
+    /// Marks the address as verified if the token is unused, unexpired and
+    /// still matches the current address.
+    async fn consume_email_verification(&self, token_hash: Vec<u8>) -> Result<bool, Error>;
+
+    async fn unverified_email(&self, user_id: uuid::Uuid) -> Result<Option<String>, Error>;
+
+    async fn verified_email(
+        &self,
+        login_name: String,
+    ) -> Result<Option<(uuid::Uuid, String)>, Error>;
+
+    async fn insert_password_reset(
+        &self,
+        user_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), Error>;
+
+    async fn consume_password_reset(
+        &self,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<uuid::Uuid>, Error>;
+
+    async fn totp(&self, user_id: uuid::Uuid) -> Result<Option<TotpState>, Error>;
+
+    /// Stores a pending secret unless TOTP is already enabled.
+    async fn begin_totp(&self, user_id: uuid::Uuid, secret: Vec<u8>) -> Result<bool, Error>;
+
+    async fn enable_totp(
+        &self,
+        user_id: uuid::Uuid,
+        step: i64,
+        recovery_code_hashes: Vec<Vec<u8>>,
+    ) -> Result<(), Error>;
+
+    /// Also drops recovery codes and pending login challenges.
+    async fn disable_totp(&self, user_id: uuid::Uuid) -> Result<(), Error>;
+
+    async fn set_totp_step(&self, user_id: uuid::Uuid, step: i64) -> Result<(), Error>;
+
+    async fn use_recovery_code(
+        &self,
+        user_id: uuid::Uuid,
+        code_hash: Vec<u8>,
+    ) -> Result<bool, Error>;
+
+    async fn identity_user(
+        &self,
+        issuer: String,
+        subject: String,
+    ) -> Result<Option<uuid::Uuid>, Error>;
+
+    async fn login_name_exists(&self, login_name: String) -> Result<bool, Error>;
+
+    async fn email_exists(&self, email: String) -> Result<bool, Error>;
+
+    async fn insert_oidc_user(
+        &self,
+        record: OidcUserInsert,
+        issuer: String,
+        subject: String,
+    ) -> Result<uuid::Uuid, Error>;
+
+    async fn user_id(&self, login_name: String) -> Result<Option<uuid::Uuid>, Error>;
+
+    async fn set_admin(&self, user_id: uuid::Uuid, is_admin: bool) -> Result<(), Error>;
+
+    /// Returns the number of users and how many of them are admins.
+    async fn count_users(&self) -> Result<(i64, i64), Error>;
+}
+
+/// Ways to act as a user: login sessions, pending second factor challenges,
+/// personal API tokens and in-flight OIDC logins.
+#[async_trait]
+pub(crate) trait SessionRepository: Send + Sync {
+    /// Returns the session id and its access token.
+    async fn create_session(&self, user_id: uuid::Uuid) -> Result<(uuid::Uuid, Vec<u8>), Error>;
+
+    async fn session_user(
+        &self,
+        session_id: uuid::Uuid,
+        access_token: Vec<u8>,
+    ) -> Result<Option<SessionUser>, Error>;
+
+    async fn delete_sessions(
+        &self,
+        user_id: uuid::Uuid,
+        keep: Option<uuid::Uuid>,
+    ) -> Result<(), Error>;
+
+    async fn create_login_challenge(
+        &self,
+        user_id: uuid::Uuid,
+        expires_at: NaiveDateTime,
+    ) -> Result<uuid::Uuid, Error>;
+
+    /// Counts an attempt against an unexpired challenge, returning its user
+    /// while fewer than `max_attempts` have been made.
+    async fn attempt_login_challenge(
+        &self,
+        challenge_id: uuid::Uuid,
+        max_attempts: i32,
+    ) -> Result<Option<uuid::Uuid>, Error>;
+
+    /// Login name of the user an unexpired challenge was issued to.
+    async fn login_challenge_login_name(
+        &self,
+        challenge_id: uuid::Uuid,
+    ) -> Result<Option<String>, Error>;
+
+    async fn delete_login_challenge(&self, challenge_id: uuid::Uuid) -> Result<(), Error>;
+
+    async fn insert_api_token(&self, record: ApiTokenInsert) -> Result<uuid::Uuid, Error>;
+
+    /// Looks up an unrevoked token and records that it was used.
+    async fn api_token_user(
+        &self,
+        token_id: uuid::Uuid,
+        token_hash: Vec<u8>,
+    ) -> Result<Option<(SessionUser, Vec<Scope>)>, Error>;
+
+    async fn api_tokens(&self, user_id: uuid::Uuid) -> Result<Vec<ApiTokenEntry>, Error>;
+
+    async fn revoke_api_token(
+        &self,
+        user_id: uuid::Uuid,
+        token_id: uuid::Uuid,
+    ) -> Result<bool, Error>;
+
+    /// Also prunes expired logins.
+    async fn insert_oidc_login(&self, record: OidcLoginInsert) -> Result<(), Error>;
+
+    /// Consumes an unexpired login, returning its PKCE verifier and nonce.
+    async fn take_oidc_login(&self, state: String) -> Result<Option<(String, String)>, Error>;
+
+    async fn count_sessions(&self) -> Result<i64, Error>;
+}
+
+#[async_trait]
+pub(crate) trait SampleRepository: Send + Sync {
+    async fn insert_samples(&self, samples: Vec<SampleInsert>) -> Result<(), Error>;
+
+    /// Image of a sample that was not deleted, optionally only if owned by `owner_id`.
+    async fn sample_bytes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: Option<uuid::Uuid>,
+    ) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Whether a sample of `owner_id` that was not deleted was found.
+    async fn delete_sample(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+    ) -> Result<bool, Error>;
+
+    /// Whether a sample of `owner_id` that was not deleted was found.
+    async fn set_review_status(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        status: ReviewStatus,
+    ) -> Result<bool, Error>;
+
+    /// Whether a sample of `owner_id` that was not deleted was found.
+    async fn set_notes(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        notes: Option<String>,
+    ) -> Result<bool, Error>;
+
+    /// One page of the samples without results that match `filter`, in the
+    /// sort and order of `page`, following its cursor.
+    async fn pending_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<PendingListEntry>, Error>;
+
+    /// All samples `pending_samples` pages through.
+    async fn count_pending_samples(&self, filter: SampleFilter) -> Result<i64, Error>;
+
+    /// Permanently removes soft-deleted samples, returning how many.
+    async fn purge_deleted_samples(&self) -> Result<usize, Error>;
+
+    /// Oldest first.
+    async fn select_samples(&self, selection: SampleSelection) -> Result<Vec<uuid::Uuid>, Error>;
+
+    async fn count_samples(&self) -> Result<SampleCounts, Error>;
+}
+
+#[async_trait]
+pub(crate) trait ResultRepository: Send + Sync {
+    async fn insert_results(&self, results: Vec<ResultInsert>) -> Result<(), Error>;
+
+    /// One page of the samples with a result matching `filter`, in the sort
+    /// and order of `page`, following its cursor. Each comes with all of its
+    /// results, not only the matching ones.
+    async fn inferred_samples(
+        &self,
+        filter: SampleFilter,
+        page: PageRequest,
+    ) -> Result<Vec<InferredListEntry>, Error>;
+
+    /// All samples `inferred_samples` pages through.
+    async fn count_inferred_samples(&self, filter: SampleFilter) -> Result<i64, Error>;
+
+    async fn delete_deleted_sample_results(&self) -> Result<(), Error>;
+
+    /// Swaps all results of a sample for `results` at once.
+    async fn replace_results(
+        &self,
+        sample_id: uuid::Uuid,
+        results: Vec<ResultInsert>,
+    ) -> Result<(), Error>;
+
+    async fn count_results(&self) -> Result<i64, Error>;
+}
+
+#[async_trait]
+pub(crate) trait PetRepository: Send + Sync {
+    async fn insert_pet(&self, record: PetInsert) -> Result<uuid::Uuid, Error>;
+
+    /// Oldest first.
+    async fn pets(&self, owner_id: uuid::Uuid) -> Result<Vec<PetEntry>, Error>;
+
+    /// Assigns a sample of `owner_id` that was not deleted to one of their
+    /// pets, or unassigns it. Whether the sample and the pet were found.
+    async fn assign_pet(
+        &self,
+        sample_id: uuid::Uuid,
+        owner_id: uuid::Uuid,
+        pet_id: Option<uuid::Uuid>,
+    ) -> Result<bool, Error>;
+}
+
+#[async_trait]
+pub(crate) trait SearchRepository: Send + Sync {
+    /// Samples and pets of `owner_id` matching `query`, most relevant first.
+    async fn search(
+        &self,
+        owner_id: uuid::Uuid,
+        query: String,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, Error>;
+}
+
+#[async_trait]
+pub(crate) trait HealthRepository: Send + Sync {
+    /// Round trips to the backend, returning the pool state for backends
+    /// that have one.
+    async fn ping(&self) -> Result<Option<PoolState>, Error>;
+
+    fn pool_state(&self) -> Option<PoolState>;
+}
+
+/// A storage backend implementing every repository.
+pub(crate) trait Repository:
+    UserRepository
+    + SessionRepository
+    + SampleRepository
+    + ResultRepository
+    + PetRepository
+    + SearchRepository
+    + HealthRepository
+{
+}
+
+impl<T> Repository for T where
+    T: UserRepository
+        + SessionRepository
+        + SampleRepository
+        + ResultRepository
+        + PetRepository
+        + SearchRepository
+        + HealthRepository
+{
+}
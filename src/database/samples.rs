@@ -1,5 +1,6 @@
 use chrono::NaiveDateTime;
 use diesel::{associations::Associations, Identifiable, Insertable, Queryable, Selectable};
+use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, Eq, Insertable)]
 #[diesel(table_name = crate::schema::samples)]
@@ -16,6 +17,9 @@ pub(crate) struct SampleEntry {
     pub(crate) id: uuid::Uuid,
     pub(crate) label: String,
     pub(crate) pet_id: Option<uuid::Uuid>,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) review_status: String,
+    pub(crate) notes: Option<String>,
 }
 
 #[derive(Clone, Insertable)]
@@ -28,10 +32,49 @@ pub(crate) struct ResultInsert {
     pub(crate) y: f32,
     pub(crate) width: f32,
     pub(crate) height: f32,
+    pub(crate) model_version: String,
 }
 
+/// Whether someone looked at the results of a sample and agreed with them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReviewStatus {
+    #[default]
+    Unreviewed,
+    Confirmed,
+    Rejected,
+}
+
+impl ReviewStatus {
+    pub(crate) const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unreviewed => "unreviewed",
+            Self::Confirmed => "confirmed",
+            Self::Rejected => "rejected",
+        }
+    }
+
+    /// Unknown values read as unreviewed.
+    pub(crate) fn parse(value: &str) -> Self {
+        match value {
+            "confirmed" => Self::Confirmed,
+            "rejected" => Self::Rejected,
+            _ => Self::Unreviewed,
+        }
+    }
+}
+
+use crate::error::Error;
 use crate::schema::{results, samples};
 
+#[inline]
+pub(crate) fn sample_not_found() -> Error {
+    Error::NotFound {
+        code: "sample_not_found",
+        message: "Sample not found".into(),
+    }
+}
+
 #[derive(Queryable, Identifiable, Selectable)]
 #[table_name = "samples"]
 pub struct Sample {
@@ -40,6 +83,8 @@ pub struct Sample {
     pub pet_id: Option<uuid::Uuid>,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
+    pub review_status: String,
+    pub notes: Option<String>,
 }
 
 #[derive(Queryable, Identifiable, Associations, Selectable)]
@@ -61,4 +106,5 @@ pub struct Result {
     pub sample_id: uuid::Uuid,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
+    pub model_version: Option<String>,
 }
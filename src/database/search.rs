@@ -0,0 +1,48 @@
+use chrono::NaiveDateTime;
+use diesel::sql_types::{Float4, Nullable, Text, Timestamp, Uuid};
+use diesel::QueryableByName;
+
+/// Samples and pets of `$1` matching the web search style query `$2`, `$3`
+/// at most. The document expressions are the ones `v009_search` indexes,
+/// they have to stay identical for the indexes to be used.
+pub(crate) const SEARCH_QUERY: &str = "\
+SELECT * FROM (
+    SELECT 'sample' AS kind, samples.id, samples.label::text AS title, samples.pet_id,
+        samples.created_at,
+        ts_rank(
+            setweight(to_tsvector('simple', samples.label), 'A')
+                || setweight(to_tsvector('simple', coalesce(samples.notes, '')), 'B'),
+            query
+        ) AS rank
+    FROM samples, websearch_to_tsquery('simple', $2) query
+    WHERE samples.owner_id = $1
+        AND samples.deleted = false
+        AND (
+            setweight(to_tsvector('simple', samples.label), 'A')
+                || setweight(to_tsvector('simple', coalesce(samples.notes, '')), 'B')
+        ) @@ query
+    UNION ALL
+    SELECT 'pet', pets.id, pets.name::text, NULL::uuid, pets.created_at,
+        ts_rank(setweight(to_tsvector('simple', pets.name), 'A'), query)
+    FROM pets, websearch_to_tsquery('simple', $2) query
+    WHERE pets.owner_id = $1
+        AND setweight(to_tsvector('simple', pets.name), 'A') @@ query
+) hits
+ORDER BY rank DESC, created_at DESC, id
+LIMIT $3";
+
+#[derive(QueryableByName)]
+pub(crate) struct SearchRow {
+    #[diesel(sql_type = Text)]
+    pub(crate) kind: String,
+    #[diesel(sql_type = Uuid)]
+    pub(crate) id: uuid::Uuid,
+    #[diesel(sql_type = Text)]
+    pub(crate) title: String,
+    #[diesel(sql_type = Nullable<Uuid>)]
+    pub(crate) pet_id: Option<uuid::Uuid>,
+    #[diesel(sql_type = Timestamp)]
+    pub(crate) created_at: NaiveDateTime,
+    #[diesel(sql_type = Float4)]
+    pub(crate) rank: f32,
+}
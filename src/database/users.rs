@@ -1,14 +1,19 @@
 use actix_web::dev::Payload;
-use actix_web::http::StatusCode;
+use actix_web::http::header::AUTHORIZATION;
 use actix_web::web::Data;
 use actix_web::HttpRequest;
 
 use base64::prelude::{Engine, BASE64_STANDARD};
-use diesel::Insertable;
+use chrono::NaiveDateTime;
+use diesel::{AsChangeset, Insertable};
+use serde::{Deserialize, Serialize};
 
-use crate::schema::users;
+use crate::schema::{api_tokens, users};
 
+use crate::error::Error;
+use crate::oidc::Identity;
 use crate::password_hasher::PasswordHash;
+use crate::validation::{LOGIN_NAME_MAX, PERSON_NAME_MAX};
 
 #[derive(Clone, Debug, PartialEq, Eq, Insertable)]
 #[diesel(table_name = users)]
@@ -17,71 +22,311 @@ pub(crate) struct UserInsert {
     pub(crate) first_name: String,
     pub(crate) last_name: String,
     pub(crate) argon2: PasswordHash,
+    pub(crate) email: Option<String>,
+    pub(crate) is_admin: bool,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) const EMAIL_UNIQUE_CONSTRAINT: &str = "users_email_key";
+
+#[derive(Clone, Debug, PartialEq, Eq, AsChangeset)]
+#[diesel(table_name = users)]
+pub(crate) struct UserUpdate {
+    pub(crate) first_name: Option<String>,
+    pub(crate) last_name: Option<String>,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified_at: Option<Option<NaiveDateTime>>,
+}
+
+/// Account provisioned on first OpenID Connect login.
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = users)]
+pub(crate) struct OidcUserInsert {
+    pub(crate) login_name: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) argon2: PasswordHash,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified_at: Option<NaiveDateTime>,
+}
+
+impl OidcUserInsert {
+    /// Login name derived from `preferred_username` or the email local part,
+    /// reduced to lowercase alphanumerics, `_`, `-` and `.`.
+    pub(crate) fn login_name_base(identity: &Identity) -> String {
+        let source = identity
+            .preferred_username
+            .as_deref()
+            .or_else(|| {
+                identity
+                    .email
+                    .as_deref()
+                    .and_then(|email| email.split('@').next())
+            })
+            .unwrap_or_default();
+
+        let name: String = source
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            .map(|c| c.to_ascii_lowercase())
+            .take(LOGIN_NAME_MAX)
+            .collect();
+
+        if name.is_empty() {
+            "user".to_string()
+        } else {
+            name
+        }
+    }
+
+    #[inline]
+    pub(crate) fn person_name(name: Option<String>) -> String {
+        name.unwrap_or_default()
+            .trim()
+            .chars()
+            .take(PERSON_NAME_MAX)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[diesel(table_name = api_tokens)]
+pub(crate) struct ApiTokenInsert {
+    pub(crate) name: String,
+    pub(crate) token_hash: Vec<u8>,
+    pub(crate) scopes: Vec<Option<String>>,
+    pub(crate) user_id: uuid::Uuid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub(crate) enum Scope {
+    #[serde(rename = "samples:read")]
+    ReadSamples,
+    #[serde(rename = "samples:upload")]
+    Upload,
+    #[serde(rename = "samples:infer")]
+    Infer,
+    #[serde(rename = "samples:review")]
+    Review,
+}
+
+impl Scope {
+    pub(crate) const fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadSamples => "samples:read",
+            Self::Upload => "samples:upload",
+            Self::Infer => "samples:infer",
+            Self::Review => "samples:review",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "samples:read" => Some(Self::ReadSamples),
+            "samples:upload" => Some(Self::Upload),
+            "samples:infer" => Some(Self::Infer),
+            "samples:review" => Some(Self::Review),
+            _ => None,
+        }
+    }
+
+    /// Scopes as stored, skipping any this version no longer knows.
+    pub(crate) fn parse_all(values: &[Option<String>]) -> Vec<Self> {
+        values
+            .iter()
+            .flatten()
+            .filter_map(|value| Self::parse(value))
+            .collect()
+    }
+}
+
+/// How the caller proved who they are. Sessions come from `/users/login` and
+/// carry every scope; API tokens only carry the scopes they were created with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Credential {
+    Session { id: uuid::Uuid },
+    ApiToken { id: uuid::Uuid, scopes: Vec<Scope> },
+}
+
+#[derive(Debug, Serialize)]
 pub(crate) struct UserSession {
     pub(crate) user_id: uuid::Uuid,
     pub(crate) login_name: String,
     pub(crate) first_name: String,
     pub(crate) last_name: String,
+    #[serde(skip)]
+    pub(crate) credential: Credential,
+}
+
+impl UserSession {
+    pub(crate) fn require(&self, scope: Scope) -> Result<(), Error> {
+        match &self.credential {
+            Credential::Session { .. } => Ok(()),
+            Credential::ApiToken { scopes, .. } if scopes.contains(&scope) => Ok(()),
+            Credential::ApiToken { .. } => Err(Error::Forbidden {
+                code: "missing_scope",
+                message: format!("Token lacks the {} scope", scope.as_str()).into(),
+            }),
+        }
+    }
+
+    pub(crate) fn require_session(&self) -> Result<uuid::Uuid, Error> {
+        match &self.credential {
+            Credential::Session { id } => Ok(*id),
+            Credential::ApiToken { .. } => Err(Error::Forbidden {
+                code: "session_required",
+                message: "Only available to a logged in session".into(),
+            }),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub(crate) struct AuthorizationError;
+/// Token presented through `Authorization: Bearer`.
+///
+/// Session tokens are `<session id>.<access token>` as handed out by
+/// `/users/login`, personal API tokens are `pat_<token id>.<secret>`.
+pub(crate) enum BearerToken {
+    Session {
+        id: uuid::Uuid,
+        access_token: Vec<u8>,
+    },
+    ApiToken {
+        id: uuid::Uuid,
+        secret: Vec<u8>,
+    },
+}
+
+impl BearerToken {
+    const API_TOKEN_PREFIX: &'static str = "pat_";
+
+    pub(crate) fn parse(value: &str) -> Result<Self, Error> {
+        let (is_api_token, value) = match value.strip_prefix(Self::API_TOKEN_PREFIX) {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+
+        let (id, secret) = value.split_once('.').ok_or_else(unauthenticated)?;
+        let id = id.parse::<uuid::Uuid>().map_err(|_| unauthenticated())?;
+        let secret = BASE64_STANDARD
+            .decode(secret)
+            .map_err(|_| unauthenticated())?;
+
+        Ok(if is_api_token {
+            Self::ApiToken { id, secret }
+        } else {
+            Self::Session {
+                id,
+                access_token: secret,
+            }
+        })
+    }
+
+    pub(crate) fn format_session(id: uuid::Uuid, access_token: &str) -> String {
+        format!("{id}.{access_token}")
+    }
+
+    pub(crate) fn format_api_token(id: uuid::Uuid, secret: &[u8]) -> String {
+        format!(
+            "{}{id}.{}",
+            Self::API_TOKEN_PREFIX,
+            BASE64_STANDARD.encode(secret)
+        )
+    }
+}
 
 impl actix_web::FromRequest for UserSession {
-    type Error = AuthorizationError;
+    type Error = Error;
 
-    type Future =
-        std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self, AuthorizationError>>>>;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self, Error>>>>;
 
     fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
         let req = req.clone();
 
         Box::pin(async move {
+            let database = req
+                .app_data::<Data<super::Database>>()
+                .ok_or_else(|| Error::internal("Database is not registered as app data"))?;
+
+            if let Some(header) = req.headers().get(AUTHORIZATION) {
+                let token = header
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .ok_or_else(unauthenticated)?;
+
+                return match BearerToken::parse(token.trim())? {
+                    BearerToken::Session { id, access_token } => {
+                        database.get_user_session(id, access_token).await
+                    }
+                    BearerToken::ApiToken { id, secret } => {
+                        database.get_api_token_session(id, secret).await
+                    }
+                };
+            }
+
             let session_id = req
                 .cookie("session")
-                .ok_or(AuthorizationError)?
+                .ok_or_else(unauthenticated)?
                 .value()
                 .to_owned()
                 .parse::<uuid::Uuid>()
-                .or(Err(AuthorizationError))?;
+                .map_err(|_| unauthenticated())?;
 
             let access_token = BASE64_STANDARD
                 .decode(
                     req.cookie("access_token")
-                        .ok_or(AuthorizationError)?
+                        .ok_or_else(unauthenticated)?
                         .value(),
                 )
-                .or(Err(AuthorizationError))?;
-
-            let database = req.app_data::<Data<super::Database>>().unwrap();
+                .map_err(|_| unauthenticated())?;
 
             database.get_user_session(session_id, access_token).await
         })
     }
 }
 
-impl actix_web::ResponseError for AuthorizationError {
-    fn status_code(&self) -> StatusCode {
-        StatusCode::UNAUTHORIZED
+#[inline]
+pub(crate) fn unauthenticated() -> Error {
+    Error::Unauthorized {
+        code: "unauthenticated",
+        message: "Missing or invalid credentials".into(),
     }
+}
 
-    fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
-        let mut res = actix_web::HttpResponse::new(self.status_code());
+#[inline]
+pub(crate) fn invalid_credentials() -> Error {
+    Error::Unauthorized {
+        code: "invalid_credentials",
+        message: "Invalid login name, password or code".into(),
+    }
+}
 
-        res.headers_mut().insert(
-            actix_web::http::header::CONTENT_TYPE,
-            actix_web::http::header::HeaderValue::from_static("application/json"),
-        );
+#[inline]
+pub(crate) fn invalid_code() -> Error {
+    Error::invalid_field("invalid_code", "code", "Invalid code")
+}
+
+#[inline]
+pub(crate) fn user_not_found() -> Error {
+    Error::NotFound {
+        code: "user_not_found",
+        message: "User not found".into(),
+    }
+}
 
-        res
+#[inline]
+pub(crate) fn login_name_taken() -> Error {
+    Error::Conflict {
+        code: "login_name_taken",
+        field: "login_name",
+        message: "Already exists".into(),
     }
 }
 
-impl std::fmt::Display for AuthorizationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Authorization error")
+#[inline]
+pub(crate) fn email_taken() -> Error {
+    Error::Conflict {
+        code: "email_taken",
+        field: "email",
+        message: "Already exists".into(),
     }
 }
@@ -0,0 +1,89 @@
+mod onnx;
+mod scripted;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+use crate::error::Error;
+
+pub use onnx::OnnxConfig;
+pub(crate) use onnx::OnnxDetector;
+pub(crate) use scripted::ScriptedDetector;
+
+/// Finds regions of interest in a 640x640 image.
+#[async_trait]
+pub(crate) trait Detector: Send + Sync {
+    async fn infer(&self, image: &DynamicImage) -> Result<Vec<ResultBox>, Error>;
+
+    /// Runs the model once on a blank input so the first real inference
+    /// does not pay for lazy initialisation. Not counted in any metric.
+    async fn warm_up(&self) -> Result<(), Error>;
+
+    /// Identifies the weights in use, reported by `/health/ready`.
+    fn model_version(&self) -> &str;
+}
+
+#[derive(Clone)]
+pub enum DetectorConfig {
+    Onnx(OnnxConfig),
+    /// Replays the responses in a JSON file, see [`ScriptedDetector`].
+    Scripted(PathBuf),
+}
+
+pub(crate) fn from_config(config: &DetectorConfig) -> Arc<dyn Detector> {
+    match config {
+        DetectorConfig::Onnx(config) => Arc::new(OnnxDetector::new(config)),
+        DetectorConfig::Scripted(path) => Arc::new(
+            ScriptedDetector::from_file(path)
+                .unwrap_or_else(|e| panic!("Invalid detector script {path:?}: {e}")),
+        ),
+    }
+}
+
+/// Crops a photo the way `/scan` does and scales it to the model input size.
+pub(crate) fn prepare_scan(raw: &DynamicImage) -> DynamicImage {
+    let (width, height) = raw.dimensions();
+    let size = width.min(height);
+    let (center_x, center_y) = (width / 2, height / 2);
+    let (x, y) = (center_x - size / 2, center_y - size / 2);
+
+    raw.crop_imm(x, y, width, height)
+        .resize_exact(640, 640, FilterType::CatmullRom)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub enum Classification {
+    Normal,
+    Incipient,
+}
+
+impl Classification {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Incipient => "incipient",
+        }
+    }
+}
+
+impl From<usize> for Classification {
+    fn from(value: usize) -> Self {
+        match value {
+            3 => Self::Normal,
+            _ => Self::Incipient,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
+pub struct ResultBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub probability: f32,
+    pub classification: Classification,
+}
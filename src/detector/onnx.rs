@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use image::{DynamicImage, GenericImageView};
+use ndarray::{s, Array, Axis};
+use ort::{tensor::InputTensor, Environment, ExecutionProvider, InMemorySession};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use super::{Classification, Detector, ResultBox};
+use crate::error::Error;
+use crate::logging::LogLevel;
+use crate::metrics::METRICS;
+
+#[derive(Clone)]
+pub struct OnnxConfig {
+    /// Uses the model built into the binary when unset.
+    pub model: Option<PathBuf>,
+    /// Boxes less likely than this are dropped.
+    pub confidence_threshold: f32,
+    /// Overlapping boxes above this intersection over union are merged.
+    pub iou_threshold: f32,
+    pub log_level: LogLevel,
+}
+
+pub(crate) struct OnnxDetector {
+    session: Mutex<InMemorySession<'static>>,
+    version: String,
+    confidence_threshold: f32,
+    iou_threshold: f32,
+}
+
+impl OnnxDetector {
+    const MAX_BYTES_RECIP: f32 = 1.0 / 255.0;
+
+    pub(crate) fn new(config: &OnnxConfig) -> Self {
+        // The session borrows the model for its whole life, which is the
+        // life of the process.
+        let model: &'static [u8] = match &config.model {
+            Some(path) => std::fs::read(path)
+                .unwrap_or_else(|e| panic!("Could not read model {}: {e}", path.display()))
+                .leak(),
+            None => include_bytes!("../../model.onnx"),
+        };
+
+        let environment = Environment::builder()
+            .with_log_level(match config.log_level {
+                LogLevel::Trace => ort::LoggingLevel::Verbose,
+                LogLevel::Debug => ort::LoggingLevel::Info,
+                LogLevel::Info | LogLevel::Warn => ort::LoggingLevel::Warning,
+                LogLevel::Error => ort::LoggingLevel::Error,
+            })
+            .with_execution_providers([ExecutionProvider::cuda(), ExecutionProvider::onednn()])
+            .build()
+            .unwrap()
+            .into_arc();
+
+        let session = Mutex::new(
+            ort::SessionBuilder::new(&environment)
+                .unwrap()
+                .with_optimization_level(ort::GraphOptimizationLevel::Level3)
+                .unwrap()
+                .with_intra_threads(1)
+                .unwrap()
+                .with_model_from_memory(model)
+                .unwrap(),
+        );
+
+        let digest = Sha256::digest(model);
+
+        Self {
+            session,
+            version: format!(
+                "sha256:{}",
+                digest[..6]
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<String>()
+            ),
+            confidence_threshold: config.confidence_threshold,
+            iou_threshold: config.iou_threshold,
+        }
+    }
+
+    #[inline(always)]
+    fn iou(a: &OutputBox, b: &OutputBox) -> f32 {
+        let intersection = {
+            let intersect_left = a.left.max(b.left);
+            let intersect_top = a.top.max(b.top);
+            let intersect_right = a.right.min(b.right);
+            let intersect_bottom = a.bottom.min(b.bottom);
+
+            let intersect_width = intersect_right - intersect_left;
+            let intersect_height = intersect_bottom - intersect_top;
+
+            intersect_width * intersect_height
+        };
+
+        let union = {
+            let box1_area = a.width * a.height;
+            let box2_area = b.width * b.height;
+
+            box1_area + box2_area - intersection
+        };
+
+        intersection / union
+    }
+}
+
+#[async_trait]
+impl Detector for OnnxDetector {
+    #[tracing::instrument(skip_all, fields(model = %self.version))]
+    async fn infer(&self, image: &DynamicImage) -> Result<Vec<ResultBox>, Error> {
+        let stage = Instant::now();
+        let mut input = Array::zeros((1, 3, 640, 640)).into_dyn();
+
+        for pixel in image.pixels() {
+            let x = pixel.0 as _;
+            let y = pixel.1 as _;
+            let [r, g, b, _] = pixel.2 .0;
+            input[[0, 0, y, x]] = (r as f32) * OnnxDetector::MAX_BYTES_RECIP;
+            input[[0, 1, y, x]] = (g as f32) * OnnxDetector::MAX_BYTES_RECIP;
+            input[[0, 2, y, x]] = (b as f32) * OnnxDetector::MAX_BYTES_RECIP;
+        }
+
+        let preprocess = stage.elapsed();
+        METRICS
+            .inference_duration
+            .with_label_values(&["preprocess"])
+            .observe(preprocess.as_secs_f64());
+
+        let (output, waited, run) = {
+            let waiting = Instant::now();
+            let model = self.session.lock().await;
+            let waited = waiting.elapsed();
+            METRICS.session_lock_wait.observe(waited.as_secs_f64());
+
+            let stage = Instant::now();
+            let outputs = model
+                .run([InputTensor::FloatTensor(input)])
+                .map_err(Error::internal)?;
+            let tensor = outputs
+                .first()
+                .ok_or_else(|| Error::internal("Model returned no output"))?
+                .try_extract::<f32>()
+                .map_err(Error::internal)?;
+
+            let output = tensor.view().t().to_owned();
+            (output, waited, stage.elapsed())
+        };
+
+        METRICS
+            .inference_duration
+            .with_label_values(&["run"])
+            .observe(run.as_secs_f64());
+
+        let stage = Instant::now();
+
+        let mut boxes = Vec::new();
+        let output = output.slice(s![.., .., 0]);
+        for row in output.axis_iter(Axis(0)) {
+            let row: Vec<_> = row.iter().copied().collect();
+            let Some((class_id, probability)) = row
+                .iter()
+                .skip(4)
+                .enumerate()
+                .map(|(index, value)| (index, *value))
+                .reduce(|a, row| if row.1 > a.1 { row } else { a })
+            else {
+                continue;
+            };
+
+            if probability < self.confidence_threshold {
+                continue;
+            }
+
+            boxes.push(OutputBox::new(
+                row[0],
+                row[1],
+                row[2],
+                row[3],
+                probability,
+                Classification::from(class_id),
+            ));
+        }
+
+        boxes.sort_by(|box1, box2| box2.probability.total_cmp(&box1.probability));
+        let mut result = Vec::<ResultBox>::new();
+        while !boxes.is_empty() {
+            let first = boxes[0];
+            result.push(first.into_result_box());
+            boxes.retain(|box1| OnnxDetector::iou(&first, box1) < self.iou_threshold)
+        }
+
+        let postprocess = stage.elapsed();
+        METRICS
+            .inference_duration
+            .with_label_values(&["postprocess"])
+            .observe(postprocess.as_secs_f64());
+
+        for item in &result {
+            METRICS
+                .detections
+                .with_label_values(&[item.classification.label()])
+                .inc();
+        }
+
+        tracing::debug!(
+            boxes = result.len(),
+            ?waited,
+            ?preprocess,
+            ?run,
+            ?postprocess,
+            "Inferred"
+        );
+
+        Ok(result)
+    }
+
+    async fn warm_up(&self) -> Result<(), Error> {
+        let input = Array::zeros((1, 3, 640, 640)).into_dyn();
+
+        self.session
+            .lock()
+            .await
+            .run([InputTensor::FloatTensor(input)])
+            .map_err(Error::internal)?;
+
+        Ok(())
+    }
+
+    fn model_version(&self) -> &str {
+        &self.version
+    }
+}
+
+#[derive(Clone, Copy)]
+struct OutputBox {
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+    width: f32,
+    height: f32,
+    probability: f32,
+    classification: Classification,
+}
+
+impl OutputBox {
+    #[inline(always)]
+    fn new(
+        x_center: f32,
+        y_center: f32,
+        width: f32,
+        height: f32,
+        probability: f32,
+        classification: Classification,
+    ) -> Self {
+        let w_half = width * 0.5;
+        let h_half = height * 0.5;
+
+        let left = x_center - w_half;
+        let top = y_center - h_half;
+        let right = x_center + w_half;
+        let bottom = y_center + h_half;
+
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+            width,
+            height,
+            probability,
+            classification,
+        }
+    }
+
+    #[inline(always)]
+    fn into_result_box(self) -> ResultBox {
+        ResultBox {
+            x: self.left,
+            y: self.top,
+            width: self.width,
+            height: self.height,
+            probability: self.probability,
+            classification: self.classification,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use image::DynamicImage;
+
+use super::{Detector, ResultBox};
+use crate::error::Error;
+
+/// Returns preconfigured boxes instead of running the model, cycling through
+/// `responses` one call at a time. Without any responses nothing is detected.
+pub(crate) struct ScriptedDetector {
+    responses: Vec<Vec<ResultBox>>,
+    version: String,
+    next: AtomicUsize,
+}
+
+impl ScriptedDetector {
+    pub(crate) fn new(responses: Vec<Vec<ResultBox>>, version: String) -> Self {
+        Self {
+            responses,
+            version,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reads a JSON array of responses, each an array of boxes as returned by
+    /// `/scan`.
+    pub(crate) fn from_file(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let responses = serde_json::from_reader(std::io::BufReader::new(file))?;
+
+        Ok(Self::new(responses, format!("scripted:{}", path.display())))
+    }
+}
+
+#[async_trait]
+impl Detector for ScriptedDetector {
+    async fn infer(&self, _image: &DynamicImage) -> Result<Vec<ResultBox>, Error> {
+        let call = self.next.fetch_add(1, Ordering::Relaxed);
+
+        if self.responses.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self.responses[call % self.responses.len()].clone())
+    }
+
+    /// Nothing to load, and the script is not advanced.
+    async fn warm_up(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn model_version(&self) -> &str {
+        &self.version
+    }
+}
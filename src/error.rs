@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+
+use crate::validation::FieldErrors;
+
+type Message = Cow<'static, str>;
+
+/// Every way a request can fail, rendered as a JSON problem body:
+///
+/// ```json
+/// { "code": "email_taken", "message": "Already exists", "fields": { "email": "Already exists" } }
+/// ```
+///
+/// `code` is stable and meant for clients to match on, `message` is for
+/// humans and `fields` is only present when specific input fields are at fault.
+#[derive(Debug)]
+pub(crate) enum Error {
+    Validation(FieldErrors),
+    BadRequest {
+        code: &'static str,
+        message: Message,
+    },
+    Unauthorized {
+        code: &'static str,
+        message: Message,
+    },
+    /// Wrong password for an action that re-confirms it.
+    IncorrectPassword {
+        field: &'static str,
+    },
+    Forbidden {
+        code: &'static str,
+        message: Message,
+    },
+    NotFound {
+        code: &'static str,
+        message: Message,
+    },
+    Conflict {
+        code: &'static str,
+        field: &'static str,
+        message: Message,
+    },
+    UnsupportedMediaType {
+        message: Message,
+    },
+    Unprocessable {
+        code: &'static str,
+        field: Option<&'static str>,
+        message: Message,
+    },
+    TooManyRequests {
+        retry_after: Duration,
+    },
+    BadGateway {
+        code: &'static str,
+        message: Message,
+    },
+    Unavailable {
+        code: &'static str,
+        message: Message,
+    },
+    /// Details are logged but never sent to the client.
+    Internal(String),
+}
+
+/// Body of every error response.
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct Problem<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<FieldErrors>,
+}
+
+impl Error {
+    #[inline]
+    pub(crate) fn internal(error: impl std::fmt::Display) -> Self {
+        Self::Internal(error.to_string())
+    }
+
+    /// Invalid value of a single field, e.g. an expired token.
+    #[inline]
+    pub(crate) fn invalid_field(
+        code: &'static str,
+        field: &'static str,
+        message: impl Into<Message>,
+    ) -> Self {
+        Self::Unprocessable {
+            code,
+            field: Some(field),
+            message: message.into(),
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(_) => "validation_failed",
+            Self::IncorrectPassword { .. } => "incorrect_password",
+            Self::UnsupportedMediaType { .. } => "unsupported_media_type",
+            Self::TooManyRequests { .. } => "rate_limited",
+            Self::Internal(_) => "internal_error",
+            Self::BadRequest { code, .. }
+            | Self::Unauthorized { code, .. }
+            | Self::Forbidden { code, .. }
+            | Self::NotFound { code, .. }
+            | Self::Conflict { code, .. }
+            | Self::Unprocessable { code, .. }
+            | Self::BadGateway { code, .. }
+            | Self::Unavailable { code, .. } => code,
+        }
+    }
+
+    fn message(&self) -> Cow<'_, str> {
+        match self {
+            Self::Validation(_) => "Some fields are invalid".into(),
+            Self::IncorrectPassword { .. } => "Incorrect password".into(),
+            Self::TooManyRequests { retry_after } => format!(
+                "Too many requests, retry in {} seconds",
+                Self::retry_after_seconds(*retry_after)
+            )
+            .into(),
+            Self::Internal(_) => "Internal server error".into(),
+            Self::BadRequest { message, .. }
+            | Self::Unauthorized { message, .. }
+            | Self::Forbidden { message, .. }
+            | Self::NotFound { message, .. }
+            | Self::Conflict { message, .. }
+            | Self::UnsupportedMediaType { message }
+            | Self::Unprocessable { message, .. }
+            | Self::BadGateway { message, .. }
+            | Self::Unavailable { message, .. } => Cow::Borrowed(message),
+        }
+    }
+
+    fn fields(&self) -> Option<FieldErrors> {
+        let (field, message) = match self {
+            Self::Validation(errors) => return Some(errors.clone()),
+            Self::IncorrectPassword { field } => (*field, "Incorrect password"),
+            Self::Conflict { field, message, .. } => (*field, message.as_ref()),
+            Self::Unprocessable {
+                field: Some(field),
+                message,
+                ..
+            } => (*field, message.as_ref()),
+            _ => return None,
+        };
+
+        let mut errors = FieldErrors::new();
+        errors.add(field, message);
+
+        Some(errors)
+    }
+
+    /// Rounded up so clients never retry a moment too early.
+    #[inline]
+    fn retry_after_seconds(retry_after: Duration) -> u64 {
+        (retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0)).max(1)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Internal(details) => write!(f, "{}: {details}", self.code()),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::Unprocessable { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::BadRequest { .. } => StatusCode::BAD_REQUEST,
+            Self::Unauthorized { .. } | Self::IncorrectPassword { .. } => StatusCode::UNAUTHORIZED,
+            Self::Forbidden { .. } => StatusCode::FORBIDDEN,
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::Conflict { .. } => StatusCode::CONFLICT,
+            Self::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::BadGateway { .. } => StatusCode::BAD_GATEWAY,
+            Self::Unavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let Self::Internal(details) = self {
+            tracing::error!("Internal error: {details}");
+        }
+
+        let mut response = HttpResponse::build(self.status_code());
+
+        if let Self::TooManyRequests { retry_after } = self {
+            response.insert_header((
+                RETRY_AFTER,
+                Self::retry_after_seconds(*retry_after).to_string(),
+            ));
+        }
+
+        response.json(Problem {
+            code: self.code(),
+            message: &self.message(),
+            fields: self.fields(),
+        })
+    }
+}
+
+impl From<FieldErrors> for Error {
+    fn from(val: FieldErrors) -> Self {
+        Self::Validation(val)
+    }
+}
+
+impl From<diesel::result::Error> for Error {
+    fn from(val: diesel::result::Error) -> Self {
+        Self::internal(val)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for Error {
+    fn from(val: diesel::r2d2::PoolError) -> Self {
+        tracing::error!("Database pool error: {val}");
+
+        Self::Unavailable {
+            code: "database_unavailable",
+            message: "Database is unavailable, try again later".into(),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(val: std::io::Error) -> Self {
+        Self::internal(val)
+    }
+}
+
+impl From<actix_multipart::MultipartError> for Error {
+    fn from(val: actix_multipart::MultipartError) -> Self {
+        Self::BadRequest {
+            code: "invalid_multipart",
+            message: val.to_string().into(),
+        }
+    }
+}
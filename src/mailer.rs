@@ -0,0 +1,126 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+pub(crate) struct Mail {
+    pub(crate) from: String,
+    pub(crate) to: String,
+    pub(crate) subject: String,
+    pub(crate) body: String,
+}
+
+/// Delivery backend for outgoing mail.
+pub(crate) trait Transport: Send + Sync {
+    fn send(&self, mail: Mail) -> std::io::Result<()>;
+}
+
+#[derive(Clone)]
+pub enum MailerConfig {
+    Log,
+    File(PathBuf),
+}
+
+pub(crate) struct Mailer {
+    transport: Box<dyn Transport>,
+    from: String,
+    password_reset_url: Option<String>,
+    email_verification_url: Option<String>,
+}
+
+impl Mailer {
+    #[inline]
+    pub(crate) fn new(
+        config: &MailerConfig,
+        from: String,
+        password_reset_url: Option<String>,
+        email_verification_url: Option<String>,
+    ) -> Self {
+        let transport: Box<dyn Transport> = match config {
+            MailerConfig::Log => Box::new(LogTransport),
+            MailerConfig::File(dir) => Box::new(FileTransport { dir: dir.clone() }),
+        };
+
+        Self {
+            transport,
+            from,
+            password_reset_url,
+            email_verification_url,
+        }
+    }
+
+    #[inline]
+    fn link(url: &Option<String>, token: &str) -> String {
+        match url {
+            Some(url) => format!("{url}?token={token}"),
+            None => token.to_string(),
+        }
+    }
+
+    pub(crate) fn send_password_reset(&self, to: &str, token: &str) -> std::io::Result<()> {
+        let link = Self::link(&self.password_reset_url, token);
+
+        self.transport.send(Mail {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: "Reset your PupSight password".to_string(),
+            body: format!(
+                "Use the following to choose a new password:\n\n{link}\n\nIt expires in one hour."
+            ),
+        })
+    }
+
+    pub(crate) fn send_email_verification(&self, to: &str, token: &str) -> std::io::Result<()> {
+        let link = Self::link(&self.email_verification_url, token);
+
+        self.transport.send(Mail {
+            from: self.from.clone(),
+            to: to.to_string(),
+            subject: "Verify your PupSight email address".to_string(),
+            body: format!(
+                "Use the following to verify your email address:\n\n{link}\n\nIt expires in 24 hours."
+            ),
+        })
+    }
+}
+
+/// Logs outgoing mail, for local development.
+pub(crate) struct LogTransport;
+
+impl Transport for LogTransport {
+    fn send(&self, mail: Mail) -> std::io::Result<()> {
+        tracing::info!(
+            from = %mail.from,
+            to = %mail.to,
+            subject = %mail.subject,
+            "Mail\n{}",
+            mail.body
+        );
+
+        Ok(())
+    }
+}
+
+/// Writes each outgoing mail as an `.eml` file into a directory, for local
+/// testing of flows that deliver tokens by mail.
+pub(crate) struct FileTransport {
+    dir: PathBuf,
+}
+
+impl Transport for FileTransport {
+    fn send(&self, mail: Mail) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        let path = self.dir.join(format!(
+            "{}-{:08x}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            rand::random::<u32>()
+        ));
+
+        let mut file = std::fs::File::create(path)?;
+
+        write!(
+            file,
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            mail.from, mail.to, mail.subject, mail.body
+        )
+    }
+}
@@ -1,92 +1,223 @@
+mod batch_scan;
+mod cli;
 mod config;
 mod database;
 mod detector;
+mod error;
+mod logging;
+mod mailer;
 mod messages;
+mod metrics;
+mod middleware;
+mod oidc;
+mod openapi;
 mod password_hasher;
+mod rate_limit;
 mod routes;
 mod schema;
+mod shutdown;
+mod tls;
+mod totp;
+mod validation;
 
-use actix_multipart::{Field, Multipart};
 use actix_web::{
     web::{self},
-    App, Error, HttpResponse, HttpServer,
+    App, HttpServer,
 };
 
-use config::ServerConfig;
-use futures::{StreamExt, TryStreamExt};
-use image::{imageops::FilterType, GenericImageView};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::{ConfigArgs, ServerConfig};
+use std::net::SocketAddr;
+use std::time::Duration;
 
 use database::Database;
 use detector::Detector;
+use error::Error;
+use mailer::Mailer;
+use oidc::{IdentityProvider, OidcClient};
 use password_hasher::PasswordHasher;
+use rate_limit::{LoginThrottle, RateLimiter};
+use shutdown::Drain;
 
 fn main() -> std::io::Result<()> {
-    let config = ServerConfig::load();
+    let cli = Cli::parse();
 
-    tokio::runtime::Builder::new_multi_thread()
+    let command = cli.command.unwrap_or(Command::Serve);
+
+    // `serve` sets up logging once its whole configuration is validated.
+    if !matches!(command, Command::Serve) {
+        logging::init(&ServerConfig::log(&cli.config).unwrap_or_else(|errors| errors.exit()));
+    }
+
+    match command {
+        Command::Serve => serve(&cli.config),
+        Command::Migrate { action } => cli::migrate(&cli.config, action),
+        Command::User { action } => cli::user(&cli.config, action),
+        Command::Samples { action } => cli::samples(&cli.config, action),
+        Command::Stats => cli::stats(&cli.config),
+        Command::Scan(args) => batch_scan::run(&cli.config, args),
+    }
+}
+
+fn serve(args: &ConfigArgs) -> std::io::Result<()> {
+    let config = ServerConfig::load(args).unwrap_or_else(|errors| errors.exit());
+    logging::init(&config.log);
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(start(config))
+        .unwrap();
+    let result = runtime.block_on(start(config));
+
+    // Tracked work has been drained by now; anything still blocking was
+    // abandoned and must not hold up the exit.
+    runtime.shutdown_background();
+
+    result
 }
 
 async fn start(config: ServerConfig) -> std::io::Result<()> {
     let server_url = config.socket_addr();
-
-    let database = web::Data::new(Database::new(&config.database_url).await);
-    let detector = web::Data::new(Detector::new());
+    let tls = config.tls.as_ref().map(tls::server_config).transpose()?;
+    let redirect = config
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.redirect_port)
+        .map(|port| SocketAddr::new(config.bind, port));
+    let json_body_limit = config.json_body_limit;
+    let legacy_paths = config.legacy_paths;
+    let shutdown = config.shutdown;
+    let drain = Drain::default();
+
+    let database = web::Data::new(Database::new(&config.database));
+    let detector: web::Data<dyn Detector> =
+        web::Data::from(detector::from_config(&config.detector));
     let hasher = web::Data::new(PasswordHasher::new(config.salt));
-
-    println!("SERVER_URL: {server_url}");
-
-    HttpServer::new(move || {
-        App::new()
+    let mailer = web::Data::new(Mailer::new(
+        &config.mailer,
+        config.mail_from,
+        config.password_reset_url,
+        config.email_verification_url,
+    ));
+    let cookies = web::Data::new(config.cookie);
+    let csrf_trusted_origins = config.csrf_trusted_origins;
+    let rate_limiter = std::sync::Arc::new(RateLimiter::new(&config.rate_limit));
+    let login_throttle = web::Data::new(LoginThrottle::new(&config.rate_limit));
+    let health = web::Data::new(routes::health::Health::new());
+    {
+        let (health, detector) = (health.clone(), detector.clone());
+        // Off the runtime threads, the model run blocks.
+        tokio::task::spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(health.warm_up(detector.as_ref()))
+        });
+    }
+    let drained = web::Data::new(drain.clone());
+    let oidc = config.oidc.map(|oidc| {
+        let provider: std::sync::Arc<dyn IdentityProvider> =
+            std::sync::Arc::new(OidcClient::new(oidc));
+        web::Data::from(provider)
+    });
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!("Listening on {scheme}://{server_url}");
+
+    let server = HttpServer::new(move || {
+        let mut app = App::new()
+            .wrap(middleware::Csrf::new(csrf_trusted_origins.clone()))
+            .wrap(middleware::RateLimit::new(rate_limiter.clone()))
+            .wrap(middleware::Metrics)
+            .wrap(middleware::RequestId)
             .app_data(database.clone())
             .app_data(detector.clone())
             .app_data(hasher.clone())
-            .service(routes::users::scope())
-            .service(routes::samples::scope())
-            .service(process_image)
+            .app_data(cookies.clone())
+            .app_data(mailer.clone())
+            .app_data(login_throttle.clone())
+            .app_data(health.clone())
+            .app_data(drained.clone())
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(json_body_limit)
+                    .error_handler(|error, _| {
+                        Error::BadRequest {
+                            code: "invalid_body",
+                            message: error.to_string().into(),
+                        }
+                        .into()
+                    }),
+            )
+            .app_data(web::QueryConfig::default().error_handler(|error, _| {
+                Error::BadRequest {
+                    code: "invalid_query",
+                    message: error.to_string().into(),
+                }
+                .into()
+            }));
+
+        if let Some(oidc) = &oidc {
+            app = app.app_data(oidc.clone());
+        }
+
+        if legacy_paths {
+            app = app.configure(routes::legacy);
+        }
+
+        app.configure(routes::configure)
+            .configure(openapi::configure)
     })
-    .bind(server_url)?
-    .run()
-    .await
-}
-
-#[actix_web::post("/scan")]
-async fn process_image(
-    (detector, mut payload): (web::Data<Detector>, Multipart),
-) -> Result<HttpResponse, Error> {
-    println!("Proccessing image");
-    if let Ok(Some(mut field)) = payload.try_next().await {
-        let file_data = get_field_filedata(&mut field).await?;
-
-        let image = {
-            let raw = image::load_from_memory(&file_data).unwrap();
-            let (width, height) = raw.dimensions();
-            let size = width.min(height);
-            let (center_x, center_y) = (width / 2, height / 2);
-            let (x, y) = (center_x - size / 2, center_y - size / 2);
+    .disable_signals()
+    .shutdown_timeout(shutdown.timeout.as_secs());
 
-            raw.crop_imm(x, y, width, height)
-                .resize_exact(640, 640, FilterType::CatmullRom)
-        };
-
-        let result = detector.infer(&image).await;
-
-        return Ok(HttpResponse::Ok().json(result));
+    let server = match tls {
+        Some(tls) => server.bind_rustls_0_22(server_url, tls)?,
+        None => server.bind(server_url)?,
+    }
+    .run();
+
+    let redirect = match redirect {
+        Some(redirect) => {
+            tracing::info!("Redirecting http://{redirect} to HTTPS");
+
+            let https_port = web::Data::new(server_url.port());
+
+            Some(
+                HttpServer::new(move || {
+                    App::new()
+                        .app_data(https_port.clone())
+                        .default_service(web::to(tls::redirect))
+                })
+                .disable_signals()
+                .bind(redirect)?
+                .run(),
+            )
+        }
+        None => None,
+    };
+
+    let handles = std::iter::once(server.handle())
+        .chain(redirect.as_ref().map(|redirect| redirect.handle()))
+        .collect();
+    tokio::spawn(shutdown::on_signal(
+        shutdown.clone(),
+        handles,
+        drain.clone(),
+    ));
+
+    match redirect {
+        Some(redirect) => futures::try_join!(server, redirect).map(|_| ())?,
+        None => server.await?,
     }
 
-    Ok(HttpResponse::NotAcceptable().finish())
-}
-
-pub async fn get_field_filedata(field: &mut Field) -> Result<Vec<u8>, Error> {
-    let mut buffer = Vec::<u8>::new();
+    let abandoned = if shutdown.drain {
+        drain.wait(shutdown.timeout).await
+    } else {
+        drain.wait(Duration::ZERO).await
+    };
 
-    while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        buffer.extend_from_slice(&data);
+    if abandoned > 0 {
+        tracing::warn!("Exiting with {abandoned} inferences or writes still running");
     }
 
-    Ok(buffer)
+    Ok(())
 }
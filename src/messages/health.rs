@@ -0,0 +1,59 @@
+use actix_web::HttpResponse;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::database::PoolState;
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct LiveResult {
+    pub(crate) status: &'static str,
+    pub(crate) version: &'static str,
+    pub(crate) uptime_seconds: u64,
+}
+
+impl From<LiveResult> for HttpResponse {
+    fn from(val: LiveResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct Check {
+    pub(crate) ok: bool,
+    pub(crate) latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DatabaseCheck {
+    #[serde(flatten)]
+    pub(crate) check: Check,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pool: Option<PoolState>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ReadyChecks {
+    pub(crate) database: DatabaseCheck,
+    pub(crate) detector: Check,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ReadyResult {
+    pub(crate) status: &'static str,
+    pub(crate) version: &'static str,
+    pub(crate) model_version: String,
+    pub(crate) uptime_seconds: u64,
+    pub(crate) checks: ReadyChecks,
+}
+
+impl From<ReadyResult> for HttpResponse {
+    fn from(val: ReadyResult) -> Self {
+        if val.checks.database.check.ok && val.checks.detector.ok {
+            HttpResponse::Ok().json(val)
+        } else {
+            HttpResponse::ServiceUnavailable().json(val)
+        }
+    }
+}
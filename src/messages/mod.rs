@@ -1,2 +1,5 @@
+pub(crate) mod health;
+pub(crate) mod pets;
 pub(crate) mod samples;
+pub(crate) mod search;
 pub(crate) mod users;
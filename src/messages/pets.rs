@@ -0,0 +1,58 @@
+use actix_web::HttpResponse;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::validation::{self, FieldErrors};
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreatePet {
+    pub(crate) name: String,
+    pub(crate) birthday: Option<NaiveDateTime>,
+    /// Whether `birthday` is the day of birth rather than an estimate.
+    #[serde(default)]
+    pub(crate) exact_birthday: bool,
+}
+
+impl CreatePet {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        errors.check_length("name", &self.name, validation::PET_NAME_MAX);
+
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CreatePetResult {
+    pub(crate) id: Uuid,
+}
+
+impl From<CreatePetResult> for HttpResponse {
+    fn from(val: CreatePetResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PetEntry {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) birthday: Option<NaiveDateTime>,
+    pub(crate) exact_birthday: bool,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PetListResult {
+    /// Oldest first.
+    pub(crate) items: Vec<PetEntry>,
+}
+
+impl From<PetListResult> for HttpResponse {
+    fn from(val: PetListResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
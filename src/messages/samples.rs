@@ -1,68 +1,170 @@
 use actix_web::HttpResponse;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use serde::{Deserialize, Serialize};
-
-#[derive(Deserialize)]
-pub(crate) struct SamplePendingList {
-    pub(crate) page: u32,
+use utoipa::{IntoParams, ToSchema};
+
+use crate::database::ReviewStatus;
+use crate::detector::Classification;
+use crate::validation::{self, FieldErrors};
+
+/// Query of both sample lists. Pending samples have no results, so the
+/// result filters and sorting by certainty only apply to the inferred list.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SampleList {
+    /// Samples per page, 10 by default and at most 100.
+    pub(crate) limit: Option<usize>,
+    /// `next_cursor` of the previous page, the first page when unset.
+    pub(crate) cursor: Option<String>,
+    /// Also count all matching samples.
+    #[serde(default)]
+    pub(crate) total: bool,
+    /// Part of the label, any case.
     pub(crate) keyword: Option<String>,
+    pub(crate) pet_id: Option<uuid::Uuid>,
+    /// First upload day, inclusive.
+    pub(crate) created_from: Option<NaiveDate>,
+    /// Last upload day, inclusive.
+    pub(crate) created_to: Option<NaiveDate>,
+    pub(crate) review_status: Option<ReviewStatus>,
+    /// Samples with a result of this class.
+    pub(crate) classification: Option<Classification>,
+    /// Samples with a result at least this certain, from 0 to 1.
+    pub(crate) min_certainty: Option<f32>,
+    /// Samples with a result from this model, as reported by `/health/ready`.
+    pub(crate) model_version: Option<String>,
+    #[serde(default)]
+    pub(crate) sort: SampleSort,
+    /// Newest, most certain or alphabetically first when unset.
+    pub(crate) order: Option<SortOrder>,
+}
+
+impl SampleList {
+    /// `results` tells whether the listed samples have results to filter on.
+    pub(crate) fn validate(&self, results: bool) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        if let (Some(from), Some(to)) = (self.created_from, self.created_to) {
+            if from > to {
+                errors.add("created_to", "Must not be before created_from");
+            }
+        }
+
+        if self
+            .min_certainty
+            .is_some_and(|certainty| !(0.0..=1.0).contains(&certainty))
+        {
+            errors.add("min_certainty", "Must be between 0 and 1");
+        }
+
+        if !results {
+            let no_results = "Pending samples have no results";
+
+            if self.classification.is_some() {
+                errors.add("classification", no_results);
+            }
+            if self.min_certainty.is_some() {
+                errors.add("min_certainty", no_results);
+            }
+            if self.model_version.is_some() {
+                errors.add("model_version", no_results);
+            }
+            if self.sort == SampleSort::Certainty {
+                errors.add("sort", no_results);
+            }
+        }
+
+        errors.into_result()
+    }
 }
 
-#[derive(Deserialize)]
-pub(crate) struct SampleInferredList {
-    pub(crate) page: u32,
-    pub(crate) keyword: Option<String>,
+/// What a sample list is ordered by, the sample id breaking ties.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SampleSort {
+    /// Upload time.
+    #[default]
+    Date,
+    /// Highest certainty among the results.
+    Certainty,
+    /// Label by code point, uppercase before lowercase, whatever the
+    /// locale of the database.
+    Label,
+}
+
+impl SampleSort {
+    pub(crate) const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::Certainty => "certainty",
+            Self::Label => "label",
+        }
+    }
+
+    pub(crate) const fn default_order(&self) -> SortOrder {
+        match self {
+            Self::Date | Self::Certainty => SortOrder::Desc,
+            Self::Label => SortOrder::Asc,
+        }
+    }
 }
 
-#[derive(Serialize)]
-pub(crate) struct PendingListEntry {
-    pub(crate) id: uuid::Uuid,
-    pub(crate) label: String,
-    pub(crate) pet_id: Option<uuid::Uuid>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SortOrder {
+    Asc,
+    Desc,
 }
 
-#[derive(Serialize)]
-pub(crate) struct PendingListData {
-    items: Vec<PendingListEntry>,
-    has_next: bool,
+impl SortOrder {
+    pub(crate) const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        }
+    }
 }
 
-#[derive(Serialize)]
-pub(crate) struct InferredListData {
-    items: Vec<InferredListEntry>,
-    has_next: bool,
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PendingListEntry {
+    pub(crate) id: uuid::Uuid,
+    pub(crate) label: String,
+    pub(crate) pet_id: Option<uuid::Uuid>,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) review_status: ReviewStatus,
+    pub(crate) notes: Option<String>,
 }
 
-pub(crate) enum PendingListResult {
-    Success {
-        items: Vec<PendingListEntry>,
-        has_next: bool,
-    },
-    Failed,
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PendingListResult {
+    pub(crate) items: Vec<PendingListEntry>,
+    pub(crate) has_next: bool,
+    /// Pass as `cursor` to get the following page.
+    pub(crate) next_cursor: Option<String>,
+    /// Matching samples over all pages, when asked for with `total`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) total: Option<i64>,
 }
 
 impl From<PendingListResult> for HttpResponse {
     fn from(val: PendingListResult) -> Self {
-        match val {
-            PendingListResult::Success { items, has_next } => {
-                HttpResponse::Ok().json(PendingListData { items, has_next })
-            }
-            PendingListResult::Failed => HttpResponse::ServiceUnavailable().finish(),
-        }
+        HttpResponse::Ok().json(val)
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub(crate) struct InferredListEntry {
     pub id: uuid::Uuid,
     pub label: String,
     pub pet_id: Option<uuid::Uuid>,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
+    pub review_status: ReviewStatus,
+    pub notes: Option<String>,
     pub results: Vec<InferredResultListEntry>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub(crate) struct InferredResultListEntry {
     pub id: uuid::Uuid,
     pub certainty: f32,
@@ -76,84 +178,75 @@ pub(crate) struct InferredResultListEntry {
     pub iris_a: Option<f32>,
     pub iris_b: Option<f32>,
     pub coverage: Option<f32>,
+    /// Unknown for results stored before versions were recorded.
+    pub model_version: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
 }
 
-pub(crate) enum InferredListResult {
-    Success {
-        items: Vec<InferredListEntry>,
-        has_next: bool,
-    },
-    Failed,
+#[derive(Serialize, ToSchema)]
+pub(crate) struct InferredListResult {
+    pub(crate) items: Vec<InferredListEntry>,
+    pub(crate) has_next: bool,
+    /// Pass as `cursor` to get the following page.
+    pub(crate) next_cursor: Option<String>,
+    /// Matching samples over all pages, when asked for with `total`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) total: Option<i64>,
 }
 
 impl From<InferredListResult> for HttpResponse {
     fn from(val: InferredListResult) -> Self {
-        match val {
-            InferredListResult::Success { items, has_next } => {
-                HttpResponse::Ok().json(InferredListData { items, has_next })
-            }
-            InferredListResult::Failed => HttpResponse::ServiceUnavailable().finish(),
-        }
+        HttpResponse::Ok().json(val)
     }
 }
 
-pub(crate) enum SampleUploadResult {
-    Success,
-    Failed,
+#[derive(Deserialize, IntoParams, ToSchema)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct SampleImage {
+    pub(crate) sample_id: uuid::Uuid,
 }
 
-impl From<SampleUploadResult> for HttpResponse {
-    fn from(val: SampleUploadResult) -> Self {
-        match val {
-            SampleUploadResult::Success => HttpResponse::Ok().finish(),
-            SampleUploadResult::Failed => HttpResponse::UnprocessableEntity().finish(),
-        }
-    }
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SampleReview {
+    pub(crate) sample_id: uuid::Uuid,
+    pub(crate) review_status: ReviewStatus,
 }
 
-#[derive(Deserialize)]
-pub(crate) struct SampleImage {
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SamplePet {
     pub(crate) sample_id: uuid::Uuid,
+    /// One of your pets. Null unassigns the sample.
+    pub(crate) pet_id: Option<uuid::Uuid>,
 }
 
-pub(crate) enum SampleImageResult {
-    Success { bytes: Vec<u8> },
-    NotFound,
-    ServerError,
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct SampleNotes {
+    pub(crate) sample_id: uuid::Uuid,
+    /// Clinical notes, found by search. Null removes them.
+    pub(crate) notes: Option<String>,
 }
 
-impl From<SampleImageResult> for HttpResponse {
-    fn from(val: SampleImageResult) -> Self {
-        match val {
-            SampleImageResult::Success { bytes } => {
-                HttpResponse::Ok().content_type("image/webp").body(bytes)
-            }
-            SampleImageResult::NotFound => HttpResponse::NotFound().finish(),
-            SampleImageResult::ServerError => HttpResponse::InternalServerError().finish(),
+impl SampleNotes {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        if let Some(notes) = &self.notes {
+            errors.check_length("notes", notes, validation::NOTES_MAX);
         }
+
+        errors.into_result()
     }
 }
 
-pub(crate) enum SampleInferResult {
-    Success,
-    NotFound,
-    Reject,
-    ImageLoadError,
-    ServerError,
+pub(crate) struct SampleImageResult {
+    pub(crate) bytes: Vec<u8>,
 }
 
-impl From<SampleInferResult> for HttpResponse {
-    fn from(val: SampleInferResult) -> Self {
-        match val {
-            SampleInferResult::Success => HttpResponse::Accepted().finish(),
-            SampleInferResult::Reject => HttpResponse::UnprocessableEntity().finish(),
-            SampleInferResult::NotFound => HttpResponse::NotFound().body("Id not found"),
-            SampleInferResult::ImageLoadError => {
-                HttpResponse::InternalServerError().body("ImageLoadError")
-            }
-            SampleInferResult::ServerError => HttpResponse::InternalServerError().finish(),
-        }
+impl From<SampleImageResult> for HttpResponse {
+    fn from(val: SampleImageResult) -> Self {
+        HttpResponse::Ok()
+            .content_type("image/webp")
+            .body(val.bytes)
     }
 }
@@ -0,0 +1,70 @@
+use actix_web::HttpResponse;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::validation::{self, FieldErrors};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct Search {
+    /// Words to find in sample labels, pet names and clinical notes. Quoted
+    /// phrases, `or` and `-word` work like in web search engines.
+    pub(crate) q: String,
+    /// Hits to return, 10 by default and at most 100.
+    pub(crate) limit: Option<usize>,
+}
+
+impl Search {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        errors.check_length("q", &self.q, validation::SEARCH_QUERY_MAX);
+
+        errors.into_result()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SearchKind {
+    Sample,
+    Pet,
+}
+
+impl SearchKind {
+    /// Unknown values read as samples.
+    pub(crate) fn parse(value: &str) -> Self {
+        match value {
+            "pet" => Self::Pet,
+            _ => Self::Sample,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SearchHit {
+    pub(crate) kind: SearchKind,
+    /// Id of the sample or pet.
+    pub(crate) id: uuid::Uuid,
+    /// Sample label or pet name.
+    pub(crate) title: String,
+    /// Pet a sample is assigned to.
+    pub(crate) pet_id: Option<uuid::Uuid>,
+    pub(crate) created_at: NaiveDateTime,
+    /// Relevance, only comparable between hits of the same search. Label
+    /// and name matches weigh more than matches in notes.
+    pub(crate) rank: f32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SearchResult {
+    /// Most relevant first.
+    pub(crate) items: Vec<SearchHit>,
+}
+
+impl From<SearchResult> for HttpResponse {
+    fn from(val: SearchResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
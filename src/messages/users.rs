@@ -1,69 +1,343 @@
-use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use actix_web::{cookie::Cookie, HttpResponse};
-use serde::Deserialize;
+use actix_web::cookie::{time::Duration, SameSite};
+use actix_web::http::header::LOCATION;
+use actix_web::HttpResponse;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+use crate::config::CookieConfig;
+use crate::database::{BearerToken, Scope};
+use crate::middleware::csrf;
+use crate::validation::{self, FieldErrors};
+
+#[derive(Deserialize, ToSchema)]
 pub(crate) struct RegisterUser {
     pub(crate) login_name: String,
     pub(crate) first_name: String,
     pub(crate) last_name: String,
     pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) email: Option<String>,
+}
+
+impl RegisterUser {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        errors.check_length("login_name", &self.login_name, validation::LOGIN_NAME_MAX);
+        errors.check_length("first_name", &self.first_name, validation::PERSON_NAME_MAX);
+        errors.check_length("last_name", &self.last_name, validation::PERSON_NAME_MAX);
+
+        if self.password.is_empty() {
+            errors.add("password", "Required");
+        }
+
+        if let Some(email) = &self.email {
+            errors.check_email("email", email);
+        }
+
+        errors.into_result()
+    }
 }
 
-pub(crate) enum RegisterUserResult {
-    LoginNameAlreadyExists,
-    ServerError,
-    Success { id: Uuid },
+#[derive(Serialize, ToSchema)]
+pub(crate) struct RegisterUserResult {
+    pub(crate) id: Uuid,
 }
 
 impl From<RegisterUserResult> for HttpResponse {
     fn from(val: RegisterUserResult) -> Self {
-        match val {
-            RegisterUserResult::LoginNameAlreadyExists => HttpResponse::Conflict().json(json!({
-                "login_name": "Already exists"
-            })),
-            RegisterUserResult::ServerError => HttpResponse::InternalServerError().finish(),
-            RegisterUserResult::Success { id } => HttpResponse::Ok().json(json!({
-                "id": id
-            })),
-        }
+        HttpResponse::Ok().json(val)
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub(crate) struct LoginUser {
     pub(crate) login_name: String,
     pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) bearer: bool,
+}
+
+/// Login answer when `bearer` was requested; cookie logins get no body.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct BearerLogin {
+    pub(crate) token: String,
+}
+
+/// Login answer when the account has a second factor to complete with
+/// `/users/login/totp`.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SecondFactorChallenge {
+    pub(crate) challenge_id: Uuid,
+    pub(crate) second_factor: &'static str,
 }
 
 pub(crate) enum LoginUserResult {
     Success {
         id: uuid::Uuid,
         access_token: String,
+        bearer: bool,
+    },
+    SecondFactorRequired {
+        challenge_id: uuid::Uuid,
     },
-    Invalid,
-    ServerError,
-}
-
-impl From<LoginUserResult> for HttpResponse {
-    fn from(val: LoginUserResult) -> Self {
-        match val {
-            LoginUserResult::Success { id, access_token } => HttpResponse::Ok()
-                .cookie({
-                    let mut ck = Cookie::new("session", id.to_string());
-                    ck.set_path("/");
-                    ck
-                })
-                .cookie({
-                    let mut ck = Cookie::new("access_token", access_token);
-                    ck.set_path("/");
-                    ck
-                })
+}
+
+impl LoginUserResult {
+    pub(crate) fn respond(self, cookies: &CookieConfig) -> HttpResponse {
+        match self {
+            LoginUserResult::Success {
+                id,
+                access_token,
+                bearer: true,
+            } => HttpResponse::Ok().json(BearerLogin {
+                token: BearerToken::format_session(id, &access_token),
+            }),
+            LoginUserResult::Success {
+                id,
+                access_token,
+                bearer: false,
+            } => HttpResponse::Ok()
+                .cookie(cookies.build("session", id.to_string(), true))
+                .cookie(cookies.build("access_token", access_token, true))
+                .cookie(cookies.build(csrf::COOKIE_NAME, csrf::generate_token(), false))
+                .finish(),
+            LoginUserResult::SecondFactorRequired { challenge_id } => HttpResponse::Accepted()
+                .json(SecondFactorChallenge {
+                    challenge_id,
+                    second_factor: "totp",
+                }),
+        }
+    }
+
+    /// Browser flows (OIDC callback) land on a page rather than a JSON API, so
+    /// a cookie session is followed by a redirect.
+    pub(crate) fn redirect(self, cookies: &CookieConfig, location: &str) -> HttpResponse {
+        match self {
+            LoginUserResult::Success {
+                id, access_token, ..
+            } => HttpResponse::SeeOther()
+                .insert_header((LOCATION, location.to_string()))
+                .cookie(cookies.build("session", id.to_string(), true))
+                .cookie(cookies.build("access_token", access_token, true))
+                .cookie(cookies.build(csrf::COOKIE_NAME, csrf::generate_token(), false))
                 .finish(),
-            LoginUserResult::ServerError => HttpResponse::InternalServerError().finish(),
-            LoginUserResult::Invalid => HttpResponse::Unauthorized().finish(),
+            result => result.respond(cookies),
         }
     }
 }
+
+pub(crate) const OIDC_STATE_COOKIE: &str = "oidc_state";
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct OidcCallback {
+    pub(crate) state: String,
+    pub(crate) code: Option<String>,
+    pub(crate) error: Option<String>,
+}
+
+pub(crate) struct OidcRedirect {
+    pub(crate) url: String,
+    pub(crate) state: String,
+}
+
+impl OidcRedirect {
+    pub(crate) fn respond(self, cookies: &CookieConfig) -> HttpResponse {
+        let mut cookie = cookies.build(OIDC_STATE_COOKIE, self.state, true);
+        // Must survive the cross-site redirect back from the provider.
+        cookie.set_same_site(SameSite::Lax);
+        cookie.set_max_age(Duration::minutes(10));
+
+        HttpResponse::Found()
+            .insert_header((LOCATION, self.url))
+            .cookie(cookie)
+            .finish()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct LoginSecondFactor {
+    pub(crate) challenge_id: Uuid,
+    pub(crate) code: String,
+    #[serde(default)]
+    pub(crate) bearer: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateApiToken {
+    pub(crate) name: String,
+    pub(crate) scopes: Vec<Scope>,
+}
+
+impl CreateApiToken {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        errors.check_length("name", &self.name, validation::API_TOKEN_NAME_MAX);
+
+        errors.into_result()
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CreateApiTokenResult {
+    pub(crate) id: Uuid,
+    pub(crate) token: String,
+}
+
+impl From<CreateApiTokenResult> for HttpResponse {
+    fn from(val: CreateApiTokenResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiTokenEntry {
+    pub(crate) id: Uuid,
+    pub(crate) name: String,
+    pub(crate) scopes: Vec<Scope>,
+    pub(crate) last_used_at: Option<NaiveDateTime>,
+    pub(crate) created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiTokenListResult {
+    pub(crate) items: Vec<ApiTokenEntry>,
+}
+
+impl From<ApiTokenListResult> for HttpResponse {
+    fn from(val: ApiTokenListResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RevokeApiToken {
+    pub(crate) token_id: Uuid,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ChangePassword {
+    pub(crate) current_password: String,
+    pub(crate) new_password: String,
+}
+
+impl ChangePassword {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        validate_new_password(&self.new_password)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ForgotPassword {
+    pub(crate) login_name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct ResetPassword {
+    pub(crate) token: String,
+    pub(crate) new_password: String,
+}
+
+impl ResetPassword {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        validate_new_password(&self.new_password)
+    }
+}
+
+/// Held to what registration accepts.
+fn validate_new_password(password: &str) -> Result<(), FieldErrors> {
+    let mut errors = FieldErrors::new();
+
+    if password.is_empty() {
+        errors.add("new_password", "Required");
+    }
+
+    errors.into_result()
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct DeleteAccount {
+    pub(crate) password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Profile {
+    pub(crate) id: Uuid,
+    pub(crate) login_name: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified: bool,
+}
+
+impl From<Profile> for HttpResponse {
+    fn from(val: Profile) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateProfile {
+    pub(crate) first_name: Option<String>,
+    pub(crate) last_name: Option<String>,
+    pub(crate) email: Option<String>,
+}
+
+impl UpdateProfile {
+    pub(crate) fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+
+        if let Some(first_name) = &self.first_name {
+            errors.check_length("first_name", first_name, validation::PERSON_NAME_MAX);
+        }
+
+        if let Some(last_name) = &self.last_name {
+            errors.check_length("last_name", last_name, validation::PERSON_NAME_MAX);
+        }
+
+        if let Some(email) = &self.email {
+            errors.check_email("email", email);
+        }
+
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct VerifyEmail {
+    pub(crate) token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct EnrollTotpResult {
+    pub(crate) secret: String,
+    pub(crate) provisioning_uri: String,
+}
+
+impl From<EnrollTotpResult> for HttpResponse {
+    fn from(val: EnrollTotpResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct TotpCode {
+    pub(crate) code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ConfirmTotpResult {
+    pub(crate) recovery_codes: Vec<String>,
+}
+
+impl From<ConfirmTotpResult> for HttpResponse {
+    fn from(val: ConfirmTotpResult) -> Self {
+        HttpResponse::Ok().json(val)
+    }
+}
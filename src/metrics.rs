@@ -0,0 +1,123 @@
+use std::sync::LazyLock;
+
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::database::PoolState;
+
+/// Everything exported on `/metrics`. Global so the detector and upload
+/// handling can record without threading a handle through every call.
+pub(crate) static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub(crate) struct Metrics {
+    registry: Registry,
+    /// By method, matched route pattern and status.
+    pub(crate) http_requests: IntCounterVec,
+    pub(crate) http_duration: HistogramVec,
+    /// By stage: preprocess, run or postprocess.
+    pub(crate) inference_duration: HistogramVec,
+    pub(crate) session_lock_wait: Histogram,
+    pub(crate) detections: IntCounterVec,
+    pub(crate) upload_bytes: Histogram,
+    pool_connections: IntGauge,
+    pool_idle: IntGauge,
+    pool_max_size: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("pupsight".to_string()), None).unwrap();
+
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "Time from receiving a request to producing its response",
+                ),
+                &["method", "route"],
+            )
+            .unwrap(),
+            inference_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "inference_duration_seconds",
+                    "Time spent in each stage of an inference",
+                )
+                .buckets(exponential_buckets(0.001, 2.0, 14).unwrap()),
+                &["stage"],
+            )
+            .unwrap(),
+            session_lock_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "inference_session_wait_seconds",
+                    "Time an inference waited for the model session",
+                )
+                .buckets(exponential_buckets(0.0001, 2.0, 18).unwrap()),
+            )
+            .unwrap(),
+            detections: IntCounterVec::new(
+                Opts::new("detections_total", "Boxes returned by the detector"),
+                &["class"],
+            )
+            .unwrap(),
+            upload_bytes: Histogram::with_opts(
+                HistogramOpts::new("upload_size_bytes", "Size of uploaded images")
+                    .buckets(exponential_buckets(16.0 * 1024.0, 2.0, 12).unwrap()),
+            )
+            .unwrap(),
+            pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Open database connections, busy or idle",
+            )
+            .unwrap(),
+            pool_idle: IntGauge::new("db_pool_idle_connections", "Idle database connections")
+                .unwrap(),
+            pool_max_size: IntGauge::new(
+                "db_pool_max_size",
+                "Most database connections the pool opens",
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 9] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_duration.clone()),
+            Box::new(metrics.inference_duration.clone()),
+            Box::new(metrics.session_lock_wait.clone()),
+            Box::new(metrics.detections.clone()),
+            Box::new(metrics.upload_bytes.clone()),
+            Box::new(metrics.pool_connections.clone()),
+            Box::new(metrics.pool_idle.clone()),
+            Box::new(metrics.pool_max_size.clone()),
+        ];
+
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+
+    /// Pool gauges are sampled at scrape time rather than tracked.
+    pub(crate) fn set_pool(&self, pool: PoolState) {
+        self.pool_connections.set(pool.connections.into());
+        self.pool_idle.set(pool.idle.into());
+        self.pool_max_size.set(pool.max_size.into());
+    }
+
+    /// Prometheus text exposition format.
+    pub(crate) fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
@@ -0,0 +1,260 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{AUTHORIZATION, ORIGIN};
+use actix_web::http::Method;
+use actix_web::{Error, ResponseError};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use futures::future::LocalBoxFuture;
+
+pub(crate) const COOKIE_NAME: &str = "csrf_token";
+pub(crate) const HEADER_NAME: &str = "X-CSRF-Token";
+
+#[inline]
+pub(crate) fn generate_token() -> String {
+    BASE64_STANDARD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Rejects cookie-authenticated requests that change state unless they come
+/// from a trusted origin and echo the `csrf_token` cookie in `X-CSRF-Token`.
+///
+/// Requests carrying an `Authorization` header or no `session` cookie are not
+/// exposed to CSRF and pass through untouched.
+pub(crate) struct Csrf {
+    trusted_origins: Rc<[String]>,
+}
+
+impl Csrf {
+    pub(crate) fn new(trusted_origins: Vec<String>) -> Self {
+        Self {
+            trusted_origins: trusted_origins.into(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Csrf
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddleware {
+            service: Rc::new(service),
+            trusted_origins: self.trusted_origins.clone(),
+        }))
+    }
+}
+
+pub(crate) struct CsrfMiddleware<S> {
+    service: Rc<S>,
+    trusted_origins: Rc<[String]>,
+}
+
+impl<S> CsrfMiddleware<S> {
+    fn is_exempt(req: &ServiceRequest) -> bool {
+        matches!(
+            *req.method(),
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+        ) || req.headers().contains_key(AUTHORIZATION)
+            || req.cookie("session").is_none()
+    }
+
+    fn check_origin(&self, req: &ServiceRequest) -> Result<(), &'static str> {
+        let Some(origin) = req.headers().get(ORIGIN) else {
+            return Ok(());
+        };
+
+        let origin = origin.to_str().map_err(|_| "Invalid origin")?;
+        let own_origin = {
+            let info = req.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        };
+
+        if origin == own_origin || self.trusted_origins.iter().any(|item| item == origin) {
+            Ok(())
+        } else {
+            Err("Untrusted origin")
+        }
+    }
+
+    fn check_token(req: &ServiceRequest) -> Result<(), &'static str> {
+        let cookie = req.cookie(COOKIE_NAME).ok_or("Missing token cookie")?;
+        let header = req
+            .headers()
+            .get(HEADER_NAME)
+            .and_then(|value| value.to_str().ok())
+            .ok_or("Missing token header")?;
+
+        if !cookie.value().is_empty() && cookie.value() == header {
+            Ok(())
+        } else {
+            Err("Token mismatch")
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !Self::is_exempt(&req) {
+            if let Err(reason) = self
+                .check_origin(&req)
+                .and_then(|_| Self::check_token(&req))
+            {
+                let response = crate::error::Error::Forbidden {
+                    code: "csrf_failed",
+                    message: reason.into(),
+                }
+                .error_response();
+
+                return Box::pin(
+                    async move { Ok(req.into_response(response).map_into_right_body()) },
+                );
+            }
+        }
+
+        let service = self.service.clone();
+
+        Box::pin(async move { Ok(service.call(req).await?.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::cookie::{time::Duration, Cookie, SameSite};
+    use actix_web::http::header::{AUTHORIZATION, HOST, ORIGIN};
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    use super::{Csrf, COOKIE_NAME, HEADER_NAME};
+    use crate::config::CookieConfig;
+    use crate::messages::users::LoginUserResult;
+
+    const TOKEN: &str = "double-submitted";
+
+    /// A POST from a logged in browser, with the token echoed when given.
+    fn cookie_post(token: Option<&str>) -> TestRequest {
+        let request = TestRequest::post()
+            .uri("/change")
+            .insert_header((HOST, "pupsight.test"))
+            .cookie(Cookie::new("session", "session-id"))
+            .cookie(Cookie::new(COOKIE_NAME, TOKEN));
+
+        match token {
+            Some(token) => request.insert_header((HEADER_NAME, token)),
+            None => request,
+        }
+    }
+
+    async fn status(request: TestRequest) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .wrap(Csrf::new(vec!["https://app.pupsight.test".to_string()]))
+                .route("/change", web::post().to(HttpResponse::Ok))
+                .route("/change", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        call_service(&app, request.to_request()).await.status()
+    }
+
+    #[actix_web::test]
+    async fn cookie_requests_need_the_token() {
+        assert_eq!(status(cookie_post(None)).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(cookie_post(Some("guessed"))).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(status(cookie_post(Some(TOKEN))).await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn cross_site_origins_are_rejected() {
+        let untrusted = cookie_post(Some(TOKEN)).insert_header((ORIGIN, "https://evil.test"));
+        assert_eq!(status(untrusted).await, StatusCode::FORBIDDEN);
+
+        let own = cookie_post(Some(TOKEN)).insert_header((ORIGIN, "http://pupsight.test"));
+        assert_eq!(status(own).await, StatusCode::OK);
+
+        let trusted = cookie_post(Some(TOKEN)).insert_header((ORIGIN, "https://app.pupsight.test"));
+        assert_eq!(status(trusted).await, StatusCode::OK);
+    }
+
+    /// Safe methods, bearer requests and requests without a session are not
+    /// exposed to CSRF.
+    #[actix_web::test]
+    async fn exempt_requests_pass() {
+        let safe = TestRequest::get()
+            .uri("/change")
+            .cookie(Cookie::new("session", "session-id"))
+            .insert_header((ORIGIN, "https://evil.test"));
+        assert_eq!(status(safe).await, StatusCode::OK);
+
+        let bearer = cookie_post(None).insert_header((AUTHORIZATION, "Bearer token"));
+        assert_eq!(status(bearer).await, StatusCode::OK);
+
+        let anonymous = TestRequest::post().uri("/change");
+        assert_eq!(status(anonymous).await, StatusCode::OK);
+    }
+
+    /// Session cookies are out of reach of scripts, the token cookie is read
+    /// by the app to echo it.
+    #[test]
+    fn login_cookie_attributes() {
+        let cookies = CookieConfig {
+            secure: true,
+            same_site: SameSite::Strict,
+            max_age: Duration::days(7),
+            domain: None,
+        };
+        let response = LoginUserResult::Success {
+            id: uuid::Uuid::new_v4(),
+            access_token: "access".to_string(),
+            bearer: false,
+        }
+        .respond(&cookies);
+
+        let cookies: Vec<_> = response.cookies().collect();
+        assert_eq!(cookies.len(), 3);
+
+        for cookie in cookies {
+            assert_eq!(cookie.secure(), Some(true), "{}", cookie.name());
+            assert_eq!(
+                cookie.same_site(),
+                Some(SameSite::Strict),
+                "{}",
+                cookie.name()
+            );
+            assert_eq!(
+                cookie.max_age(),
+                Some(Duration::days(7)),
+                "{}",
+                cookie.name()
+            );
+            assert_eq!(
+                cookie.http_only().unwrap_or(false),
+                cookie.name() != COOKIE_NAME,
+                "{}",
+                cookie.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue, LINK};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+
+pub(crate) const HEADER_NAME: HeaderName = HeaderName::from_static("deprecation");
+
+/// When the unversioned paths were deprecated, as an RFC 9745 date.
+const DEPRECATED_SINCE: HeaderValue = HeaderValue::from_static("@1792368000");
+
+/// Marks responses of a legacy alias as deprecated and links the same path
+/// under `successor`, so clients can find where to move before it goes away.
+pub(crate) struct Deprecated {
+    successor: &'static str,
+}
+
+impl Deprecated {
+    pub(crate) fn new(successor: &'static str) -> Self {
+        Self { successor }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Deprecated
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = DeprecatedMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeprecatedMiddleware {
+            service: Rc::new(service),
+            successor: self.successor,
+        }))
+    }
+}
+
+pub(crate) struct DeprecatedMiddleware<S> {
+    service: Rc<S>,
+    successor: &'static str,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecatedMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let link = format!(
+            "<{}{}>; rel=\"successor-version\"",
+            self.successor,
+            req.path()
+        );
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+
+            headers.insert(HEADER_NAME, DEPRECATED_SINCE);
+            if let Ok(link) = HeaderValue::from_str(&link) {
+                headers.append(LINK, link);
+            }
+
+            Ok(res)
+        })
+    }
+}
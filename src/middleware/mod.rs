@@ -0,0 +1,11 @@
+pub(crate) mod csrf;
+pub(crate) mod deprecated;
+pub(crate) mod metrics;
+pub(crate) mod rate_limit;
+pub(crate) mod request_id;
+
+pub(crate) use csrf::Csrf;
+pub(crate) use deprecated::Deprecated;
+pub(crate) use metrics::Metrics;
+pub(crate) use rate_limit::RateLimit;
+pub(crate) use request_id::RequestId;
@@ -0,0 +1,107 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+use tracing::Instrument;
+
+pub(crate) const HEADER_NAME: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest `X-Request-Id` taken over from a caller.
+const MAX_LENGTH: usize = 128;
+
+/// Runs each request inside a span carrying its id, so every event logged
+/// while handling it, down to database and detector work, can be correlated.
+/// A caller supplied `X-Request-Id` is kept, otherwise one is generated, and
+/// it is echoed back on the response.
+pub(crate) struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct RequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S> RequestIdMiddleware<S> {
+    fn request_id(req: &ServiceRequest) -> String {
+        req.headers()
+            .get(&HEADER_NAME)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| {
+                !value.is_empty()
+                    && value.len() <= MAX_LENGTH
+                    && value
+                        .bytes()
+                        .all(|byte| byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte))
+            })
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = Self::request_id(&req);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        let service = self.service.clone();
+
+        Box::pin(
+            async move {
+                let started = Instant::now();
+                let result = service.call(req).await;
+                let latency_ms = started.elapsed().as_millis() as u64;
+
+                match result {
+                    Ok(mut res) => {
+                        tracing::info!(status = res.status().as_u16(), latency_ms, "Handled");
+
+                        if let Ok(value) = HeaderValue::from_str(&request_id) {
+                            res.headers_mut().insert(HEADER_NAME, value);
+                        }
+
+                        Ok(res)
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, latency_ms, "Failed");
+                        Err(e)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
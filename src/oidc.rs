@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+};
+use tokio::sync::OnceCell;
+
+use crate::error::Error;
+
+#[derive(Clone)]
+pub struct OidcConfig {
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    pub post_login_redirect: String,
+}
+
+/// Pending authorization request, kept until the provider redirects back.
+pub(crate) struct AuthorizationRequest {
+    pub(crate) url: String,
+    pub(crate) state: String,
+    pub(crate) pkce_verifier: String,
+    pub(crate) nonce: String,
+}
+
+/// Claims of a verified ID token that are used to find or create the user.
+pub(crate) struct Identity {
+    pub(crate) issuer: String,
+    pub(crate) subject: String,
+    pub(crate) preferred_username: Option<String>,
+    pub(crate) email: Option<String>,
+    pub(crate) email_verified: bool,
+    pub(crate) given_name: Option<String>,
+    pub(crate) family_name: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct OidcError(String);
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl OidcError {
+    pub(crate) fn new(context: &str, error: impl std::fmt::Display) -> Self {
+        Self(format!("{context}: {error}"))
+    }
+}
+
+impl From<OidcError> for Error {
+    fn from(val: OidcError) -> Self {
+        tracing::warn!("OIDC error: {val}");
+
+        Error::BadGateway {
+            code: "identity_provider_error",
+            message: "Identity provider request failed".into(),
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn invalid_state() -> Error {
+    Error::BadRequest {
+        code: "invalid_oidc_state",
+        message: "Invalid or expired login state".into(),
+    }
+}
+
+/// Where single sign-on logins are authorized and their codes redeemed.
+#[async_trait]
+pub(crate) trait IdentityProvider: Send + Sync {
+    /// Starts a login; the browser is sent to the returned url.
+    async fn authorize(&self) -> Result<AuthorizationRequest, OidcError>;
+
+    /// Redeems the code the provider sent back, with the PKCE verifier and
+    /// nonce of the authorization request it answers.
+    async fn exchange(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<Identity, OidcError>;
+
+    /// Where the browser lands once logged in.
+    fn post_login_redirect(&self) -> &str;
+}
+
+/// OpenID Connect relying party using the authorization code flow with PKCE.
+///
+/// Provider metadata is discovered on first use rather than at startup so the
+/// server still boots while the identity provider is unreachable.
+pub(crate) struct OidcClient {
+    config: OidcConfig,
+    client: OnceCell<CoreClient>,
+}
+
+impl OidcClient {
+    pub(crate) fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            client: OnceCell::new(),
+        }
+    }
+
+    async fn client(&self) -> Result<&CoreClient, OidcError> {
+        self.client
+            .get_or_try_init(|| async {
+                let issuer = IssuerUrl::new(self.config.issuer_url.clone())
+                    .map_err(|e| OidcError::new("Invalid issuer url", e))?;
+                let metadata = CoreProviderMetadata::discover_async(issuer, async_http_client)
+                    .await
+                    .map_err(|e| OidcError::new("Discovery failed", e))?;
+                let redirect = RedirectUrl::new(self.config.redirect_url.clone())
+                    .map_err(|e| OidcError::new("Invalid redirect url", e))?;
+
+                Ok(CoreClient::from_provider_metadata(
+                    metadata,
+                    ClientId::new(self.config.client_id.clone()),
+                    self.config.client_secret.clone().map(ClientSecret::new),
+                )
+                .set_redirect_uri(redirect))
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for OidcClient {
+    async fn authorize(&self) -> Result<AuthorizationRequest, OidcError> {
+        let client = self.client().await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .set_pkce_challenge(pkce_challenge);
+
+        for scope in &self.config.scopes {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+
+        let (url, state, nonce) = request.url();
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state: state.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+            nonce: nonce.secret().clone(),
+        })
+    }
+
+    async fn exchange(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<Identity, OidcError> {
+        let client = self.client().await?;
+
+        let response = client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| OidcError::new("Token exchange failed", e))?;
+
+        let id_token = response
+            .id_token()
+            .ok_or_else(|| OidcError("Provider returned no ID token".to_string()))?;
+
+        let claims = id_token
+            .claims(&client.id_token_verifier(), &Nonce::new(nonce))
+            .map_err(|e| OidcError::new("Invalid ID token", e))?;
+
+        Ok(Identity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            preferred_username: claims.preferred_username().map(|name| name.to_string()),
+            email: claims.email().map(|email| email.to_string()),
+            email_verified: claims.email_verified().unwrap_or(false),
+            given_name: claims
+                .given_name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            family_name: claims
+                .family_name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+        })
+    }
+
+    #[inline]
+    fn post_login_redirect(&self) -> &str {
+        &self.config.post_login_redirect
+    }
+}
@@ -0,0 +1,269 @@
+use actix_web::{get, web, HttpResponse};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_scalar::{Scalar, Servable};
+
+use crate::routes;
+
+/// The whole HTTP API. Each route module describes its own paths, nested
+/// here under the scope it is mounted at.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Pupsight API",
+        description = "The app API lives under `/api/v1`. The same routes without the prefix \
+            are deprecated aliases, answered with `Deprecation` and `Link` headers."
+    ),
+    paths(routes::metrics::get_metrics, get_openapi),
+    nest(
+        (path = "/health", api = routes::health::HealthApi),
+        (path = "/api/v1/users", api = routes::users::UsersApi),
+        (path = "/api/v1/samples", api = routes::samples::SamplesApi),
+        (path = "/api/v1/pets", api = routes::pets::PetsApi),
+        (path = "/api/v1/scan", api = routes::scan::ScanApi),
+        (path = "/api/v1/search", api = routes::search::SearchApi),
+    ),
+    modifiers(&Security),
+    tags(
+        (name = "users", description = "Accounts, sessions and API tokens"),
+        (name = "samples", description = "Sample images and detection results"),
+        (name = "pets", description = "Pets that samples are taken of"),
+        (name = "search", description = "Full-text search over samples and pets"),
+        (name = "operations", description = "Probes, metrics and this document"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+struct Security;
+
+impl Modify for Security {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        // The `access_token` cookie goes along with it, and state changing
+        // requests also need the `X-CSRF-Token` header.
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                "session",
+                "Set by /api/v1/users/login together with access_token and csrf_token",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A bearer login or a personal API token"))
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// This document.
+#[utoipa::path(
+    tag = "operations",
+    responses((status = 200, description = "OpenAPI 3 document", content_type = "application/json"))
+)]
+#[get("/openapi.json")]
+async fn get_openapi(openapi: web::Data<utoipa::openapi::OpenApi>) -> HttpResponse {
+    HttpResponse::Ok().json(openapi.as_ref())
+}
+
+/// `/openapi.json` and an interactive reference at `/docs`.
+pub(crate) fn configure(config: &mut web::ServiceConfig) {
+    let openapi = ApiDoc::openapi();
+
+    config
+        .app_data(web::Data::new(openapi.clone()))
+        .service(Scalar::with_url("/docs", openapi))
+        .service(get_openapi);
+}
+
+/// A documented operation, with the statuses it answers with.
+#[cfg(test)]
+pub(crate) struct Operation {
+    pub(crate) method: actix_web::http::Method,
+    pub(crate) path: String,
+    pub(crate) statuses: Vec<actix_web::http::StatusCode>,
+    /// Whether it needs a session or bearer credential.
+    pub(crate) secured: bool,
+}
+
+/// Every operation of `ApiDoc`, for the tests holding routes to it.
+#[cfg(test)]
+pub(crate) fn operations() -> &'static [Operation] {
+    use std::sync::LazyLock;
+
+    use utoipa::openapi::HttpMethod;
+
+    static OPERATIONS: LazyLock<Vec<Operation>> = LazyLock::new(|| {
+        let mut operations = Vec::new();
+
+        for (path, item) in ApiDoc::openapi().paths.paths {
+            let methods = [
+                (HttpMethod::Get, &item.get),
+                (HttpMethod::Post, &item.post),
+                (HttpMethod::Put, &item.put),
+                (HttpMethod::Patch, &item.patch),
+                (HttpMethod::Delete, &item.delete),
+            ];
+
+            for (method, operation) in methods {
+                let Some(operation) = operation else {
+                    continue;
+                };
+
+                let method = serde_json::to_value(method).unwrap();
+
+                operations.push(Operation {
+                    method: method.as_str().unwrap().to_uppercase().parse().unwrap(),
+                    path: path.clone(),
+                    statuses: operation
+                        .responses
+                        .responses
+                        .keys()
+                        .map(|status| status.parse().unwrap())
+                        .collect(),
+                    secured: operation
+                        .security
+                        .as_ref()
+                        .is_some_and(|security| !security.is_empty()),
+                });
+            }
+        }
+
+        operations
+    });
+
+    &OPERATIONS
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    use super::{operations, Operation};
+    use crate::middleware::deprecated;
+    use crate::routes;
+
+    const ROUTE_MACROS: [&str; 5] = ["#[get(", "#[post(", "#[put(", "#[patch(", "#[delete("];
+
+    /// Every documented operation is served at the documented path, and v1
+    /// operations also at their deprecated unversioned alias.
+    #[actix_web::test]
+    async fn documented_routes_exist() {
+        let app = init_service(
+            App::new()
+                .configure(routes::legacy)
+                .configure(routes::configure)
+                .configure(super::configure),
+        )
+        .await;
+
+        for Operation { method, path, .. } in operations() {
+            let legacy = path.strip_prefix(routes::V1);
+
+            for path in std::iter::once(path.as_str()).chain(legacy) {
+                let request = TestRequest::default()
+                    .method(method.clone())
+                    .uri(path)
+                    .to_request();
+                let response = call_service(&app, request).await;
+
+                assert_eq!(
+                    response.request().match_pattern().as_deref(),
+                    Some(path),
+                    "{method} {path} is documented but not routed"
+                );
+                assert!(
+                    !matches!(
+                        response.status(),
+                        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+                    ),
+                    "{method} {path} is documented but not routed"
+                );
+                assert_eq!(
+                    response.headers().contains_key(deprecated::HEADER_NAME),
+                    legacy == Some(path),
+                    "{method} {path} has the wrong Deprecation header"
+                );
+            }
+        }
+    }
+
+    /// What the route tests see answered has to be documented, see
+    /// `routes::tests::send`. Beyond that, every operation documents how it
+    /// succeeds, and the secured ones how they refuse a missing login.
+    #[test]
+    fn statuses_are_documented() {
+        for operation in operations() {
+            let Operation {
+                method,
+                path,
+                statuses,
+                secured,
+            } = operation;
+
+            assert!(
+                statuses
+                    .iter()
+                    .any(|status| status.is_success() || status.is_redirection()),
+                "{method} {path} documents no success"
+            );
+            assert!(
+                !secured || statuses.contains(&StatusCode::UNAUTHORIZED),
+                "{method} {path} is secured but documents no 401"
+            );
+        }
+    }
+
+    /// Every route handler is documented, and nothing is documented twice.
+    #[test]
+    fn routes_are_documented() {
+        let mut routes = 0;
+
+        for dir in ["src", "src/routes"] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+
+                if path.extension().is_none_or(|ext| ext != "rs") {
+                    continue;
+                }
+
+                let source = std::fs::read_to_string(&path).unwrap();
+                let lines: Vec<&str> = source.lines().map(str::trim).collect();
+
+                for (index, line) in lines.iter().enumerate() {
+                    if !ROUTE_MACROS.iter().any(|m| line.starts_with(m)) {
+                        continue;
+                    }
+
+                    routes += 1;
+
+                    // The annotation sits directly above, possibly spanning lines.
+                    let documented = lines[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|line| !line.is_empty() && !line.starts_with("///"))
+                        .any(|line| line.starts_with("#[utoipa::path"));
+
+                    assert!(
+                        documented,
+                        "{line} in {} has no #[utoipa::path]",
+                        path.display()
+                    );
+                }
+            }
+        }
+
+        assert_eq!(
+            routes,
+            operations().len(),
+            "route handlers and documented operations differ"
+        );
+    }
+}
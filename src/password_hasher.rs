@@ -8,6 +8,8 @@ use diesel::sql_types::Binary;
 
 use serde::Deserialize;
 
+use crate::error::Error;
+
 pub(crate) struct PasswordHasher<'a> {
     argon2: Argon2<'a>,
     salt: Box<[u8]>,
@@ -23,14 +25,14 @@ impl<'a> PasswordHasher<'a> {
     }
 
     #[inline]
-    pub(crate) fn hash(&self, password: &str) -> PasswordHash {
+    pub(crate) fn hash(&self, password: &str) -> Result<PasswordHash, Error> {
         let mut output = [0u8; 32];
 
         self.argon2
             .hash_password_into(password.as_bytes(), &self.salt, &mut output)
-            .unwrap();
+            .map_err(Error::internal)?;
 
-        PasswordHash(output)
+        Ok(PasswordHash(output))
     }
 }
 
@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+#[derive(Clone, Copy)]
+pub struct Rate {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl std::str::FromStr for Rate {
+    type Err = String;
+
+    /// Parses `<requests>/<seconds>`, e.g. `10/60`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (requests, seconds) = value
+            .split_once('/')
+            .ok_or_else(|| format!("Expected <requests>/<seconds>, got {value:?}"))?;
+
+        let requests = requests
+            .trim()
+            .parse::<u32>()
+            .map_err(|e| format!("Invalid request count {requests:?}: {e}"))?;
+        let seconds = seconds
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid period {seconds:?}: {e}"))?;
+
+        if requests == 0 || seconds == 0 {
+            return Err(format!("Rate must be positive, got {value:?}"));
+        }
+
+        Ok(Self {
+            requests,
+            per: Duration::from_secs(seconds),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    pub auth: Rate,
+    pub scan: Rate,
+    pub default: Rate,
+    pub account: Rate,
+    pub lockout_threshold: u32,
+    pub lockout_base: Duration,
+    pub lockout_max: Duration,
+    pub trust_proxy_headers: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum RouteGroup {
+    Auth,
+    Scan,
+    Default,
+    /// Probes and metrics, polled on a schedule by infrastructure. Neither
+    /// throttled nor counted against the quota of an address they share.
+    Exempt,
+}
+
+impl RouteGroup {
+    const AUTH_PATHS: [&'static str; 7] = [
+        "/users/login",
+        "/users/login/totp",
+        "/users/oidc/login",
+        "/users/oidc/callback",
+        "/users/register",
+        "/users/password/forgot",
+        "/users/password/reset",
+    ];
+
+    const EXEMPT_PATHS: [&'static str; 2] = ["/health", "/metrics"];
+
+    pub(crate) fn classify(path: &str) -> Self {
+        let path = path.trim_end_matches('/');
+
+        if Self::EXEMPT_PATHS.iter().any(|item| {
+            path.strip_prefix(item)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        }) {
+            Self::Exempt
+        } else if Self::AUTH_PATHS.iter().any(|item| path.ends_with(item)) {
+            Self::Auth
+        } else if path.ends_with("/scan") {
+            Self::Scan
+        } else {
+            Self::Default
+        }
+    }
+}
+
+/// Request was refused; the caller may retry after the given delay.
+#[derive(Debug)]
+pub(crate) struct Throttled {
+    pub(crate) retry_after: Duration,
+}
+
+impl From<Throttled> for Error {
+    fn from(val: Throttled) -> Self {
+        Error::TooManyRequests {
+            retry_after: val.retry_after,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.requests as f64,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let per_second = rate.requests as f64 / rate.per.as_secs_f64();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * per_second).min(rate.requests as f64);
+        self.updated_at = now;
+    }
+
+    fn take(&mut self, rate: Rate, now: Instant) -> Result<(), Throttled> {
+        self.refill(rate, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let per_second = rate.requests as f64 / rate.per.as_secs_f64();
+
+        Err(Throttled {
+            retry_after: Duration::from_secs_f64((1.0 - self.tokens) / per_second),
+        })
+    }
+
+    fn is_full(&self, rate: Rate, now: Instant) -> bool {
+        let per_second = rate.requests as f64 / rate.per.as_secs_f64();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens + elapsed * per_second >= rate.requests as f64
+    }
+}
+
+/// Token buckets keyed by an arbitrary string, pruned of idle entries once
+/// the map grows past `PRUNE_THRESHOLD`.
+struct Buckets {
+    rate: Rate,
+    entries: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Buckets {
+    const PRUNE_THRESHOLD: usize = 10_000;
+
+    fn new(rate: Rate) -> Self {
+        Self {
+            rate,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, key: &str) -> Result<(), Throttled> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if entries.len() > Self::PRUNE_THRESHOLD {
+            entries.retain(|_, bucket| !bucket.is_full(self.rate, now));
+        }
+
+        entries
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::full(self.rate, now))
+            .take(self.rate, now)
+    }
+}
+
+/// Per-client request limits for each route group.
+pub(crate) struct RateLimiter {
+    auth: Buckets,
+    scan: Buckets,
+    default: Buckets,
+    trust_proxy_headers: bool,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            auth: Buckets::new(config.auth),
+            scan: Buckets::new(config.scan),
+            default: Buckets::new(config.default),
+            trust_proxy_headers: config.trust_proxy_headers,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn trust_proxy_headers(&self) -> bool {
+        self.trust_proxy_headers
+    }
+
+    pub(crate) fn check(&self, group: RouteGroup, client: &str) -> Result<(), Throttled> {
+        match group {
+            RouteGroup::Auth => self.auth.take(client),
+            RouteGroup::Scan => self.scan.take(client),
+            RouteGroup::Default => self.default.take(client),
+            RouteGroup::Exempt => Ok(()),
+        }
+    }
+}
+
+struct Lockout {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Per-account login limits: a token bucket on attempts plus a lockout that
+/// doubles with every failure past the threshold.
+pub(crate) struct LoginThrottle {
+    attempts: Buckets,
+    lockouts: Mutex<HashMap<String, Lockout>>,
+    threshold: u32,
+    base: Duration,
+    max: Duration,
+}
+
+impl LoginThrottle {
+    pub(crate) fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            attempts: Buckets::new(config.account),
+            lockouts: Mutex::new(HashMap::new()),
+            threshold: config.lockout_threshold,
+            base: config.lockout_base,
+            max: config.lockout_max,
+        }
+    }
+
+    #[inline]
+    fn key(login_name: &str) -> String {
+        login_name.trim().to_lowercase()
+    }
+
+    pub(crate) fn check(&self, login_name: &str) -> Result<(), Throttled> {
+        let key = Self::key(login_name);
+
+        if let Some(Lockout {
+            locked_until: Some(until),
+            ..
+        }) = self
+            .lockouts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+        {
+            let now = Instant::now();
+
+            if *until > now {
+                return Err(Throttled {
+                    retry_after: *until - now,
+                });
+            }
+        }
+
+        self.attempts.take(&key)
+    }
+
+    pub(crate) fn record_failure(&self, login_name: &str) {
+        let now = Instant::now();
+        let mut lockouts = self.lockouts.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if lockouts.len() > Buckets::PRUNE_THRESHOLD {
+            lockouts.retain(|_, lockout| lockout.locked_until.is_some_and(|until| until > now));
+        }
+
+        let lockout = lockouts.entry(Self::key(login_name)).or_insert(Lockout {
+            failures: 0,
+            locked_until: None,
+        });
+
+        lockout.failures += 1;
+
+        if lockout.failures >= self.threshold {
+            let exponent = (lockout.failures - self.threshold).min(16);
+            let duration = self.base.saturating_mul(1 << exponent).min(self.max);
+
+            lockout.locked_until = Some(now + duration);
+        }
+    }
+
+    pub(crate) fn record_success(&self, login_name: &str) {
+        self.lockouts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&Self::key(login_name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::http::StatusCode;
+    use actix_web::ResponseError;
+
+    use super::{Bucket, LoginThrottle, Rate, RateLimitConfig, RouteGroup, Throttled};
+    use crate::error::Error;
+
+    fn rate(requests: u32, seconds: u64) -> Rate {
+        Rate {
+            requests,
+            per: Duration::from_secs(seconds),
+        }
+    }
+
+    fn throttle(threshold: u32, base: u64, max: u64) -> LoginThrottle {
+        LoginThrottle::new(&RateLimitConfig {
+            auth: rate(1000, 60),
+            scan: rate(1000, 60),
+            default: rate(1000, 60),
+            account: rate(1000, 60),
+            lockout_threshold: threshold,
+            lockout_base: Duration::from_secs(base),
+            lockout_max: Duration::from_secs(max),
+            trust_proxy_headers: false,
+        })
+    }
+
+    /// Seconds left on the lockout of `login_name`, if any.
+    fn locked_for(throttle: &LoginThrottle, login_name: &str) -> Option<u64> {
+        throttle
+            .check(login_name)
+            .err()
+            .map(|throttled| throttled.retry_after.as_secs_f64().round() as u64)
+    }
+
+    #[test]
+    fn classify() {
+        for (path, group) in [
+            ("/api/v1/users/login", RouteGroup::Auth),
+            ("/api/v1/users/login/", RouteGroup::Auth),
+            ("/api/v1/users/login/totp", RouteGroup::Auth),
+            ("/api/v1/users/oidc/callback", RouteGroup::Auth),
+            ("/api/v1/users/password/reset", RouteGroup::Auth),
+            ("/api/v1/scan", RouteGroup::Scan),
+            ("/health", RouteGroup::Exempt),
+            ("/health/ready", RouteGroup::Exempt),
+            ("/metrics", RouteGroup::Exempt),
+            ("/healthz", RouteGroup::Default),
+            ("/api/v1/health", RouteGroup::Default),
+            ("/api/v1/users/password", RouteGroup::Default),
+            ("/api/v1/samples/upload", RouteGroup::Default),
+        ] {
+            assert!(RouteGroup::classify(path) == group, "{path}");
+        }
+    }
+
+    #[test]
+    fn bucket_refills_up_to_its_size() {
+        let rate = rate(2, 10);
+        let start = Instant::now();
+        let mut bucket = Bucket::full(rate, start);
+
+        assert!(bucket.take(rate, start).is_ok());
+        assert!(bucket.take(rate, start).is_ok());
+        let throttled = bucket.take(rate, start).unwrap_err();
+        assert_eq!(throttled.retry_after, Duration::from_secs(5));
+
+        assert!(bucket.take(rate, start + Duration::from_secs(5)).is_ok());
+        assert!(bucket.take(rate, start + Duration::from_secs(5)).is_err());
+
+        // An idle bucket holds no more than a full period of requests.
+        let later = start + Duration::from_secs(600);
+        assert!(bucket.is_full(rate, later));
+        assert!(bucket.take(rate, later).is_ok());
+        assert!(bucket.take(rate, later).is_ok());
+        assert!(bucket.take(rate, later).is_err());
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let throttle = throttle(3, 30, 100);
+
+        for _ in 0..2 {
+            throttle.record_failure("alice");
+            assert_eq!(locked_for(&throttle, "alice"), None);
+        }
+
+        for expected in [30, 60, 100, 100] {
+            throttle.record_failure("alice");
+            assert_eq!(locked_for(&throttle, "alice"), Some(expected));
+        }
+
+        // Login names are matched like at login.
+        assert_eq!(locked_for(&throttle, " Alice "), Some(100));
+        assert_eq!(locked_for(&throttle, "bob"), None);
+    }
+
+    #[test]
+    fn success_resets_the_lockout() {
+        let throttle = throttle(2, 30, 3600);
+
+        throttle.record_failure("alice");
+        throttle.record_failure("alice");
+        assert_eq!(locked_for(&throttle, "alice"), Some(30));
+
+        throttle.record_success("alice");
+        assert_eq!(locked_for(&throttle, "alice"), None);
+
+        // Counting starts over.
+        throttle.record_failure("alice");
+        assert_eq!(locked_for(&throttle, "alice"), None);
+        throttle.record_failure("alice");
+        assert_eq!(locked_for(&throttle, "alice"), Some(30));
+    }
+
+    #[test]
+    fn retry_after_rounds_up_to_whole_seconds() {
+        for (retry_after, header) in [
+            (Duration::from_secs(30), "30"),
+            (Duration::from_millis(1500), "2"),
+            (Duration::from_millis(10), "1"),
+            (Duration::ZERO, "1"),
+        ] {
+            let response = Error::from(Throttled { retry_after }).error_response();
+
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), header);
+        }
+    }
+}
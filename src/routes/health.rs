@@ -0,0 +1,151 @@
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use actix_web::{get, web, HttpResponse};
+use utoipa::OpenApi;
+
+use crate::database::Database;
+use crate::detector::Detector;
+use crate::error::Error;
+use crate::messages::health::{Check, DatabaseCheck, LiveResult, ReadyChecks, ReadyResult};
+
+/// A probe taking longer than this counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Process wide state the probes report on.
+pub(crate) struct Health {
+    started: Instant,
+    /// Outcome of the detector warm-up, unset while it runs.
+    warm_up: Mutex<Option<Check>>,
+}
+
+impl Health {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            warm_up: Mutex::new(None),
+        }
+    }
+
+    /// Runs the detector once, for `/ready` to report on from then on. Probes
+    /// never run the model themselves, so they neither wait for the session
+    /// nor show up in the inference metrics.
+    pub(crate) async fn warm_up(&self, detector: &dyn Detector) {
+        let started = Instant::now();
+        let error = detector.warm_up().await.err();
+
+        if let Some(error) = &error {
+            tracing::error!("Detector warm-up failed: {error}");
+        }
+
+        *self.warm_up.lock().unwrap_or_else(PoisonError::into_inner) = Some(Check {
+            ok: error.is_none(),
+            latency_ms: started.elapsed().as_millis() as u64,
+            error: error.map(|error| error.to_string()),
+        });
+    }
+
+    fn detector_check(&self) -> Check {
+        self.warm_up
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+            .unwrap_or_else(|| Check {
+                ok: false,
+                latency_ms: 0,
+                error: Some("Warming up".to_string()),
+            })
+    }
+
+    #[inline]
+    fn uptime_seconds(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+}
+
+/// Runs a check with a deadline, timing it.
+async fn check<T>(task: impl Future<Output = Result<T, Error>>) -> (Check, Option<T>) {
+    let started = Instant::now();
+    let result = tokio::time::timeout(CHECK_TIMEOUT, task).await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (error, value) = match result {
+        Ok(Ok(value)) => (None, Some(value)),
+        Ok(Err(e)) => (Some(e.to_string()), None),
+        Err(_) => (Some("Timed out".to_string()), None),
+    };
+
+    let check = Check {
+        ok: error.is_none(),
+        latency_ms,
+        error,
+    };
+
+    (check, value)
+}
+
+/// The process is up and serving requests.
+#[utoipa::path(
+    tag = "operations",
+    responses((status = 200, body = LiveResult))
+)]
+#[get("/live")]
+async fn live(health: web::Data<Health>) -> HttpResponse {
+    LiveResult {
+        status: "live",
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: health.uptime_seconds(),
+    }
+    .into()
+}
+
+/// The database answers and the detector warmed up. The detector check
+/// reports the warm-up run at startup, its latency is how long that took.
+#[utoipa::path(
+    tag = "operations",
+    responses(
+        (status = 200, body = ReadyResult),
+        (status = 503, description = "A check failed", body = ReadyResult),
+    )
+)]
+#[get("/ready")]
+async fn ready(
+    (health, database, detector): (
+        web::Data<Health>,
+        web::Data<Database>,
+        web::Data<dyn Detector>,
+    ),
+) -> HttpResponse {
+    let (database, pool) = check(database.ping()).await;
+    let detector_check = health.detector_check();
+
+    let database = DatabaseCheck {
+        check: database,
+        pool: pool.flatten(),
+    };
+
+    ReadyResult {
+        status: if database.check.ok && detector_check.ok {
+            "ready"
+        } else {
+            "unavailable"
+        },
+        version: env!("CARGO_PKG_VERSION"),
+        model_version: detector.model_version().to_string(),
+        uptime_seconds: health.uptime_seconds(),
+        checks: ReadyChecks {
+            database,
+            detector: detector_check,
+        },
+    }
+    .into()
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(live, ready))]
+pub(crate) struct HealthApi;
+
+pub(crate) fn scope() -> actix_web::Scope {
+    web::scope("/health").service(live).service(ready)
+}
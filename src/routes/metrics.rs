@@ -0,0 +1,21 @@
+use actix_web::{get, web, HttpResponse};
+
+use crate::database::Database;
+use crate::error::Error;
+use crate::metrics::METRICS;
+
+/// Prometheus text exposition.
+#[utoipa::path(
+    tag = "operations",
+    responses((status = 200, description = "Current metrics", content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub(crate) async fn get_metrics(database: web::Data<Database>) -> Result<HttpResponse, Error> {
+    if let Some(pool) = database.pool_state() {
+        METRICS.set_pool(pool);
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(METRICS.encode().map_err(Error::internal)?))
+}
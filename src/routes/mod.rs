@@ -1,2 +1,48 @@
+pub(crate) mod health;
+pub(crate) mod metrics;
+pub(crate) mod pets;
 pub(crate) mod samples;
+pub(crate) mod scan;
+pub(crate) mod search;
 pub(crate) mod users;
+
+#[cfg(test)]
+mod tests;
+
+use actix_web::web;
+
+use crate::middleware::Deprecated;
+
+/// Prefix of the current API version.
+pub(crate) const V1: &str = "/api/v1";
+
+/// Every API route, shared by the server and the OpenAPI route check.
+/// Probes and metrics stay unversioned, they are not part of the app contract.
+pub(crate) fn configure(config: &mut web::ServiceConfig) {
+    config
+        .service(health::scope())
+        .service(metrics::get_metrics)
+        .service(web::scope(V1).configure(v1));
+}
+
+/// The scopes of `/api/v1`. A later version gets its own scope next to it,
+/// registering the v1 scopes it keeps unchanged and its own where they differ.
+fn v1(config: &mut web::ServiceConfig) {
+    config
+        .service(users::scope())
+        .service(samples::scope())
+        .service(pets::scope())
+        .service(scan::scope())
+        .service(search::scope());
+}
+
+/// The v1 scopes at their unversioned paths, for app builds that predate
+/// `/api/v1`. Responses carry `Deprecation` and a successor link.
+pub(crate) fn legacy(config: &mut web::ServiceConfig) {
+    config
+        .service(users::scope().wrap(Deprecated::new(V1)))
+        .service(samples::scope().wrap(Deprecated::new(V1)))
+        .service(pets::scope().wrap(Deprecated::new(V1)))
+        .service(scan::scope().wrap(Deprecated::new(V1)))
+        .service(search::scope().wrap(Deprecated::new(V1)));
+}
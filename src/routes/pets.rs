@@ -0,0 +1,55 @@
+use actix_web::{get, post, web, HttpResponse};
+use utoipa::OpenApi;
+
+use crate::database::{Database, Scope, UserSession};
+use crate::error::{Error, Problem};
+use crate::messages::pets::{CreatePet, CreatePetResult, PetListResult};
+
+#[utoipa::path(
+    tag = "pets",
+    request_body = CreatePet,
+    responses(
+        (status = 200, body = CreatePetResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 422, description = "Name empty or too long", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:upload"]))
+)]
+#[post("")]
+async fn post_pet(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Json<CreatePet>),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Upload)?;
+
+    Ok(database
+        .create_pet(user.user_id, desc.into_inner())
+        .await?
+        .into())
+}
+
+#[utoipa::path(
+    tag = "pets",
+    responses(
+        (status = 200, body = PetListResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:read"]))
+)]
+#[get("")]
+async fn get_pets(
+    (database, user): (web::Data<Database>, UserSession),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::ReadSamples)?;
+
+    Ok(database.list_pets(user.user_id).await?.into())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(post_pet, get_pets))]
+pub(crate) struct PetsApi;
+
+pub(crate) fn scope() -> actix_web::Scope {
+    web::scope("/pets").service(post_pet).service(get_pets)
+}
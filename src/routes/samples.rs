@@ -1,23 +1,55 @@
 use actix_multipart::{Field, Multipart};
-use actix_web::{delete, get, http::Error, post, web, HttpResponse};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
 use futures::TryStreamExt;
 use image::GenericImageView;
+use utoipa::OpenApi;
 
-use crate::database::{SampleInsert, UserSession};
-use crate::messages::samples::{SampleImage, SampleInferredList, SamplePendingList};
+use crate::database::{SampleInsert, Scope, UserSession};
+use crate::error::{Error, Problem};
+use crate::messages::samples::{
+    InferredListResult, PendingListResult, SampleImage, SampleList, SampleNotes, SamplePet,
+    SampleReview,
+};
+use crate::metrics::METRICS;
+use crate::shutdown::Drain;
+use crate::validation::{self, FieldErrors};
 
+#[utoipa::path(
+    tag = "samples",
+    request_body(
+        content_type = "multipart/form-data",
+        description = "One image per field, the field name becomes the sample label"
+    ),
+    responses(
+        (status = 200, description = "Samples stored"),
+        (status = 422, description = "A field name is empty or too long for a label", body = Problem),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 415, description = "A field is not a supported image", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:upload"]))
+)]
 #[post("/upload")]
 async fn post_upload(
-    (database, info, mut payload): (
+    (database, drain, info, mut payload): (
         web::Data<crate::database::Database>,
+        web::Data<Drain>,
         crate::database::UserSession,
         Multipart,
     ),
-) -> HttpResponse {
+) -> Result<HttpResponse, Error> {
+    info.require(Scope::Upload)?;
+
     let mut samples = Vec::new();
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let file_data = get_field_filedata(&mut field).await.unwrap();
-        let mut raw = image::load_from_memory(&file_data).unwrap();
+    while let Some(mut field) = payload.try_next().await? {
+        let label = field.name().to_string();
+
+        let mut errors = FieldErrors::new();
+        errors.check_length("label", &label, validation::SAMPLE_LABEL_MAX);
+        errors.into_result()?;
+
+        let file_data = get_field_filedata(&mut field).await?;
+        let mut raw = image::load_from_memory(&file_data).map_err(unsupported_image)?;
         let mut buffer = std::io::Cursor::new(Vec::<u8>::new());
 
         let (width, height) = raw.dimensions();
@@ -29,100 +61,290 @@ async fn post_upload(
 
         let cropped_image = raw.crop(x, y, size, size);
 
-        if cropped_image
+        cropped_image
             .write_to(&mut buffer, image::ImageFormat::WebP)
-            .is_err()
-        {
-            return HttpResponse::UnsupportedMediaType().finish();
-        }
+            .map_err(unsupported_image)?;
 
         samples.push(SampleInsert {
-            label: field.name().to_string(),
+            label,
             bytes: file_data,
             owner_id: info.user_id,
             deleted: false,
         });
     }
 
-    database.upload_samples(samples).await.into()
+    drain
+        .run(async move { database.upload_samples(samples).await })
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[inline]
-async fn get_field_filedata(field: &mut Field) -> Result<Vec<u8>, Error> {
+fn unsupported_image(error: image::ImageError) -> Error {
+    Error::UnsupportedMediaType {
+        message: format!("Unsupported image: {error}").into(),
+    }
+}
+
+#[inline]
+pub(crate) async fn get_field_filedata(field: &mut Field) -> Result<Vec<u8>, Error> {
     let mut buffer = Vec::<u8>::new();
 
     while let Some(chunk) = futures::StreamExt::next(field).await {
-        let data = chunk.unwrap();
-        buffer.extend_from_slice(&data);
+        buffer.extend_from_slice(&chunk?);
     }
 
+    METRICS.upload_bytes.observe(buffer.len() as f64);
+
     Ok(buffer)
 }
 
+#[utoipa::path(
+    tag = "samples",
+    params(SampleImage),
+    responses(
+        (status = 200, description = "The sample image", content_type = "image/webp"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:read"]))
+)]
 #[get("/image")]
 async fn get_image(
-    (database, desc): (
+    (database, user, desc): (
         web::Data<crate::database::Database>,
+        UserSession,
         web::Query<SampleImage>,
     ),
-) -> HttpResponse {
-    database.get_sample_image(desc.sample_id).await.into()
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::ReadSamples)?;
+
+    Ok(database
+        .get_sample_image(user.user_id, desc.sample_id)
+        .await?
+        .into())
 }
 
+#[utoipa::path(
+    tag = "samples",
+    params(SampleList),
+    responses(
+        (status = 200, description = "Samples without results, newest first unless sorted otherwise", body = PendingListResult),
+        (status = 400, description = "Invalid cursor", body = Problem),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 422, description = "Invalid filters, result filters do not apply to pending samples", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:read"]))
+)]
 #[get("/pendings")]
 async fn get_pendings(
     (database, user, desc): (
         web::Data<crate::database::Database>,
         UserSession,
-        web::Query<SamplePendingList>,
+        web::Query<SampleList>,
     ),
-) -> HttpResponse {
-    database
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::ReadSamples)?;
+
+    Ok(database
         .get_pending_list(user.user_id, desc.into_inner())
-        .await
-        .into()
+        .await?
+        .into())
 }
 
+#[utoipa::path(
+    tag = "samples",
+    params(SampleList),
+    responses(
+        (status = 200, description = "Samples with a result matching the result filters, newest first unless sorted otherwise", body = InferredListResult),
+        (status = 400, description = "Invalid cursor", body = Problem),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 422, description = "Invalid filters", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:read"]))
+)]
 #[get("/infers")]
 async fn get_infers(
     (database, user, desc): (
         web::Data<crate::database::Database>,
         UserSession,
-        web::Query<SampleInferredList>,
+        web::Query<SampleList>,
     ),
-) -> HttpResponse {
-    database
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::ReadSamples)?;
+
+    Ok(database
         .get_inferred_list(user.user_id, desc.into_inner())
-        .await
-        .into()
+        .await?
+        .into())
 }
 
+#[utoipa::path(
+    tag = "samples",
+    request_body = SampleImage,
+    responses(
+        (status = 202, description = "Results stored"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, body = Problem),
+        (status = 422, description = "Nothing was detected", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:infer"]))
+)]
 #[post("/infer")]
 async fn post_infer(
     (database, detector, user, desc): (
         web::Data<crate::Database>,
-        web::Data<crate::Detector>,
+        web::Data<dyn crate::Detector>,
         UserSession,
         web::Json<SampleImage>,
     ),
-) -> HttpResponse {
+    drain: web::Data<Drain>,
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Infer)?;
+
+    drain
+        .run(async move {
+            database
+                .infer_sample_image(user.user_id, desc.sample_id, detector.as_ref())
+                .await
+        })
+        .await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[utoipa::path(
+    tag = "samples",
+    request_body = SampleReview,
+    responses(
+        (status = 200, description = "Review status set"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:review"]))
+)]
+#[patch("/review")]
+async fn patch_review(
+    (database, user, desc): (
+        web::Data<crate::Database>,
+        UserSession,
+        web::Json<SampleReview>,
+    ),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Review)?;
+
     database
-        .infer_sample_image(user.user_id, desc.sample_id, detector.as_ref())
-        .await
-        .into()
+        .review_sample(user.user_id, desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
+#[utoipa::path(
+    tag = "samples",
+    request_body = SamplePet,
+    responses(
+        (status = 200, description = "Pet set"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, description = "Sample or pet not found", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:review"]))
+)]
+#[patch("/pet")]
+async fn patch_pet(
+    (database, user, desc): (
+        web::Data<crate::Database>,
+        UserSession,
+        web::Json<SamplePet>,
+    ),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Review)?;
+
+    database
+        .assign_sample_pet(user.user_id, desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "samples",
+    request_body = SampleNotes,
+    responses(
+        (status = 200, description = "Notes set"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, body = Problem),
+        (status = 422, description = "Notes empty or too long", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:review"]))
+)]
+#[patch("/notes")]
+async fn patch_notes(
+    (database, user, desc): (
+        web::Data<crate::Database>,
+        UserSession,
+        web::Json<SampleNotes>,
+    ),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Review)?;
+
+    database
+        .set_sample_notes(user.user_id, desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "samples",
+    params(SampleImage),
+    responses(
+        (status = 200, description = "Sample deleted"),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 404, body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:upload"]))
+)]
 #[delete("/delete")]
 async fn delete_samples(
-    (database, _user, desc): (
+    (database, user, desc): (
         web::Data<crate::Database>,
         UserSession,
         web::Query<SampleImage>,
     ),
-) -> HttpResponse {
-    database.delete_sample_image(desc.sample_id).await.into()
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::Upload)?;
+
+    database
+        .delete_sample_image(user.user_id, desc.sample_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    post_upload,
+    get_image,
+    get_pendings,
+    get_infers,
+    post_infer,
+    patch_review,
+    patch_pet,
+    patch_notes,
+    delete_samples,
+))]
+pub(crate) struct SamplesApi;
+
 pub(crate) fn scope() -> actix_web::Scope {
     web::scope("/samples")
         .service(post_upload)
@@ -130,5 +352,8 @@ pub(crate) fn scope() -> actix_web::Scope {
         .service(get_pendings)
         .service(get_infers)
         .service(post_infer)
+        .service(patch_review)
+        .service(patch_pet)
+        .service(patch_notes)
         .service(delete_samples)
 }
@@ -0,0 +1,50 @@
+use actix_multipart::Multipart;
+use actix_web::{post, web, HttpResponse};
+use futures::TryStreamExt;
+use utoipa::OpenApi;
+
+use crate::detector::{self, Detector, ResultBox};
+use crate::error::{Error, Problem};
+use crate::shutdown::Drain;
+
+/// Runs the detector on an image without storing anything.
+#[utoipa::path(
+    tag = "samples",
+    request_body(content_type = "multipart/form-data", description = "The image in its first field"),
+    responses(
+        (status = 200, description = "Detected boxes", body = [ResultBox]),
+        (status = 406, description = "No image was sent"),
+        (status = 415, description = "Not a supported image", body = Problem),
+    )
+)]
+#[post("")]
+async fn post_scan(
+    (detector, drain, mut payload): (web::Data<dyn Detector>, web::Data<Drain>, Multipart),
+) -> Result<HttpResponse, Error> {
+    tracing::debug!("Processing image");
+    if let Some(mut field) = payload.try_next().await? {
+        let file_data = super::samples::get_field_filedata(&mut field).await?;
+
+        let raw =
+            image::load_from_memory(&file_data).map_err(|error| Error::UnsupportedMediaType {
+                message: format!("Unsupported image: {error}").into(),
+            })?;
+        let image = detector::prepare_scan(&raw);
+
+        let result = drain
+            .run(async move { detector.infer(&image).await })
+            .await?;
+
+        return Ok(HttpResponse::Ok().json(result));
+    }
+
+    Ok(HttpResponse::NotAcceptable().finish())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(post_scan))]
+pub(crate) struct ScanApi;
+
+pub(crate) fn scope() -> actix_web::Scope {
+    web::scope("/scan").service(post_scan)
+}
@@ -0,0 +1,37 @@
+use actix_web::{get, web, HttpResponse};
+use utoipa::OpenApi;
+
+use crate::database::{Database, Scope, UserSession};
+use crate::error::{Error, Problem};
+use crate::messages::search::{Search, SearchResult};
+
+#[utoipa::path(
+    tag = "search",
+    params(Search),
+    responses(
+        (status = 200, description = "Matching samples and pets, most relevant first", body = SearchResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "API token lacks the scope", body = Problem),
+        (status = 422, description = "Query empty or too long", body = Problem),
+    ),
+    security(("session" = []), ("bearer" = ["samples:read"]))
+)]
+#[get("")]
+async fn get_search(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Query<Search>),
+) -> Result<HttpResponse, Error> {
+    user.require(Scope::ReadSamples)?;
+
+    Ok(database
+        .search(user.user_id, desc.into_inner())
+        .await?
+        .into())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_search))]
+pub(crate) struct SearchApi;
+
+pub(crate) fn scope() -> actix_web::Scope {
+    web::scope("/search").service(get_search)
+}
@@ -0,0 +1,1556 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_http::Request;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{call_service, init_service, TestRequest};
+use actix_web::{web, App};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use super::health::Health;
+use crate::config::CookieConfig;
+use crate::database::{Database, MemoryRepository};
+use crate::detector::{Classification, Detector, ResultBox, ScriptedDetector};
+use crate::mailer::{Mailer, MailerConfig};
+use crate::messages::users::OIDC_STATE_COOKIE;
+use crate::oidc::{AuthorizationRequest, Identity, IdentityProvider, OidcError};
+use crate::openapi;
+use crate::password_hasher::PasswordHasher;
+use crate::rate_limit::{LoginThrottle, Rate, RateLimitConfig};
+use crate::shutdown::Drain;
+
+const PASSWORD: &str = "correct horse battery";
+const BOUNDARY: &str = "pupsight-test-boundary";
+
+/// The API routes on `repository`, inferring with `detector`.
+async fn app(
+    repository: Arc<MemoryRepository>,
+    detector: ScriptedDetector,
+) -> impl Service<Request, Response = ServiceResponse, Error = actix_web::Error> {
+    Setup {
+        repository,
+        detector,
+        ..Setup::default()
+    }
+    .start()
+    .await
+}
+
+/// What the routes of a test app run on. By default an empty memory
+/// repository, an idle detector that has not warmed up, mail to the log, no
+/// single sign-on, and limits no test reaches.
+struct Setup {
+    repository: Arc<MemoryRepository>,
+    detector: ScriptedDetector,
+    health: web::Data<Health>,
+    mailer: MailerConfig,
+    oidc: Option<Arc<dyn IdentityProvider>>,
+    limits: RateLimitConfig,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        let rate = Rate {
+            requests: 1000,
+            per: Duration::from_secs(60),
+        };
+
+        Self {
+            repository: Arc::new(MemoryRepository::new()),
+            detector: idle(),
+            health: web::Data::new(Health::new()),
+            mailer: MailerConfig::Log,
+            oidc: None,
+            limits: RateLimitConfig {
+                auth: rate,
+                scan: rate,
+                default: rate,
+                account: rate,
+                lockout_threshold: 5,
+                lockout_base: Duration::from_secs(30),
+                lockout_max: Duration::from_secs(3600),
+                trust_proxy_headers: false,
+            },
+        }
+    }
+}
+
+impl Setup {
+    async fn start(
+        self,
+    ) -> impl Service<Request, Response = ServiceResponse, Error = actix_web::Error> {
+        let detector: web::Data<dyn Detector> =
+            web::Data::from(Arc::new(self.detector) as Arc<dyn Detector>);
+
+        let mut app = App::new()
+            .app_data(web::Data::new(Database::with_repository(self.repository)))
+            .app_data(detector)
+            .app_data(self.health)
+            .app_data(web::Data::new(PasswordHasher::new(Box::new(
+                *b"route-tests-salt",
+            ))))
+            .app_data(web::Data::new(CookieConfig {
+                secure: false,
+                same_site: SameSite::Lax,
+                max_age: actix_web::cookie::time::Duration::days(1),
+                domain: None,
+            }))
+            .app_data(web::Data::new(Mailer::new(
+                &self.mailer,
+                "pupsight@localhost".to_string(),
+                None,
+                None,
+            )))
+            .app_data(web::Data::new(LoginThrottle::new(&self.limits)))
+            .app_data(web::Data::new(Drain::default()));
+
+        if let Some(oidc) = self.oidc {
+            app = app.app_data(web::Data::from(oidc));
+        }
+
+        init_service(app.configure(super::configure)).await
+    }
+}
+
+/// For tests that never infer.
+fn idle() -> ScriptedDetector {
+    ScriptedDetector::new(Vec::new(), "idle".to_string())
+}
+
+fn detection(classification: Classification, probability: f32) -> ResultBox {
+    ResultBox {
+        x: 10.0,
+        y: 20.0,
+        width: 30.0,
+        height: 40.0,
+        probability,
+        classification,
+    }
+}
+
+/// Calls `app`, holding the answer to the statuses the API document lists
+/// for the route.
+async fn send<S>(app: &S, request: TestRequest) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let response = call_service(app, request.to_request()).await;
+    let status = response.status();
+
+    if let Some(path) = response.request().match_pattern() {
+        let method = response.request().method();
+        let operation = openapi::operations()
+            .iter()
+            .find(|operation| operation.method == method && operation.path == path)
+            .unwrap_or_else(|| panic!("{method} {path} is not documented"));
+
+        assert!(
+            operation.statuses.contains(&status),
+            "{method} {path} answered {status}, which is not documented"
+        );
+    }
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn get(token: &str, uri: &str) -> TestRequest {
+    TestRequest::get()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+}
+
+fn post(token: &str, uri: &str, body: Value) -> TestRequest {
+    TestRequest::post()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+        .set_json(body)
+}
+
+fn patch(token: &str, uri: &str, body: Value) -> TestRequest {
+    TestRequest::patch()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+        .set_json(body)
+}
+
+fn delete(token: &str, uri: &str) -> TestRequest {
+    TestRequest::delete()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+}
+
+/// Registers `login_name` and logs in, returning a bearer session token.
+async fn sign_up<S>(app: &S, login_name: &str) -> String
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let register = TestRequest::post()
+        .uri("/api/v1/users/register")
+        .set_json(json!({
+            "login_name": login_name,
+            "first_name": "Test",
+            "last_name": "User",
+            "password": PASSWORD,
+        }));
+    assert_eq!(send(app, register).await.0, StatusCode::OK);
+
+    let (status, token) = log_in(app, login_name, PASSWORD).await;
+    assert_eq!(status, StatusCode::OK);
+
+    token.unwrap()
+}
+
+fn png() -> Vec<u8> {
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::new_rgb8(8, 8)
+        .write_to(&mut bytes, image::ImageFormat::Png)
+        .unwrap();
+
+    bytes.into_inner()
+}
+
+/// A multipart body with one image field per label.
+fn images(token: &str, uri: &str, labels: &[&str]) -> TestRequest {
+    let mut body = Vec::new();
+    for label in labels {
+        body.extend_from_slice(
+            format!(
+                "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{label}\"; \
+                 filename=\"{label}.png\"\r\nContent-Type: image/png\r\n\r\n"
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&png());
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+    TestRequest::post()
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        ))
+        .set_payload(body)
+}
+
+/// Labels of a sample list, in list order.
+fn labels(list: &Value) -> Vec<&str> {
+    list["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect()
+}
+
+/// Id of the listed sample labelled `label`.
+fn sample_id(list: &Value, label: &str) -> String {
+    list["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|item| item["label"] == label)
+        .unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+/// A fresh directory for a test mailer to write into.
+fn mail_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("pupsight-mail-{}", uuid::Uuid::new_v4()))
+}
+
+/// Removes the mail written to `dir` so far, returning the recipient and the
+/// token of each.
+fn take_mail(dir: &std::path::Path) -> Vec<(String, String)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let mail = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+
+            let (headers, body) = mail.split_once("\r\n\r\n").unwrap();
+            let to = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("To: "))
+                .unwrap();
+            // Without a link configured, the link is the token itself.
+            let token = body.lines().nth(2).unwrap();
+
+            (to.to_string(), token.to_string())
+        })
+        .collect()
+}
+
+/// Logs `login_name` in with `password`, returning the status and the bearer
+/// session token on success.
+async fn log_in<S>(app: &S, login_name: &str, password: &str) -> (StatusCode, Option<String>)
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let login = TestRequest::post()
+        .uri("/api/v1/users/login")
+        .set_json(json!({ "login_name": login_name, "password": password, "bearer": true }));
+    let (status, body) = send(app, login).await;
+
+    (status, body["token"].as_str().map(str::to_string))
+}
+
+/// Code of the authenticator set up from `provisioning_uri`, `offset` steps
+/// from now. Each accepted code must be of a later step than the last one.
+fn totp_code(provisioning_uri: &str, offset: i64) -> String {
+    let totp = totp_rs::TOTP::from_url_unchecked(provisioning_uri).unwrap();
+    let now = chrono::Utc::now().timestamp();
+
+    totp.generate((now + offset * totp.step as i64) as u64)
+}
+
+/// Enrolls `token` in two-factor login, returning the provisioning URI and
+/// the recovery codes.
+async fn enable_totp<S>(app: &S, token: &str) -> (String, Vec<String>)
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let (status, enrolled) = send(app, post(token, "/api/v1/users/totp/enroll", json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let uri = enrolled["provisioning_uri"].as_str().unwrap().to_string();
+
+    let confirm = json!({ "code": totp_code(&uri, -1) });
+    let (status, confirmed) = send(app, post(token, "/api/v1/users/totp/confirm", confirm)).await;
+    assert_eq!(status, StatusCode::OK);
+    let codes = confirmed["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+
+    (uri, codes)
+}
+
+/// Password login up to the second factor, returning the challenge id.
+async fn challenge<S>(app: &S, login_name: &str) -> String
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let login = TestRequest::post()
+        .uri("/api/v1/users/login")
+        .set_json(json!({ "login_name": login_name, "password": PASSWORD, "bearer": true }));
+    let (status, body) = send(app, login).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(body["second_factor"], "totp");
+
+    body["challenge_id"].as_str().unwrap().to_string()
+}
+
+fn second_factor(challenge_id: &str, code: &str) -> TestRequest {
+    TestRequest::post()
+        .uri("/api/v1/users/login/totp")
+        .set_json(json!({ "challenge_id": challenge_id, "code": code, "bearer": true }))
+}
+
+#[actix_web::test]
+async fn register_and_login() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let token = sign_up(&app, "alice").await;
+
+    let (status, profile) = send(&app, get(&token, "/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["login_name"], "alice");
+
+    let taken = TestRequest::post()
+        .uri("/api/v1/users/register")
+        .set_json(json!({
+            "login_name": "alice",
+            "first_name": "Other",
+            "last_name": "User",
+            "password": PASSWORD,
+        }));
+    assert_eq!(send(&app, taken).await.0, StatusCode::CONFLICT);
+
+    let wrong = TestRequest::post()
+        .uri("/api/v1/users/login")
+        .set_json(json!({ "login_name": "alice", "password": "not the password" }));
+    assert_eq!(send(&app, wrong).await.0, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(&app, TestRequest::get().uri("/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// Pages follow the cursor in byte order of the labels, uppercase first,
+/// like `COLLATE "C"` on Postgres.
+#[actix_web::test]
+async fn upload_and_page_by_label() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let token = sign_up(&app, "alice").await;
+
+    let upload = images(
+        &token,
+        "/api/v1/samples/upload",
+        &["beta", "Gamma", "alpha"],
+    );
+    assert_eq!(send(&app, upload).await.0, StatusCode::OK);
+
+    let (status, first) = send(
+        &app,
+        get(
+            &token,
+            "/api/v1/samples/pendings?sort=label&limit=2&total=true",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(labels(&first), ["Gamma", "alpha"]);
+    assert_eq!(first["has_next"], true);
+    assert_eq!(first["total"], 3);
+
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (_, second) = send(
+        &app,
+        get(
+            &token,
+            &format!("/api/v1/samples/pendings?sort=label&limit=2&cursor={cursor}"),
+        ),
+    )
+    .await;
+    assert_eq!(labels(&second), ["beta"]);
+    assert_eq!(second["has_next"], false);
+
+    let long = "x".repeat(33);
+    let upload = images(&token, "/api/v1/samples/upload", &[&long]);
+    assert_eq!(send(&app, upload).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[actix_web::test]
+async fn review_sample() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let token = sign_up(&app, "alice").await;
+
+    send(
+        &app,
+        images(&token, "/api/v1/samples/upload", &["ear", "paw"]),
+    )
+    .await;
+    let (_, list) = send(&app, get(&token, "/api/v1/samples/pendings")).await;
+    let ear = sample_id(&list, "ear");
+
+    let review = json!({ "sample_id": ear, "review_status": "rejected" });
+    let (status, _) = send(&app, patch(&token, "/api/v1/samples/review", review)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, rejected) = send(
+        &app,
+        get(&token, "/api/v1/samples/pendings?review_status=rejected"),
+    )
+    .await;
+    assert_eq!(labels(&rejected), ["ear"]);
+    assert_eq!(rejected["items"][0]["review_status"], "rejected");
+
+    let missing = json!({ "sample_id": uuid::Uuid::new_v4(), "review_status": "confirmed" });
+    let (status, _) = send(&app, patch(&token, "/api/v1/samples/review", missing)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// Samples of other users are not found, whatever is done to them.
+#[actix_web::test]
+async fn delete_is_scoped_to_the_owner() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let alice = sign_up(&app, "alice").await;
+    let bob = sign_up(&app, "bob").await;
+
+    send(&app, images(&alice, "/api/v1/samples/upload", &["ear"])).await;
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    let ear = sample_id(&list, "ear");
+
+    let image = format!("/api/v1/samples/image?sample_id={ear}");
+    let remove = format!("/api/v1/samples/delete?sample_id={ear}");
+
+    assert_eq!(send(&app, get(&bob, &image)).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(
+        send(&app, delete(&bob, &remove)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    let review = json!({ "sample_id": ear, "review_status": "confirmed" });
+    assert_eq!(
+        send(&app, patch(&bob, "/api/v1/samples/review", review))
+            .await
+            .0,
+        StatusCode::NOT_FOUND
+    );
+    let (_, list) = send(&app, get(&bob, "/api/v1/samples/pendings")).await;
+    assert!(labels(&list).is_empty());
+
+    assert_eq!(send(&app, get(&alice, &image)).await.0, StatusCode::OK);
+    assert_eq!(send(&app, delete(&alice, &remove)).await.0, StatusCode::OK);
+    assert_eq!(
+        send(&app, delete(&alice, &remove)).await.0,
+        StatusCode::NOT_FOUND
+    );
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    assert!(labels(&list).is_empty());
+}
+
+/// Samples are assigned to pets of their owner only, and pets go along with
+/// the account of their owner.
+#[actix_web::test]
+async fn pets() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let alice = sign_up(&app, "alice").await;
+    let bob = sign_up(&app, "bob").await;
+
+    send(
+        &app,
+        images(&alice, "/api/v1/samples/upload", &["ear", "paw"]),
+    )
+    .await;
+    send(&app, images(&bob, "/api/v1/samples/upload", &["tail"])).await;
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    let ear = sample_id(&list, "ear");
+    let (_, list) = send(&app, get(&bob, "/api/v1/samples/pendings")).await;
+    let tail = sample_id(&list, "tail");
+
+    let rex = json!({ "name": "Rex", "birthday": "2021-04-01T00:00:00" });
+    let (status, rex) = send(&app, post(&alice, "/api/v1/pets", rex)).await;
+    assert_eq!(status, StatusCode::OK);
+    let rex = rex["id"].as_str().unwrap().to_string();
+    let (status, body) = send(&app, post(&alice, "/api/v1/pets", json!({ "name": "" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["fields"]["name"], "Required");
+
+    let (_, pets) = send(&app, get(&alice, "/api/v1/pets")).await;
+    assert_eq!(pets["items"][0]["id"], rex);
+    assert_eq!(pets["items"][0]["name"], "Rex");
+    assert_eq!(pets["items"][0]["birthday"], "2021-04-01T00:00:00");
+    assert_eq!(pets["items"][0]["exact_birthday"], false);
+    let (_, pets) = send(&app, get(&bob, "/api/v1/pets")).await;
+    assert!(pets["items"].as_array().unwrap().is_empty());
+
+    let assign = |sample: &str, pet: &str| json!({ "sample_id": sample, "pet_id": pet });
+    let (status, _) = send(
+        &app,
+        patch(&alice, "/api/v1/samples/pet", assign(&ear, &rex)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(
+        &app,
+        patch(&bob, "/api/v1/samples/pet", assign(&tail, &rex)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "sample_or_pet_not_found");
+    let (status, _) = send(&app, patch(&bob, "/api/v1/samples/pet", assign(&ear, &rex))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, list) = send(
+        &app,
+        get(&alice, &format!("/api/v1/samples/pendings?pet_id={rex}")),
+    )
+    .await;
+    assert_eq!(labels(&list), ["ear"]);
+    assert_eq!(list["items"][0]["pet_id"], rex);
+
+    let (_, hits) = send(&app, get(&alice, "/api/v1/search?q=rex")).await;
+    assert_eq!(hits["items"][0]["kind"], "pet");
+    assert_eq!(hits["items"][0]["id"], rex);
+    let (_, hits) = send(&app, get(&bob, "/api/v1/search?q=rex")).await;
+    assert!(hits["items"].as_array().unwrap().is_empty());
+
+    let unassign = json!({ "sample_id": ear, "pet_id": null });
+    let (status, _) = send(&app, patch(&alice, "/api/v1/samples/pet", unassign)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, list) = send(
+        &app,
+        get(&alice, &format!("/api/v1/samples/pendings?pet_id={rex}")),
+    )
+    .await;
+    assert!(labels(&list).is_empty());
+
+    let leave = TestRequest::delete()
+        .uri("/api/v1/users/account")
+        .insert_header((header::AUTHORIZATION, format!("Bearer {alice}")))
+        .set_json(json!({ "password": PASSWORD }));
+    assert_eq!(send(&app, leave).await.0, StatusCode::OK);
+    let (_, hits) = send(
+        &app,
+        get(&sign_up(&app, "alice").await, "/api/v1/search?q=rex"),
+    )
+    .await;
+    assert!(hits["items"].as_array().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn search() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let alice = sign_up(&app, "alice").await;
+    let bob = sign_up(&app, "bob").await;
+
+    send(
+        &app,
+        images(&alice, "/api/v1/samples/upload", &["left-ear", "paw"]),
+    )
+    .await;
+    send(&app, images(&bob, "/api/v1/samples/upload", &["right-ear"])).await;
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    let paw = sample_id(&list, "paw");
+
+    let notes = json!({ "sample_id": paw, "notes": "Redness between the toes, ear clean" });
+    let (status, _) = send(&app, patch(&alice, "/api/v1/samples/notes", notes)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Label matches rank above notes matches, other users' samples never show.
+    let (status, hits) = send(&app, get(&alice, "/api/v1/search?q=ear")).await;
+    assert_eq!(status, StatusCode::OK);
+    let titles: Vec<_> = hits["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hit| hit["title"].as_str().unwrap())
+        .collect();
+    assert_eq!(titles, ["left-ear", "paw"]);
+
+    let (_, hits) = send(&app, get(&alice, "/api/v1/search?q=toes%20redness")).await;
+    assert_eq!(hits["items"][0]["id"], paw);
+
+    let (status, _) = send(&app, get(&alice, "/api/v1/search?q=")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+/// `/scan` answers with the boxes of the detector as they are, one call at a
+/// time, and stores nothing.
+#[actix_web::test]
+async fn scan_returns_detections() {
+    let script = vec![
+        vec![detection(Classification::Normal, 0.9)],
+        vec![
+            detection(Classification::Incipient, 0.6),
+            detection(Classification::Normal, 0.3),
+        ],
+    ];
+    let app = app(
+        Arc::new(MemoryRepository::new()),
+        ScriptedDetector::new(script, "scripted-test".to_string()),
+    )
+    .await;
+    let token = sign_up(&app, "alice").await;
+
+    let (status, boxes) = send(&app, images(&token, "/api/v1/scan", &["file"])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        boxes,
+        json!([{ "x": 10.0, "y": 20.0, "width": 30.0, "height": 40.0,
+                 "probability": 0.9, "classification": "Normal" }])
+    );
+
+    let (_, boxes) = send(&app, images(&token, "/api/v1/scan", &["file"])).await;
+    let classes: Vec<_> = boxes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|detection| detection["classification"].as_str().unwrap())
+        .collect();
+    assert_eq!(classes, ["Incipient", "Normal"]);
+
+    let (_, list) = send(&app, get(&token, "/api/v1/samples/pendings?total=true")).await;
+    assert_eq!(list["total"], 0);
+
+    let broken = TestRequest::post()
+        .uri("/api/v1/scan")
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        ))
+        .set_payload(format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"\r\n\r\n\
+             not an image\r\n--{BOUNDARY}--\r\n"
+        ));
+    assert_eq!(
+        send(&app, broken).await.0,
+        StatusCode::UNSUPPORTED_MEDIA_TYPE
+    );
+}
+
+/// `/samples/infer` stores every box of the detector as a result of the
+/// sample, with its certainty, class and the model version.
+#[actix_web::test]
+async fn infer_stores_detections() {
+    let script = vec![
+        vec![detection(Classification::Normal, 0.9)],
+        vec![
+            detection(Classification::Incipient, 0.6),
+            detection(Classification::Incipient, 0.3),
+        ],
+        Vec::new(),
+    ];
+    let app = app(
+        Arc::new(MemoryRepository::new()),
+        ScriptedDetector::new(script, "scripted-test".to_string()),
+    )
+    .await;
+    let alice = sign_up(&app, "alice").await;
+    let bob = sign_up(&app, "bob").await;
+
+    send(
+        &app,
+        images(&alice, "/api/v1/samples/upload", &["ear", "paw", "tail"]),
+    )
+    .await;
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    let (ear, paw, tail) = (
+        sample_id(&list, "ear"),
+        sample_id(&list, "paw"),
+        sample_id(&list, "tail"),
+    );
+
+    // Someone else's sample is not found and does not use up the script.
+    let (status, _) = send(
+        &app,
+        post(&bob, "/api/v1/samples/infer", json!({ "sample_id": ear })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for sample in [&ear, &paw] {
+        let (status, _) = send(
+            &app,
+            post(
+                &alice,
+                "/api/v1/samples/infer",
+                json!({ "sample_id": sample }),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+
+    let (status, _) = send(
+        &app,
+        post(
+            &alice,
+            "/api/v1/samples/infer",
+            json!({ "sample_id": tail }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (_, pending) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    assert_eq!(labels(&pending), ["tail"]);
+
+    let (_, inferred) = send(&app, get(&alice, "/api/v1/samples/infers?sort=certainty")).await;
+    assert_eq!(labels(&inferred), ["ear", "paw"]);
+
+    let results = |index: usize| -> Vec<(f64, bool, String)> {
+        inferred["items"][index]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                (
+                    (result["certainty"].as_f64().unwrap() * 100.0).round() / 100.0,
+                    result["is_normal"].as_bool().unwrap(),
+                    result["model_version"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    };
+    assert_eq!(results(0), [(0.9, true, "scripted-test".to_string())]);
+    let mut paw_results = results(1);
+    paw_results.sort_by(|a, b| a.0.total_cmp(&b.0));
+    assert_eq!(
+        paw_results,
+        [
+            (0.3, false, "scripted-test".to_string()),
+            (0.6, false, "scripted-test".to_string()),
+        ]
+    );
+    assert_eq!(inferred["items"][0]["results"][0]["x"], 10.0);
+    assert_eq!(inferred["items"][0]["results"][0]["height"], 40.0);
+
+    let (_, incipient) = send(
+        &app,
+        get(&alice, "/api/v1/samples/infers?classification=Incipient"),
+    )
+    .await;
+    assert_eq!(labels(&incipient), ["paw"]);
+
+    let (_, certain) = send(
+        &app,
+        get(&alice, "/api/v1/samples/infers?min_certainty=0.7"),
+    )
+    .await;
+    assert_eq!(labels(&certain), ["ear"]);
+}
+
+/// Personal API tokens work as bearer credentials within their scopes, and
+/// stop working once revoked.
+#[actix_web::test]
+async fn api_tokens() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let session = sign_up(&app, "alice").await;
+
+    let create = json!({ "name": "phone", "scopes": ["samples:read", "samples:upload"] });
+    let (status, created) = send(&app, post(&session, "/api/v1/users/tokens", create)).await;
+    assert_eq!(status, StatusCode::OK);
+    let token = created["token"].as_str().unwrap();
+
+    let (_, tokens) = send(&app, get(&session, "/api/v1/users/tokens")).await;
+    assert_eq!(tokens["items"][0]["id"], created["id"]);
+    assert_eq!(tokens["items"][0]["name"], "phone");
+    assert_eq!(
+        tokens["items"][0]["scopes"],
+        json!(["samples:read", "samples:upload"])
+    );
+
+    let upload = images(token, "/api/v1/samples/upload", &["ear"]);
+    assert_eq!(send(&app, upload).await.0, StatusCode::OK);
+    let (status, list) = send(&app, get(token, "/api/v1/samples/pendings")).await;
+    assert_eq!(status, StatusCode::OK);
+    let ear = sample_id(&list, "ear");
+
+    // Reviewing needs `samples:review`, managing tokens a session.
+    let review = json!({ "sample_id": ear, "review_status": "confirmed" });
+    let (status, body) = send(&app, patch(token, "/api/v1/samples/review", review)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "missing_scope");
+    let (status, body) = send(&app, get(token, "/api/v1/users/tokens")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "session_required");
+
+    let long = json!({ "name": "x".repeat(49), "scopes": ["samples:read"] });
+    let (status, body) = send(&app, post(&session, "/api/v1/users/tokens", long)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["fields"]["name"].is_string());
+
+    let revoke = format!(
+        "/api/v1/users/tokens?token_id={}",
+        created["id"].as_str().unwrap()
+    );
+    assert_eq!(
+        send(&app, delete(&session, &revoke)).await.0,
+        StatusCode::OK
+    );
+
+    let (status, _) = send(&app, get(token, "/api/v1/samples/pendings")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (_, tokens) = send(&app, get(&session, "/api/v1/users/tokens")).await;
+    assert!(tokens["items"].as_array().unwrap().is_empty());
+}
+
+/// Fields of the profile are validated together, and only the given ones
+/// change.
+#[actix_web::test]
+async fn update_profile() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let alice = sign_up(&app, "alice").await;
+
+    let invalid = json!({ "first_name": "x".repeat(49), "last_name": " ", "email": "alice" });
+    let (status, body) = send(&app, patch(&alice, "/api/v1/users/info", invalid)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(
+        body["fields"],
+        json!({
+            "first_name": "Must be at most 48 characters",
+            "last_name": "Required",
+            "email": "Invalid email address",
+        })
+    );
+
+    let rename = json!({ "first_name": "Alice" });
+    let (status, profile) = send(&app, patch(&alice, "/api/v1/users/info", rename)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["first_name"], "Alice");
+    assert_eq!(profile["last_name"], "User");
+    assert_eq!(profile["email"], Value::Null);
+
+    let (status, _) = send(
+        &app,
+        TestRequest::patch()
+            .uri("/api/v1/users/info")
+            .set_json(json!({ "first_name": "Mallory" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// A new address is verified with the latest token mailed to it. Tokens of
+/// an address that was replaced since do not verify the new one.
+#[actix_web::test]
+async fn verify_email() {
+    let mail = mail_dir();
+    let app = Setup {
+        mailer: MailerConfig::File(mail.clone()),
+        ..Setup::default()
+    }
+    .start()
+    .await;
+    let alice = sign_up(&app, "alice").await;
+    let verify = |token: &str| {
+        TestRequest::post()
+            .uri("/api/v1/users/email/verify")
+            .set_json(json!({ "token": token }))
+    };
+
+    let (status, body) = send(&app, post(&alice, "/api/v1/users/email/resend", json!({}))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "no_pending_email");
+
+    let first = json!({ "email": "alice@example.com" });
+    let (status, profile) = send(&app, patch(&alice, "/api/v1/users/info", first)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(profile["email"], "alice@example.com");
+    assert_eq!(profile["email_verified"], false);
+    let stale = take_mail(&mail);
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].0, "alice@example.com");
+
+    let second = json!({ "email": "alice@example.org" });
+    send(&app, patch(&alice, "/api/v1/users/info", second)).await;
+    take_mail(&mail);
+    let (status, _) = send(&app, post(&alice, "/api/v1/users/email/resend", json!({}))).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let resent = take_mail(&mail);
+    assert_eq!(resent.len(), 1);
+    assert_eq!(resent[0].0, "alice@example.org");
+
+    assert_eq!(
+        send(&app, verify(&stale[0].1)).await.0,
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+    assert_eq!(
+        send(&app, verify("not-a-token")).await.0,
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+    assert_eq!(send(&app, verify(&resent[0].1)).await.0, StatusCode::OK);
+    assert_eq!(
+        send(&app, verify(&resent[0].1)).await.0,
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    let (_, profile) = send(&app, get(&alice, "/api/v1/users/info")).await;
+    assert_eq!(profile["email"], "alice@example.org");
+    assert_eq!(profile["email_verified"], true);
+    let (status, _) = send(&app, post(&alice, "/api/v1/users/email/resend", json!({}))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // Saving the same address again keeps it verified and mails nothing.
+    let same = json!({ "email": "alice@example.org" });
+    let (_, profile) = send(&app, patch(&alice, "/api/v1/users/info", same)).await;
+    assert_eq!(profile["email_verified"], true);
+    assert!(take_mail(&mail).is_empty());
+
+    std::fs::remove_dir_all(mail).unwrap();
+}
+
+/// Once confirmed, logins need a code of the authenticator or an unused
+/// recovery code, until disabled with a code.
+#[actix_web::test]
+async fn totp() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let alice = sign_up(&app, "alice").await;
+
+    let (status, enrolled) = send(&app, post(&alice, "/api/v1/users/totp/enroll", json!({}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(enrolled["provisioning_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/PupSight:alice?"));
+    let wrong = json!({ "code": "12345" });
+    let (status, body) = send(&app, post(&alice, "/api/v1/users/totp/confirm", wrong)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["fields"]["code"].is_string());
+
+    let (uri, recovery) = enable_totp(&app, &alice).await;
+    assert_eq!(recovery.len(), 10);
+    let (status, body) = send(&app, post(&alice, "/api/v1/users/totp/enroll", json!({}))).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "totp_enabled");
+
+    let challenge_id = challenge(&app, "alice").await;
+    let (status, _) = send(&app, second_factor(&challenge_id, "12345")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = send(&app, second_factor(&challenge_id, &totp_code(&uri, 0))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &app,
+        get(body["token"].as_str().unwrap(), "/api/v1/users/info"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // The challenge is used up.
+    let (status, _) = send(&app, second_factor(&challenge_id, &recovery[1])).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Recovery codes work once, however they are typed.
+    let lowercase = recovery[0].to_lowercase();
+    let (status, _) = send(
+        &app,
+        second_factor(&challenge(&app, "alice").await, &lowercase),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &app,
+        second_factor(&challenge(&app, "alice").await, &recovery[0]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let disable = |code: String| {
+        TestRequest::delete()
+            .uri("/api/v1/users/totp")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {alice}")))
+            .set_json(json!({ "code": code }))
+    };
+    let (status, _) = send(&app, disable("12345".to_string())).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, _) = send(&app, disable(totp_code(&uri, 1))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(log_in(&app, "alice", PASSWORD).await.0, StatusCode::OK);
+}
+
+/// Wrong codes count against the lockout of the account, shared with wrong
+/// passwords. The right password alone does not clear it, so fresh
+/// challenges bring no fresh guesses.
+#[actix_web::test]
+async fn totp_lockout() {
+    let mut setup = Setup::default();
+    setup.limits.lockout_threshold = 3;
+    let app = setup.start().await;
+    let alice = sign_up(&app, "alice").await;
+    let (uri, _) = enable_totp(&app, &alice).await;
+
+    let mut challenge_id = String::new();
+    for _ in 0..3 {
+        challenge_id = challenge(&app, "alice").await;
+        let (status, _) = send(&app, second_factor(&challenge_id, "12345")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    let (status, _) = send(&app, second_factor(&challenge_id, &totp_code(&uri, 0))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        log_in(&app, "alice", PASSWORD).await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+/// Changing the password ends every other session of the user, and is held
+/// to what registration accepts.
+#[actix_web::test]
+async fn change_password() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+    let current = sign_up(&app, "alice").await;
+    let (_, other) = log_in(&app, "alice", PASSWORD).await;
+    let other = other.unwrap();
+
+    let wrong = json!({ "current_password": "not the password", "new_password": "new" });
+    let (status, body) = send(&app, post(&current, "/api/v1/users/password", wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["fields"]["current_password"].is_string());
+
+    let empty = json!({ "current_password": PASSWORD, "new_password": "" });
+    let (status, body) = send(&app, post(&current, "/api/v1/users/password", empty)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["fields"]["new_password"].is_string());
+
+    let change = json!({ "current_password": PASSWORD, "new_password": "battery staple" });
+    let (status, _) = send(&app, post(&current, "/api/v1/users/password", change)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&app, get(&other, "/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, get(&current, "/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(
+        log_in(&app, "alice", PASSWORD).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        log_in(&app, "alice", "battery staple").await.0,
+        StatusCode::OK
+    );
+}
+
+/// A forgotten password is reset with the token mailed to the verified
+/// address, once, which ends every session of the user.
+#[actix_web::test]
+async fn forgot_and_reset_password() {
+    let mail = mail_dir();
+    let app = Setup {
+        mailer: MailerConfig::File(mail.clone()),
+        ..Setup::default()
+    }
+    .start()
+    .await;
+
+    let register = TestRequest::post()
+        .uri("/api/v1/users/register")
+        .set_json(json!({
+            "login_name": "alice",
+            "first_name": "Test",
+            "last_name": "User",
+            "password": PASSWORD,
+            "email": "alice@example.com",
+        }));
+    assert_eq!(send(&app, register).await.0, StatusCode::OK);
+    let (_, session) = log_in(&app, "alice", PASSWORD).await;
+    let session = session.unwrap();
+
+    // Only verified addresses get a reset mail.
+    let forgot = || {
+        TestRequest::post()
+            .uri("/api/v1/users/password/forgot")
+            .set_json(json!({ "login_name": "alice" }))
+    };
+    let verification = take_mail(&mail);
+    assert_eq!(send(&app, forgot()).await.0, StatusCode::ACCEPTED);
+    assert!(take_mail(&mail).is_empty());
+
+    let verify = TestRequest::post()
+        .uri("/api/v1/users/email/verify")
+        .set_json(json!({ "token": verification[0].1 }));
+    assert_eq!(send(&app, verify).await.0, StatusCode::OK);
+
+    assert_eq!(send(&app, forgot()).await.0, StatusCode::ACCEPTED);
+    let reset = take_mail(&mail);
+    assert_eq!(reset.len(), 1);
+    assert_eq!(reset[0].0, "alice@example.com");
+
+    let unknown = TestRequest::post()
+        .uri("/api/v1/users/password/forgot")
+        .set_json(json!({ "login_name": "nobody" }));
+    assert_eq!(send(&app, unknown).await.0, StatusCode::ACCEPTED);
+    assert!(take_mail(&mail).is_empty());
+
+    let with = |token: &str, new_password: &str| {
+        TestRequest::post()
+            .uri("/api/v1/users/password/reset")
+            .set_json(json!({ "token": token, "new_password": new_password }))
+    };
+    let (status, body) = send(&app, with(&reset[0].1, "")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(body["fields"]["new_password"].is_string());
+    let (status, _) = send(&app, with("not-a-token", "battery staple")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = send(&app, with(&reset[0].1, "battery staple")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, with(&reset[0].1, "another one")).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (status, _) = send(&app, get(&session, "/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        log_in(&app, "alice", "battery staple").await.0,
+        StatusCode::OK
+    );
+
+    std::fs::remove_dir_all(mail).unwrap();
+}
+
+/// Deleting an account takes its samples, results, sessions and tokens with
+/// it, and frees the login name. Other accounts are left alone. Pets go too,
+/// see `pets`.
+#[actix_web::test]
+async fn delete_account() {
+    let app = app(
+        Arc::new(MemoryRepository::new()),
+        ScriptedDetector::new(
+            vec![vec![detection(Classification::Incipient, 0.9)]],
+            "scripted".to_string(),
+        ),
+    )
+    .await;
+    let alice = sign_up(&app, "alice").await;
+    let bob = sign_up(&app, "bob").await;
+
+    let upload = images(&alice, "/api/v1/samples/upload", &["ear"]);
+    assert_eq!(send(&app, upload).await.0, StatusCode::OK);
+    let upload = images(&bob, "/api/v1/samples/upload", &["paw"]);
+    assert_eq!(send(&app, upload).await.0, StatusCode::OK);
+    let (_, list) = send(&app, get(&alice, "/api/v1/samples/pendings")).await;
+    let ear = sample_id(&list, "ear");
+    let infer = json!({ "sample_id": ear });
+    assert_eq!(
+        send(&app, post(&alice, "/api/v1/samples/infer", infer))
+            .await
+            .0,
+        StatusCode::ACCEPTED
+    );
+    let create = json!({ "name": "phone", "scopes": ["samples:read"] });
+    let (_, created) = send(&app, post(&alice, "/api/v1/users/tokens", create)).await;
+    let token = created["token"].as_str().unwrap();
+
+    let remove = |token: &str, password: &str| {
+        TestRequest::delete()
+            .uri("/api/v1/users/account")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+            .set_json(json!({ "password": password }))
+    };
+    let (status, body) = send(&app, remove(&alice, "not the password")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(body["fields"]["password"].is_string());
+    assert_eq!(send(&app, remove(&alice, PASSWORD)).await.0, StatusCode::OK);
+
+    let (status, _) = send(&app, get(&alice, "/api/v1/users/info")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, get(token, "/api/v1/samples/pendings")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        log_in(&app, "alice", PASSWORD).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let (_, list) = send(&app, get(&bob, "/api/v1/samples/pendings")).await;
+    assert_eq!(labels(&list), ["paw"]);
+
+    let again = sign_up(&app, "alice").await;
+    let (_, list) = send(&app, get(&again, "/api/v1/samples/pendings")).await;
+    assert!(labels(&list).is_empty());
+    let (_, list) = send(&app, get(&again, "/api/v1/samples/infers")).await;
+    assert!(labels(&list).is_empty());
+}
+
+/// The process is live right away, and ready once the detector warmed up.
+#[actix_web::test]
+async fn health() {
+    let health = web::Data::new(Health::new());
+    let app = Setup {
+        health: health.clone(),
+        ..Setup::default()
+    }
+    .start()
+    .await;
+
+    let (status, live) = send(&app, TestRequest::get().uri("/health/live")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(live["status"], "live");
+    assert_eq!(live["version"], env!("CARGO_PKG_VERSION"));
+
+    let (status, ready) = send(&app, TestRequest::get().uri("/health/ready")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(ready["status"], "unavailable");
+    assert_eq!(ready["checks"]["database"]["ok"], true);
+    assert_eq!(ready["checks"]["detector"]["ok"], false);
+    assert_eq!(ready["checks"]["detector"]["error"], "Warming up");
+
+    health.warm_up(&idle()).await;
+
+    let (status, ready) = send(&app, TestRequest::get().uri("/health/ready")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ready["status"], "ready");
+    assert_eq!(ready["model_version"], "idle");
+    assert_eq!(ready["checks"]["detector"]["ok"], true);
+}
+
+#[actix_web::test]
+async fn metrics() {
+    let app = app(Arc::new(MemoryRepository::new()), idle()).await;
+
+    let response = call_service(&app, TestRequest::get().uri("/metrics").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+
+    let body = actix_web::body::to_bytes(response.into_body())
+        .await
+        .unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("# TYPE pupsight_inference_session_wait_seconds histogram"));
+}
+
+/// Identity provider numbering its logins, letting in whoever a test granted
+/// a code to. Like a real provider, a code is only redeemed with the PKCE
+/// verifier and nonce of the login it was issued for.
+#[derive(Default)]
+struct StubProvider {
+    logins: AtomicUsize,
+    grants: Mutex<HashMap<String, (usize, Identity)>>,
+}
+
+impl StubProvider {
+    /// A code finishing login number `login` as `identity`.
+    fn grant(&self, login: usize, identity: Identity) -> String {
+        let code = format!("code-{}", uuid::Uuid::new_v4());
+
+        self.grants
+            .lock()
+            .unwrap()
+            .insert(code.clone(), (login, identity));
+
+        code
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for StubProvider {
+    async fn authorize(&self) -> Result<AuthorizationRequest, OidcError> {
+        let login = self.logins.fetch_add(1, Ordering::Relaxed);
+
+        Ok(AuthorizationRequest {
+            url: format!("https://idp.test/authorize?login={login}"),
+            state: format!("state-{login}"),
+            pkce_verifier: format!("verifier-{login}"),
+            nonce: format!("nonce-{login}"),
+        })
+    }
+
+    async fn exchange(
+        &self,
+        code: String,
+        pkce_verifier: String,
+        nonce: String,
+    ) -> Result<Identity, OidcError> {
+        let (login, identity) = self
+            .grants
+            .lock()
+            .unwrap()
+            .remove(&code)
+            .ok_or_else(|| OidcError::new("Token exchange failed", "unknown code"))?;
+
+        if pkce_verifier != format!("verifier-{login}") {
+            return Err(OidcError::new(
+                "Token exchange failed",
+                "PKCE verification failed",
+            ));
+        }
+        if nonce != format!("nonce-{login}") {
+            return Err(OidcError::new("Invalid ID token", "Nonce mismatch"));
+        }
+
+        Ok(identity)
+    }
+
+    fn post_login_redirect(&self) -> &str {
+        "https://app.test/"
+    }
+}
+
+fn identity(subject: &str, username: &str, email: Option<&str>, email_verified: bool) -> Identity {
+    Identity {
+        issuer: "https://idp.test".to_string(),
+        subject: subject.to_string(),
+        preferred_username: Some(username.to_string()),
+        email: email.map(str::to_string),
+        email_verified,
+        given_name: Some("Single".to_string()),
+        family_name: Some("Sign-On".to_string()),
+    }
+}
+
+async fn oidc_app(
+    provider: &Arc<StubProvider>,
+) -> impl Service<Request, Response = ServiceResponse, Error = actix_web::Error> {
+    Setup {
+        oidc: Some(provider.clone()),
+        ..Setup::default()
+    }
+    .start()
+    .await
+}
+
+/// Starts a single sign-on login, returning the state cookie it set.
+async fn start_login<S>(app: &S) -> String
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let request = TestRequest::get().uri("/api/v1/users/oidc/login");
+    let response = call_service(app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+
+    let state = response
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == OIDC_STATE_COOKIE)
+        .unwrap();
+
+    state.value().to_string()
+}
+
+/// The provider redirecting back with `query`, to a browser holding the
+/// state cookie `cookie`.
+fn callback(cookie: Option<&str>, query: &str) -> TestRequest {
+    let request = TestRequest::get().uri(&format!("/api/v1/users/oidc/callback?{query}"));
+
+    match cookie {
+        Some(state) => request.cookie(Cookie::new(OIDC_STATE_COOKIE, state.to_string())),
+        None => request,
+    }
+}
+
+/// Finishes login `state` as `code`, returning the profile of the user it
+/// signed in.
+async fn finish_login<S>(app: &S, state: &str, code: &str) -> Value
+where
+    S: Service<Request, Response = ServiceResponse, Error = actix_web::Error>,
+{
+    let request = callback(Some(state), &format!("state={state}&code={code}"));
+    let response = call_service(app, request.to_request()).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers().get(header::LOCATION).unwrap(),
+        "https://app.test/"
+    );
+
+    let mut info = TestRequest::get().uri("/api/v1/users/info");
+    for cookie in response.response().cookies() {
+        if matches!(cookie.name(), "session" | "access_token") {
+            info = info.cookie(Cookie::new(
+                cookie.name().to_string(),
+                cookie.value().to_string(),
+            ));
+        }
+    }
+    let (status, profile) = send(app, info).await;
+    assert_eq!(status, StatusCode::OK);
+
+    profile
+}
+
+/// The callback only goes on for the browser that started the login, and
+/// only once.
+#[actix_web::test]
+async fn oidc_state_must_match_the_browser() {
+    let provider = Arc::new(StubProvider::default());
+    let app = oidc_app(&provider).await;
+    let state = start_login(&app).await;
+    let code = provider.grant(0, identity("1", "alice", None, false));
+    let query = format!("state={state}&code={code}");
+
+    for request in [
+        callback(None, &query),
+        callback(Some("state-of-another-browser"), &query),
+        callback(Some("forged"), &format!("state=forged&code={code}")),
+    ] {
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid_oidc_state");
+    }
+
+    finish_login(&app, &state, &code).await;
+
+    let code = provider.grant(0, identity("1", "alice", None, false));
+    let (status, _) = send(
+        &app,
+        callback(Some(&state), &format!("state={state}&code={code}")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// A login the user denied at the provider fails, and its state is used up.
+#[actix_web::test]
+async fn oidc_denied_login() {
+    let provider = Arc::new(StubProvider::default());
+    let app = oidc_app(&provider).await;
+    let state = start_login(&app).await;
+
+    let (status, body) = send(
+        &app,
+        callback(Some(&state), &format!("state={state}&error=access_denied")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "oidc_denied");
+
+    let code = provider.grant(0, identity("1", "alice", None, false));
+    let (status, _) = send(
+        &app,
+        callback(Some(&state), &format!("state={state}&code={code}")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+/// A code is redeemed with the PKCE verifier and nonce stored for the state
+/// it came back with, so a code of another login is refused.
+#[actix_web::test]
+async fn oidc_code_must_match_its_login() {
+    let provider = Arc::new(StubProvider::default());
+    let app = oidc_app(&provider).await;
+    let first = start_login(&app).await;
+    let second = start_login(&app).await;
+
+    let code = provider.grant(0, identity("1", "alice", None, false));
+    let (status, body) = send(
+        &app,
+        callback(Some(&second), &format!("state={second}&code={code}")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["code"], "identity_provider_error");
+
+    let code = provider.grant(0, identity("1", "alice", None, false));
+    let profile = finish_login(&app, &first, &code).await;
+    assert_eq!(profile["login_name"], "alice");
+}
+
+/// Identities are linked to accounts by issuer and subject only. A matching
+/// login name or email never links to an existing account.
+#[actix_web::test]
+async fn oidc_links_accounts_by_identity_only() {
+    let provider = Arc::new(StubProvider::default());
+    let app = oidc_app(&provider).await;
+
+    let register = TestRequest::post()
+        .uri("/api/v1/users/register")
+        .set_json(json!({
+            "login_name": "alice",
+            "first_name": "Alice",
+            "last_name": "Password",
+            "password": PASSWORD,
+            "email": "alice@example.com",
+        }));
+    let (status, registered) = send(&app, register).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let state = start_login(&app).await;
+    let code = provider.grant(0, identity("1", "alice", Some("alice@example.com"), true));
+    let first = finish_login(&app, &state, &code).await;
+    assert_ne!(first["id"], registered["id"]);
+    assert!(first["login_name"].as_str().unwrap().starts_with("alice_"));
+    assert_eq!(first["email"], Value::Null);
+
+    // The same subject signs in to the same account, whatever its claims now.
+    let state = start_login(&app).await;
+    let code = provider.grant(1, identity("1", "renamed", Some("new@example.com"), true));
+    let again = finish_login(&app, &state, &code).await;
+    assert_eq!(again["id"], first["id"]);
+    assert_eq!(again["login_name"], first["login_name"]);
+
+    let state = start_login(&app).await;
+    let code = provider.grant(2, identity("2", "carol", Some("carol@example.com"), false));
+    let carol = finish_login(&app, &state, &code).await;
+    assert_eq!(carol["login_name"], "carol");
+    assert_eq!(carol["email"], Value::Null);
+
+    let state = start_login(&app).await;
+    let code = provider.grant(3, identity("3", "dave", Some("dave@example.com"), true));
+    let dave = finish_login(&app, &state, &code).await;
+    assert_eq!(dave["email"], "dave@example.com");
+    assert_eq!(dave["email_verified"], true);
+}
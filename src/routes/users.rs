@@ -1,47 +1,581 @@
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use utoipa::OpenApi;
 
 use crate::{
-    database::Database,
-    messages::users::{LoginUser, RegisterUser},
+    config::CookieConfig,
+    database::{Database, UserSession},
+    error::Error,
+    error::Problem,
+    mailer::Mailer,
+    messages::users::{
+        ApiTokenListResult, BearerLogin, ChangePassword, ConfirmTotpResult, CreateApiToken,
+        CreateApiTokenResult, DeleteAccount, EnrollTotpResult, ForgotPassword, LoginSecondFactor,
+        LoginUser, LoginUserResult, OidcCallback, Profile, RegisterUser, RegisterUserResult,
+        ResetPassword, RevokeApiToken, SecondFactorChallenge, TotpCode, UpdateProfile, VerifyEmail,
+        OIDC_STATE_COOKIE,
+    },
+    oidc::{self, IdentityProvider},
     password_hasher::PasswordHasher,
+    rate_limit::LoginThrottle,
 };
 
+#[utoipa::path(
+    tag = "users",
+    request_body = RegisterUser,
+    responses(
+        (status = 200, description = "Account created", body = RegisterUserResult),
+        (status = 422, description = "Invalid fields", body = Problem),
+        (status = 409, description = "Login name or email taken", body = Problem),
+    )
+)]
 #[post("/register")]
 async fn post_register(
-    (database, hasher, desc): (
+    (database, hasher, mailer, desc): (
         web::Data<Database>,
         web::Data<PasswordHasher<'static>>,
+        web::Data<Mailer>,
         web::Json<RegisterUser>,
     ),
-) -> HttpResponse {
-    database
-        .register(hasher.into_inner(), desc.into_inner())
-        .await
-        .into()
+) -> Result<HttpResponse, Error> {
+    Ok(database
+        .register(hasher.into_inner(), mailer.into_inner(), desc.into_inner())
+        .await?
+        .into())
 }
 
+#[utoipa::path(
+    tag = "users",
+    request_body = LoginUser,
+    responses(
+        (status = 200, description = "Logged in, with session cookies or a bearer token", body = BearerLogin),
+        (status = 202, description = "A second factor is required", body = SecondFactorChallenge),
+        (status = 401, description = "Wrong login name or password", body = Problem),
+        (status = 429, description = "Too many failed attempts", body = Problem),
+    )
+)]
 #[post("/login")]
 async fn post_login(
-    (database, hasher, desc): (
+    (database, hasher, cookies, desc): (
         web::Data<Database>,
         web::Data<PasswordHasher<'static>>,
+        web::Data<CookieConfig>,
         web::Json<LoginUser>,
     ),
-) -> HttpResponse {
+    throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, Error> {
+    let desc = desc.into_inner();
+    let login_name = desc.login_name.clone();
+
+    throttle.check(&login_name)?;
+
+    let result = database.login(hasher.into_inner(), desc).await;
+
+    // A password alone does not clear the lockout while a code is still due.
+    match result {
+        Ok(LoginUserResult::Success { .. }) => throttle.record_success(&login_name),
+        Err(Error::Unauthorized { .. }) => throttle.record_failure(&login_name),
+        Ok(LoginUserResult::SecondFactorRequired { .. }) | Err(_) => {}
+    }
+
+    Ok(result?.respond(&cookies))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = LoginSecondFactor,
+    responses(
+        (status = 200, description = "Logged in, with session cookies or a bearer token", body = BearerLogin),
+        (status = 401, description = "Wrong code or expired challenge", body = Problem),
+        (status = 429, description = "Too many failed attempts", body = Problem),
+    )
+)]
+#[post("/login/totp")]
+async fn post_login_totp(
+    (database, cookies, desc): (
+        web::Data<Database>,
+        web::Data<CookieConfig>,
+        web::Json<LoginSecondFactor>,
+    ),
+    throttle: web::Data<LoginThrottle>,
+) -> Result<HttpResponse, Error> {
+    let desc = desc.into_inner();
+
+    // Wrong codes lock the account like wrong passwords, or every fresh
+    // challenge would bring another round of guesses.
+    let login_name = database
+        .login_challenge_login_name(desc.challenge_id)
+        .await?;
+
+    if let Some(login_name) = &login_name {
+        throttle.check(login_name)?;
+    }
+
+    let result = database.login_second_factor(desc).await;
+
+    if let Some(login_name) = &login_name {
+        match result {
+            Ok(_) => throttle.record_success(login_name),
+            Err(Error::Unauthorized { .. }) => throttle.record_failure(login_name),
+            Err(_) => {}
+        }
+    }
+
+    Ok(result?.respond(&cookies))
+}
+
+#[inline]
+fn oidc_disabled() -> Error {
+    Error::NotFound {
+        code: "oidc_disabled",
+        message: "Single sign-on is not configured".into(),
+    }
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 302, description = "Redirect to the identity provider"),
+        (status = 404, description = "Single sign-on is not configured", body = Problem),
+    )
+)]
+#[get("/oidc/login")]
+async fn get_oidc_login(
+    (database, cookies): (web::Data<Database>, web::Data<CookieConfig>),
+    oidc: Option<web::Data<dyn IdentityProvider>>,
+) -> Result<HttpResponse, Error> {
+    let oidc = oidc.ok_or_else(oidc_disabled)?;
+    let request = oidc.authorize().await?;
+
+    Ok(database.begin_oidc_login(request).await?.respond(&cookies))
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(OidcCallback),
+    responses(
+        (status = 303, description = "Logged in, redirect to the app"),
+        (status = 400, description = "Login state unknown, expired or not from this browser", body = Problem),
+        (status = 401, description = "Login was denied at the identity provider", body = Problem),
+        (status = 502, description = "Identity provider request failed", body = Problem),
+    )
+)]
+#[get("/oidc/callback")]
+async fn get_oidc_callback(
+    (database, hasher, cookies, query): (
+        web::Data<Database>,
+        web::Data<PasswordHasher<'static>>,
+        web::Data<CookieConfig>,
+        web::Query<OidcCallback>,
+    ),
+    (oidc, request): (Option<web::Data<dyn IdentityProvider>>, HttpRequest),
+) -> Result<HttpResponse, Error> {
+    let oidc = oidc.ok_or_else(oidc_disabled)?;
+    let query = query.into_inner();
+
+    // The state must come back to the browser that started the login.
+    if request
+        .cookie(OIDC_STATE_COOKIE)
+        .is_none_or(|cookie| cookie.value() != query.state)
+    {
+        return Err(oidc::invalid_state());
+    }
+
+    let (pkce_verifier, nonce) = database.take_oidc_login(query.state.clone()).await?;
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, error) => {
+            return Err(Error::Unauthorized {
+                code: "oidc_denied",
+                message: error
+                    .unwrap_or_else(|| "Missing authorization code".to_string())
+                    .into(),
+            })
+        }
+    };
+
+    let identity = oidc.exchange(code, pkce_verifier, nonce).await?;
+
+    let mut response = database
+        .oidc_sign_in(hasher.into_inner(), identity)
+        .await?
+        .redirect(&cookies, oidc.post_login_redirect());
+
+    response
+        .add_removal_cookie(&cookies.build(OIDC_STATE_COOKIE, String::new(), true))
+        .map_err(Error::internal)?;
+
+    Ok(response)
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = "Secret to confirm with a first code", body = EnrollTotpResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 409, description = "Already enabled", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[post("/totp/enroll")]
+async fn post_totp_enroll(
+    (database, user): (web::Data<Database>, UserSession),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    Ok(database
+        .enroll_totp(user.user_id, user.login_name)
+        .await?
+        .into())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "Second factor enabled", body = ConfirmTotpResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 409, description = "Not enrolled, or already enabled", body = Problem),
+        (status = 422, description = "Wrong code", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[post("/totp/confirm")]
+async fn post_totp_confirm(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Json<TotpCode>),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    Ok(database
+        .confirm_totp(user.user_id, desc.into_inner())
+        .await?
+        .into())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "Second factor disabled"),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 422, description = "Wrong code", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[delete("/totp")]
+async fn delete_totp(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Json<TotpCode>),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
     database
-        .login(hasher.into_inner(), desc.into_inner())
-        .await
-        .into()
+        .disable_totp(user.user_id, desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, body = Profile),
+        (status = 401, body = Problem),
+    ),
+    security(("session" = []), ("bearer" = []))
+)]
 #[get("/info")]
-async fn get_info(info: crate::database::UserSession) -> HttpResponse {
-    HttpResponse::Ok().json(info)
+async fn get_info(
+    (database, user): (web::Data<Database>, UserSession),
+) -> Result<HttpResponse, Error> {
+    Ok(database.get_profile(user.user_id).await?.into())
 }
 
+#[utoipa::path(
+    tag = "users",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, body = Profile),
+        (status = 422, description = "Invalid fields", body = Problem),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[patch("/info")]
+async fn patch_info(
+    (database, mailer, user, desc): (
+        web::Data<Database>,
+        web::Data<Mailer>,
+        UserSession,
+        web::Json<UpdateProfile>,
+    ),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    Ok(database
+        .update_profile(mailer.into_inner(), user.user_id, desc.into_inner())
+        .await?
+        .into())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = VerifyEmail,
+    responses(
+        (status = 200, description = "Email verified"),
+        (status = 422, description = "Invalid or expired token", body = Problem),
+    )
+)]
+#[post("/email/verify")]
+async fn post_email_verify(
+    (database, desc): (web::Data<Database>, web::Json<VerifyEmail>),
+) -> Result<HttpResponse, Error> {
+    database.verify_email(desc.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 202, description = "Verification mail sent"),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 409, description = "No unverified email address", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[post("/email/resend")]
+async fn post_email_resend(
+    (database, mailer, user): (web::Data<Database>, web::Data<Mailer>, UserSession),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    database
+        .resend_email_verification(mailer.into_inner(), user.user_id)
+        .await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = CreateApiToken,
+    responses(
+        (status = 200, description = "The token, only shown once", body = CreateApiTokenResult),
+        (status = 422, description = "Invalid fields", body = Problem),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[post("/tokens")]
+async fn post_token(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Json<CreateApiToken>),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    Ok(database
+        .create_api_token(user.user_id, desc.into_inner())
+        .await?
+        .into())
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, body = ApiTokenListResult),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[get("/tokens")]
+async fn get_tokens(
+    (database, user): (web::Data<Database>, UserSession),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    Ok(database.list_api_tokens(user.user_id).await?.into())
+}
+
+#[utoipa::path(
+    tag = "users",
+    params(RevokeApiToken),
+    responses(
+        (status = 200, description = "Token revoked"),
+        (status = 401, body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 404, body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[delete("/tokens")]
+async fn delete_token(
+    (database, user, desc): (web::Data<Database>, UserSession, web::Query<RevokeApiToken>),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    database
+        .revoke_api_token(user.user_id, desc.token_id)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = ChangePassword,
+    responses(
+        (status = 200, description = "Password changed, other sessions ended"),
+        (status = 401, description = "Not logged in or wrong current password", body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+        (status = 422, description = "Invalid fields", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[post("/password")]
+async fn post_password(
+    (database, hasher, user, desc): (
+        web::Data<Database>,
+        web::Data<PasswordHasher<'static>>,
+        UserSession,
+        web::Json<ChangePassword>,
+    ),
+) -> Result<HttpResponse, Error> {
+    let session_id = user.require_session()?;
+
+    database
+        .change_password(
+            hasher.into_inner(),
+            user.user_id,
+            session_id,
+            desc.into_inner(),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = ForgotPassword,
+    responses(
+        (status = 202, description = "A reset mail is sent if the account has a verified email"),
+    )
+)]
+#[post("/password/forgot")]
+async fn post_password_forgot(
+    (database, mailer, desc): (
+        web::Data<Database>,
+        web::Data<Mailer>,
+        web::Json<ForgotPassword>,
+    ),
+) -> Result<HttpResponse, Error> {
+    database
+        .request_password_reset(mailer.into_inner(), desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = ResetPassword,
+    responses(
+        (status = 200, description = "Password replaced"),
+        (status = 422, description = "Invalid or expired token, or invalid fields", body = Problem),
+    )
+)]
+#[post("/password/reset")]
+async fn post_password_reset(
+    (database, hasher, desc): (
+        web::Data<Database>,
+        web::Data<PasswordHasher<'static>>,
+        web::Json<ResetPassword>,
+    ),
+) -> Result<HttpResponse, Error> {
+    database
+        .reset_password(hasher.into_inner(), desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = DeleteAccount,
+    responses(
+        (status = 200, description = "Account and its samples deleted"),
+        (status = 401, description = "Not logged in or wrong password", body = Problem),
+        (status = 403, description = "Only for login sessions, not API tokens", body = Problem),
+    ),
+    security(("session" = []))
+)]
+#[delete("/account")]
+async fn delete_account(
+    (database, hasher, user, desc): (
+        web::Data<Database>,
+        web::Data<PasswordHasher<'static>>,
+        UserSession,
+        web::Json<DeleteAccount>,
+    ),
+) -> Result<HttpResponse, Error> {
+    user.require_session()?;
+
+    database
+        .delete_account(hasher.into_inner(), user.user_id, desc.into_inner())
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    post_register,
+    post_login,
+    post_login_totp,
+    get_oidc_login,
+    get_oidc_callback,
+    get_info,
+    patch_info,
+    post_email_verify,
+    post_email_resend,
+    post_totp_enroll,
+    post_totp_confirm,
+    delete_totp,
+    post_token,
+    get_tokens,
+    delete_token,
+    post_password,
+    post_password_forgot,
+    post_password_reset,
+    delete_account,
+))]
+pub(crate) struct UsersApi;
+
 pub(crate) fn scope() -> actix_web::Scope {
     web::scope("/users")
         .service(post_register)
         .service(post_login)
+        .service(post_login_totp)
+        .service(get_oidc_login)
+        .service(get_oidc_callback)
         .service(get_info)
+        .service(patch_info)
+        .service(post_email_verify)
+        .service(post_email_resend)
+        .service(post_totp_enroll)
+        .service(post_totp_confirm)
+        .service(delete_totp)
+        .service(post_token)
+        .service(get_tokens)
+        .service(delete_token)
+        .service(post_password)
+        .service(post_password_forgot)
+        .service(post_password_reset)
+        .service(delete_account)
 }
@@ -1,5 +1,63 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_tokens (id) {
+        id -> Uuid,
+        #[max_length = 48]
+        name -> Varchar,
+        token_hash -> Bytea,
+        scopes -> Array<Nullable<Text>>,
+        user_id -> Uuid,
+        revoked -> Bool,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_verifications (id) {
+        id -> Uuid,
+        token_hash -> Bytea,
+        #[max_length = 254]
+        email -> Varchar,
+        user_id -> Uuid,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_challenges (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        attempts -> Int4,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_logins (state) {
+        state -> Text,
+        pkce_verifier -> Text,
+        nonce -> Text,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_resets (id) {
+        id -> Uuid,
+        token_hash -> Bytea,
+        user_id -> Uuid,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     pets (id) {
         id -> Uuid,
@@ -13,6 +71,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    recovery_codes (id) {
+        id -> Uuid,
+        code_hash -> Bytea,
+        user_id -> Uuid,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     results (id) {
         id -> Uuid,
@@ -30,6 +98,8 @@ diesel::table! {
         sample_id -> Uuid,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        #[max_length = 64]
+        model_version -> Nullable<Varchar>,
     }
 }
 
@@ -44,6 +114,9 @@ diesel::table! {
         deleted -> Nullable<Bool>,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        #[max_length = 16]
+        review_status -> Varchar,
+        notes -> Nullable<Text>,
     }
 }
 
@@ -56,6 +129,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    user_identities (id) {
+        id -> Uuid,
+        issuer -> Text,
+        subject -> Text,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -68,19 +151,39 @@ diesel::table! {
         argon2 -> Bytea,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
+        #[max_length = 254]
+        email -> Nullable<Varchar>,
+        email_verified_at -> Nullable<Timestamp>,
+        totp_secret -> Nullable<Bytea>,
+        totp_enabled -> Bool,
+        totp_last_step -> Nullable<Int8>,
+        is_admin -> Bool,
     }
 }
 
+diesel::joinable!(api_tokens -> users (user_id));
+diesel::joinable!(email_verifications -> users (user_id));
+diesel::joinable!(login_challenges -> users (user_id));
+diesel::joinable!(password_resets -> users (user_id));
 diesel::joinable!(pets -> users (owner_id));
+diesel::joinable!(recovery_codes -> users (user_id));
 diesel::joinable!(results -> samples (sample_id));
 diesel::joinable!(samples -> pets (pet_id));
 diesel::joinable!(samples -> users (owner_id));
 diesel::joinable!(session -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    email_verifications,
+    login_challenges,
+    oidc_logins,
+    password_resets,
     pets,
+    recovery_codes,
     results,
     samples,
     session,
+    user_identities,
     users,
 );
@@ -0,0 +1,128 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use actix_web::dev::ServerHandle;
+use tokio::sync::Notify;
+
+use crate::error::Error;
+
+#[derive(Clone)]
+pub struct ShutdownConfig {
+    /// Finish in-flight requests and tracked work before exiting, rather
+    /// than dropping them on the spot.
+    pub drain: bool,
+    /// Longest a drain may take before whatever is left is abandoned.
+    pub timeout: Duration,
+}
+
+/// Tracks work that must not be cut off halfway, such as an inference
+/// followed by writing its results, so shutdown can wait for it.
+#[derive(Clone, Default)]
+pub(crate) struct Drain(Arc<DrainState>);
+
+#[derive(Default)]
+struct DrainState {
+    active: AtomicUsize,
+    idle: Notify,
+    /// When shutdown began, which the drain timeout counts from.
+    stopping: OnceLock<Instant>,
+}
+
+struct Guard(Arc<DrainState>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Drain {
+    /// Runs `task` on its own task, so it also completes when the request
+    /// that started it is dropped by a disconnecting client or a stopping
+    /// worker.
+    pub(crate) async fn run<T, F>(&self, task: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.0.active.fetch_add(1, Ordering::AcqRel);
+        let guard = Guard(self.0.clone());
+
+        tokio::spawn(async move {
+            let _guard = guard;
+            task.await
+        })
+        .await
+        .map_err(Error::internal)?
+    }
+
+    /// Waits for tracked work to finish, returning how much is still running
+    /// once `timeout` after the start of shutdown is up.
+    pub(crate) async fn wait(&self, timeout: Duration) -> usize {
+        let timeout = match self.0.stopping.get() {
+            Some(stopping) => timeout.saturating_sub(stopping.elapsed()),
+            None => timeout,
+        };
+        let idle = async {
+            loop {
+                // Registered before the check so a wakeup in between is not lost.
+                let notified = self.0.idle.notified();
+
+                if self.0.active.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+
+                notified.await;
+            }
+        };
+
+        let _ = tokio::time::timeout(timeout, idle).await;
+
+        self.0.active.load(Ordering::Acquire)
+    }
+}
+
+/// Resolves on SIGINT or, on Unix, SIGTERM.
+async fn signal() {
+    let ctrl_c = Box::pin(tokio::signal::ctrl_c());
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                futures::future::select(ctrl_c, Box::pin(terminate.recv())).await;
+            }
+            Err(e) => {
+                tracing::warn!("Could not listen for SIGTERM: {e}");
+                let _ = ctrl_c.await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+}
+
+/// Stops the servers on the first signal. They stop accepting connections
+/// and, when draining, give running requests until their shutdown timeout.
+pub(crate) async fn on_signal(config: ShutdownConfig, servers: Vec<ServerHandle>, drain: Drain) {
+    signal().await;
+    let _ = drain.0.stopping.set(Instant::now());
+
+    if config.drain {
+        tracing::info!(
+            "Shutting down, draining for up to {}s",
+            config.timeout.as_secs()
+        );
+    } else {
+        tracing::info!("Shutting down");
+    }
+
+    futures::future::join_all(servers.iter().map(|server| server.stop(config.drain))).await;
+}
@@ -0,0 +1,124 @@
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: PathBuf,
+    /// How often the files are checked for changes, `None` to never reload.
+    pub reload_interval: Option<Duration>,
+    /// Plain HTTP port that redirects everything to HTTPS.
+    pub redirect_port: Option<u16>,
+}
+
+/// Hands out the current certificate, which `watch` swaps when the files
+/// change so renewals apply without a restart.
+#[derive(Debug)]
+struct CertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+pub(crate) fn server_config(config: &TlsConfig) -> std::io::Result<rustls::ServerConfig> {
+    let resolver = Arc::new(CertResolver(RwLock::new(Arc::new(load(config)?))));
+
+    if let Some(interval) = config.reload_interval {
+        tokio::spawn(watch(config.clone(), interval, resolver.clone()));
+    }
+
+    Ok(rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+fn load(config: &TlsConfig) -> std::io::Result<CertifiedKey> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(std::fs::File::open(&config.cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        return Err(std::io::Error::other(format!(
+            "No certificate found in {}",
+            config.cert.display()
+        )));
+    }
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(std::fs::File::open(&config.key)?))?
+        .ok_or_else(|| {
+            std::io::Error::other(format!("No private key found in {}", config.key.display()))
+        })?;
+
+    let key =
+        rustls::crypto::ring::sign::any_supported_type(&key).map_err(std::io::Error::other)?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+/// Polls the modification times rather than relying on file events, which
+/// miss the symlink swaps certbot and Kubernetes secrets use.
+async fn watch(config: TlsConfig, interval: Duration, resolver: Arc<CertResolver>) {
+    let mut seen = (modified(&config.cert), modified(&config.key));
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let current = (modified(&config.cert), modified(&config.key));
+
+        if current == seen {
+            continue;
+        }
+
+        // A half written pair fails to load and is retried on the next tick.
+        match load(&config) {
+            Ok(key) => {
+                *resolver.0.write().unwrap() = Arc::new(key);
+                seen = current;
+                tracing::info!("Reloaded TLS certificate from {}", config.cert.display());
+            }
+            Err(e) => tracing::warn!("Could not reload TLS certificate: {e}"),
+        }
+    }
+}
+
+/// Default service of the plain HTTP listener in redirect mode.
+pub(crate) async fn redirect(request: HttpRequest, https_port: web::Data<u16>) -> HttpResponse {
+    let info = request.connection_info();
+    let host = info.host();
+    // Strip the port, minding bracketed IPv6 hosts.
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+
+    let authority = match **https_port {
+        443 => host.to_string(),
+        port => format!("{host}:{port}"),
+    };
+
+    let path = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("https://{authority}{path}")))
+        .finish()
+}
@@ -0,0 +1,78 @@
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, TOTP};
+
+const ISSUER: &str = "PupSight";
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+const SKEW: i64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[inline]
+fn build(secret: Vec<u8>, account: &str) -> TOTP {
+    TOTP::new_unchecked(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        account.replace(':', ""),
+    )
+}
+
+#[inline]
+pub(crate) fn generate_secret() -> Vec<u8> {
+    rand::random::<[u8; 20]>().to_vec()
+}
+
+/// Base32 secret and `otpauth://` URI for authenticator apps; the URI is what
+/// gets rendered as a QR code by the client.
+pub(crate) fn provisioning(secret: &[u8], account: &str) -> (String, String) {
+    let totp = build(secret.to_vec(), account);
+
+    (totp.get_secret_base32(), totp.get_url())
+}
+
+/// Returns the time step `code` belongs to if it is valid within one step of
+/// clock skew and newer than `last_step`, so a code cannot be replayed.
+pub(crate) fn verify(secret: &[u8], code: &str, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+
+    if code.len() != DIGITS {
+        return None;
+    }
+
+    let totp = build(secret.to_vec(), "");
+    let now = chrono::Utc::now().timestamp() / STEP as i64;
+
+    (now - SKEW..=now + SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| totp.generate((*step as u64) * STEP) == code)
+}
+
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rand::random::<[u8; 10]>()
+                .iter()
+                .map(|byte| {
+                    RECOVERY_CODE_ALPHABET[*byte as usize % RECOVERY_CODE_ALPHABET.len()] as char
+                })
+                .collect();
+
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub(crate) fn hash_recovery_code(code: &str) -> Vec<u8> {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    Sha256::digest(normalized).to_vec()
+}
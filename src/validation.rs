@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+pub(crate) const LOGIN_NAME_MAX: usize = 24;
+pub(crate) const PERSON_NAME_MAX: usize = 48;
+pub(crate) const EMAIL_MAX: usize = 254;
+pub(crate) const API_TOKEN_NAME_MAX: usize = 48;
+pub(crate) const SAMPLE_LABEL_MAX: usize = 32;
+pub(crate) const PET_NAME_MAX: usize = 32;
+pub(crate) const NOTES_MAX: usize = 2000;
+pub(crate) const SEARCH_QUERY_MAX: usize = 200;
+
+/// Per-field validation messages, rendered as `{ "<field>": "<message>" }`
+/// under `fields` of the error body.
+#[derive(Clone, Debug, Default, Serialize, utoipa::ToSchema)]
+pub(crate) struct FieldErrors(BTreeMap<&'static str, String>);
+
+impl FieldErrors {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.0.entry(field).or_insert_with(|| message.into());
+    }
+
+    pub(crate) fn check_length(&mut self, field: &'static str, value: &str, max: usize) {
+        let length = value.chars().count();
+
+        if value.trim().is_empty() {
+            self.add(field, "Required");
+        } else if length > max {
+            self.add(field, format!("Must be at most {max} characters"));
+        }
+    }
+
+    pub(crate) fn check_email(&mut self, field: &'static str, value: &str) {
+        self.check_length(field, value, EMAIL_MAX);
+
+        let valid = value.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+        }) && !value.chars().any(char::is_whitespace);
+
+        if !valid {
+            self.add(field, "Invalid email address");
+        }
+    }
+
+    pub(crate) fn into_result(self) -> Result<(), FieldErrors> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}